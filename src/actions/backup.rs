@@ -0,0 +1,74 @@
+use crate::config::RemoteShell;
+use chrono::Utc;
+use std::path::Path;
+
+use super::shell::quote;
+
+// in the home folder of the server user, a folder per config holding a folder per deployment named
+// after its UTC time, itself holding a folder per upload with backup = true
+pub const BACKUPS_FOLDER: &str = "~/.deploy-backups";
+// in the folder of an upload, the copy of its target and the server path it was copied from
+const FILES: &str = "files";
+const TARGET_FILE: &str = "target";
+
+pub fn new_backup() -> String {
+    Utc::now().format("%Y%m%d%H%M%S").to_string()
+}
+
+// the folder of the config and the config file name, like `api_deploy` for api/deploy.toml, with
+// the characters a folder name shouldn't hold replaced
+pub fn config_key(config_path: &str) -> String {
+    let path = Path::new(config_path);
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let key = match path.parent().and_then(Path::file_name) {
+        Some(folder) => format!("{}_{}", folder.to_string_lossy(), stem),
+        None => stem.into_owned(),
+    };
+    key.chars()
+        .map(|c| match c {
+            'A'..='Z' | 'a'..='z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+// the backups of a config, still to be expanded
+pub fn backups_folder(config_key: &str) -> String {
+    format!("{}/{}", BACKUPS_FOLDER, config_key)
+}
+
+// the folder of an upload in a backup, still to be expanded
+pub fn backup_path(config_key: &str, backup: &str, action: &str) -> String {
+    format!("{}/{}/{}", backups_folder(config_key), backup, action)
+}
+
+// copies target into backup_path, run by sh whatever the login shell; a target that doesn't exist
+// yet has nothing to back up
+pub fn backup_command(shell: RemoteShell, backup_path: &str, target: &str) -> String {
+    let script = format!(
+        "[ -e \"$1\" ] || exit 0; mkdir -p \"$2\" && cp -a \"$1\" \"$2/{}\" && \
+         printf '%s\\n' \"$1\" > \"$2/{}\"",
+        FILES, TARGET_FILE
+    );
+    format!(
+        "sh -c {} sh {} {}",
+        quote(shell, &script),
+        quote(shell, target),
+        quote(shell, backup_path)
+    )
+}
+
+// replaces the target backed up in backup_path with its copy
+pub fn restore_command(shell: RemoteShell, backup_path: &str) -> String {
+    let script = format!(
+        "target=$(cat \"$1/{}\") && [ -n \"$target\" ] && rm -rf \"$target\" && \
+         cp -a \"$1/{}\" \"$target\"",
+        TARGET_FILE, FILES
+    );
+    format!(
+        "sh -c {} sh {}",
+        quote(shell, &script),
+        quote(shell, backup_path)
+    )
+}
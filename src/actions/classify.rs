@@ -0,0 +1,274 @@
+use crate::console;
+use crate::theme::THEME;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::{Match, WalkBuilder};
+use regex::Regex;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+// why a file of an upload's source folder is or isn't uploaded
+pub enum Reason {
+    Upload,
+    Ignored(String),
+    GitIgnored,
+    Skipped(String),
+    Unreadable,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reason::Upload => write!(f, "uploaded"),
+            Reason::Ignored(pattern) => write!(f, "ignored by .deployignore pattern '{}'", pattern),
+            Reason::GitIgnored => write!(f, "ignored by .gitignore"),
+            Reason::Skipped(reason) => write!(f, "skipped, {}", reason),
+            Reason::Unreadable => write!(f, "unreadable"),
+        }
+    }
+}
+
+// skips files by content, reading as little of them as possible
+pub struct ContentFilter {
+    skip_empty_files: bool,
+    skip_first_line: Option<Regex>,
+}
+
+impl ContentFilter {
+    pub fn new(skip_empty_files: bool, skip_first_line: Option<&str>) -> Result<Self, String> {
+        let skip_first_line = skip_first_line
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| format!("invalid skip_first_line pattern: {}", e))?;
+        Ok(ContentFilter {
+            skip_empty_files,
+            skip_first_line,
+        })
+    }
+
+    fn skip_reason(&self, path: &Path) -> Option<String> {
+        if self.skip_empty_files {
+            if let Ok(metadata) = fs::metadata(path) {
+                if metadata.len() == 0 {
+                    return Some("empty file".to_string());
+                }
+            }
+        }
+        if let Some(pattern) = &self.skip_first_line {
+            let mut first_line = String::new();
+            let file = File::open(path).ok()?;
+            BufReader::new(file).read_line(&mut first_line).ok()?;
+            if pattern.is_match(first_line.trim_end_matches(['\r', '\n'])) {
+                return Some(format!("first line matches '{}'", pattern));
+            }
+        }
+        None
+    }
+}
+
+// decides for every file of source_folder whether it's uploaded, ignored
+// directories are only walked into when `all` asks for each of their files
+pub fn classify_files(
+    source_folder: &str,
+    filter: &ContentFilter,
+    use_gitignore: bool,
+    all: bool,
+) -> Vec<(PathBuf, Reason)> {
+    let matcher = deployignore(source_folder);
+    // listing the files left out by .gitignore takes a second walk that skips them
+    let git_kept: Option<HashSet<PathBuf>> = if use_gitignore && all {
+        let kept = walker(source_folder, true).build().flatten();
+        Some(kept.map(|entry| entry.into_path()).collect())
+    } else {
+        None
+    };
+    let mut builder = walker(source_folder, use_gitignore && !all);
+    // a `!pattern` can bring back files of an ignored directory, which then has to be walked;
+    // ignored files are still listed with their pattern
+    if !all && matcher.num_whitelists() == 0 {
+        let matcher = matcher.clone();
+        builder.filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            entry.depth() == 0 || !is_dir || !matcher.matched(entry.path(), true).is_ignore()
+        });
+    }
+
+    let mut files = Vec::new();
+    for result in builder.build() {
+        match result {
+            Ok(entry) => {
+                let path = entry.into_path();
+                if !path.is_file() {
+                    continue;
+                }
+                let reason = match matcher.matched_path_or_any_parents(&path, false) {
+                    Match::Ignore(glob) => Reason::Ignored(glob.original().to_string()),
+                    _ if git_kept.as_ref().is_some_and(|kept| !kept.contains(&path)) => {
+                        Reason::GitIgnored
+                    }
+                    _ => match filter.skip_reason(&path) {
+                        Some(reason) => Reason::Skipped(reason),
+                        None if File::open(&path).is_err() => Reason::Unreadable,
+                        None => Reason::Upload,
+                    },
+                };
+                files.push((path, reason));
+            }
+            Err(err) => console!("{} {}", THEME.error_label("Error:"), err),
+        }
+    }
+    files
+}
+
+// .gitignore files, nested ones and those of the parent folders, are only read when asked
+fn walker(source_folder: &str, git_ignore: bool) -> WalkBuilder {
+    let mut builder = WalkBuilder::new(source_folder);
+    builder
+        .standard_filters(false)
+        .git_ignore(git_ignore)
+        .parents(git_ignore)
+        .require_git(false);
+    builder
+}
+
+// .deployignore patterns that match none of the files of source_folder
+pub fn unused_ignore_patterns(source_folder: &str) -> Vec<String> {
+    let ignore_path = Path::new(source_folder).join(".deployignore");
+    let Ok(contents) = fs::read_to_string(&ignore_path) else {
+        return Vec::new();
+    };
+    let matcher = deployignore(source_folder);
+    let mut builder = WalkBuilder::new(source_folder);
+    builder.standard_filters(false);
+
+    let mut used = HashSet::new();
+    for entry in builder.build().flatten() {
+        if !entry.path().is_file() {
+            continue;
+        }
+        match matcher.matched_path_or_any_parents(entry.path(), false) {
+            Match::Ignore(glob) | Match::Whitelist(glob) => {
+                used.insert(glob.original().to_string());
+            }
+            Match::None => {}
+        }
+    }
+
+    contents
+        .lines()
+        .map(|line| line.trim_end())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter(|line| !used.contains(*line))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+pub fn deployignore(source_folder: &str) -> Gitignore {
+    let ignore_path = Path::new(source_folder).join(".deployignore");
+    let mut builder = GitignoreBuilder::new(source_folder);
+    if ignore_path.exists() {
+        if let Some(err) = builder.add(ignore_path) {
+            console!("{} {}", THEME.error_label("Error:"), err);
+        }
+    }
+    builder.build().unwrap_or_else(|err| {
+        console!("{} {}", THEME.error_label("Error:"), err);
+        Gitignore::empty()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("deploy-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    #[test]
+    fn empty_files_are_skipped_when_asked() {
+        let folder = temp_folder("empty");
+        let empty = folder.join("empty.txt");
+        let generated = folder.join("generated.js");
+        fs::write(&empty, "").unwrap();
+        fs::write(&generated, "// @generated\nlet a = 1;\n").unwrap();
+
+        let keep_all = ContentFilter::new(false, None).unwrap();
+        let skip_empty = ContentFilter::new(true, None).unwrap();
+        let skip_generated = ContentFilter::new(false, Some("^// @generated")).unwrap();
+        let reasons = [
+            keep_all.skip_reason(&empty),
+            skip_empty.skip_reason(&empty),
+            skip_empty.skip_reason(&generated),
+            skip_generated.skip_reason(&generated),
+        ];
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(
+            reasons,
+            [
+                None,
+                Some("empty file".to_string()),
+                None,
+                Some("first line matches '^// @generated'".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn classify_gives_the_reason_of_each_file() {
+        let folder = temp_folder("explain");
+        fs::create_dir_all(folder.join("logs")).unwrap();
+        fs::write(folder.join(".deployignore"), "logs/\n*.tmp\n").unwrap();
+        fs::write(folder.join(".gitignore"), "secrets.env\n").unwrap();
+        fs::write(folder.join("index.html"), "hello").unwrap();
+        fs::write(folder.join("empty.txt"), "").unwrap();
+        fs::write(folder.join("cache.tmp"), "cache").unwrap();
+        fs::write(folder.join("logs/app.log"), "log").unwrap();
+        fs::write(folder.join("secrets.env"), "KEY=value").unwrap();
+
+        let source_folder = folder.to_str().unwrap();
+        let filter = ContentFilter::new(true, None).unwrap();
+        let mut reasons: Vec<(String, String)> = classify_files(source_folder, &filter, true, true)
+            .into_iter()
+            .map(|(path, reason)| {
+                let relative_path = path.strip_prefix(&folder).unwrap();
+                (relative_path.display().to_string(), reason.to_string())
+            })
+            .collect();
+        reasons.sort();
+        fs::remove_dir_all(&folder).unwrap();
+
+        let expected = [
+            (".deployignore", "uploaded"),
+            (".gitignore", "uploaded"),
+            ("cache.tmp", "ignored by .deployignore pattern '*.tmp'"),
+            ("empty.txt", "skipped, empty file"),
+            ("index.html", "uploaded"),
+            ("logs/app.log", "ignored by .deployignore pattern 'logs/'"),
+            ("secrets.env", "ignored by .gitignore"),
+        ];
+        let expected: Vec<(String, String)> = expected
+            .iter()
+            .map(|(path, reason)| (path.to_string(), reason.to_string()))
+            .collect();
+        assert_eq!(reasons, expected);
+    }
+
+    #[test]
+    fn patterns_matching_no_file_are_unused() {
+        let folder = temp_folder("unused-ignores");
+        let patterns = "# build output\n*.log\ndist/\n";
+        fs::write(folder.join(".deployignore"), patterns).unwrap();
+        fs::write(folder.join("index.html"), "hello").unwrap();
+        fs::write(folder.join("debug.log"), "log").unwrap();
+
+        let unused = unused_ignore_patterns(folder.to_str().unwrap());
+        fs::remove_dir_all(&folder).unwrap();
+        assert_eq!(unused, ["dist/"]);
+    }
+}
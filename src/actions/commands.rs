@@ -1,18 +1,26 @@
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::config::Config;
-use crate::log;
+use crate::config::Server;
+use crate::ssh_config::jump_servers;
+use crate::theme::THEME;
 use crate::Logger;
+use crate::{log, log_error};
 use async_trait::async_trait;
 use colored::Colorize;
 use russh::client;
-use russh::Channel;
+use russh::{Channel, ChannelMsg};
 use russh_keys::key::PublicKey;
 use russh_keys::load_secret_key;
 
 use super::upload::expand_user_path;
 
-pub struct SimpleHandler;
+#[derive(Default)]
+pub struct SimpleHandler {
+    // the session to the jump host a session is tunneled through, kept open as long as the
+    // session tunneled through it
+    _jump: Option<client::Handle<SimpleHandler>>,
+}
 
 #[async_trait]
 impl client::Handler for SimpleHandler {
@@ -29,8 +37,20 @@ impl client::Handler for SimpleHandler {
 pub async fn send_command(
     logger: &mut Logger,
     session: &client::Handle<SimpleHandler>,
-    commands: &Vec<String>,
-) {
+    name: &str,
+    commands: &[String],
+) -> Option<u32> {
+    send_command_with_timeout(logger, session, name, commands, None).await
+}
+
+// the commands are killed once they run longer than timeout
+pub async fn send_command_with_timeout(
+    logger: &mut Logger,
+    session: &client::Handle<SimpleHandler>,
+    name: &str,
+    commands: &[String],
+    timeout: Option<Duration>,
+) -> Option<u32> {
     let forged_command = commands.join(" && ");
     log!(
         logger,
@@ -40,26 +60,99 @@ pub async fn send_command(
         "\'".bright_black()
     );
 
-    let channel: Channel<client::Msg> = session.channel_open_session().await.unwrap();
-    channel
-        .exec(true, forged_command)
-        .await
-        .expect("Unable to send command");
+    // without a channel there is no exit status either, which fails the step
+    let channel: Channel<client::Msg> = match session.channel_open_session().await {
+        Ok(channel) => channel,
+        Err(e) => {
+            log_error!(
+                logger,
+                "{} '{}': {}",
+                THEME.error_label("Unable to run:"),
+                name,
+                e
+            );
+            return None;
+        }
+    };
+    if let Err(e) = channel.exec(true, forged_command).await {
+        log_error!(
+            logger,
+            "{} '{}': {}",
+            THEME.error_label("Unable to run:"),
+            name,
+            e
+        );
+        return None;
+    }
 
-    logger.start_remote_logging(channel).await;
+    logger.start_remote_logging(channel, name, timeout).await
 }
 
-pub async fn create_ssh_session(conf: &Config) -> client::Handle<SimpleHandler> {
-    let key = load_secret_key(expand_user_path(&conf.server.ssh_key), None).unwrap();
-    let config: Arc<_> = Arc::new(client::Config::default());
-    let sh = SimpleHandler;
+// runs a command without displaying it, returns its exit status and output
+pub async fn read_command(
+    session: &client::Handle<SimpleHandler>,
+    command: &str,
+) -> (Option<u32>, String) {
+    let Ok(mut channel) = session.channel_open_session().await else {
+        return (None, String::new());
+    };
+    if channel.exec(true, command).await.is_err() {
+        return (None, String::new());
+    }
 
-    let mut session = client::connect(config, (conf.server.host.to_owned(), conf.server.port), sh)
+    let mut status = None;
+    let mut output = String::new();
+    while let Some(msg) = channel.wait().await {
+        match msg {
+            ChannelMsg::Data { ref data } => output.push_str(&String::from_utf8_lossy(data)),
+            ChannelMsg::ExitStatus { exit_status } => status = Some(exit_status),
+            _ => {}
+        }
+    }
+    (status, output)
+}
+
+// connects to server, through its jump hosts first when it has some
+pub async fn create_ssh_session(server: &Server) -> Result<client::Handle<SimpleHandler>, String> {
+    let config: Arc<_> = Arc::new(client::Config::default());
+    let mut hops = jump_servers(server).into_iter().chain([server.clone()]);
+    let first = hops.next().expect("the server is the last hop");
+    let sh = SimpleHandler::default();
+    let mut session = client::connect(config.clone(), (first.host.to_owned(), first.port), sh)
         .await
-        .unwrap();
-    session
-        .authenticate_publickey(conf.server.user.to_owned(), Arc::new(key))
+        .map_err(|e| format!("unable to connect to {}:{}: {}", first.host, first.port, e))?;
+    authenticate(&mut session, &first).await?;
+    for hop in hops {
+        let channel = session
+            .channel_open_direct_tcpip(hop.host.to_owned(), hop.port.into(), "127.0.0.1", 0)
+            .await
+            .map_err(|e| format!("unable to tunnel to {}:{}: {}", hop.host, hop.port, e))?;
+        let sh = SimpleHandler {
+            _jump: Some(session),
+        };
+        session = client::connect_stream(config.clone(), channel.into_stream(), sh)
+            .await
+            .map_err(|e| format!("unable to connect to {}:{}: {}", hop.host, hop.port, e))?;
+        authenticate(&mut session, &hop).await?;
+    }
+    Ok(session)
+}
+
+async fn authenticate(
+    session: &mut client::Handle<SimpleHandler>,
+    server: &Server,
+) -> Result<(), String> {
+    let key = load_secret_key(expand_user_path(&server.ssh_key), None)
+        .map_err(|e| format!("unable to load the key '{}': {}", server.ssh_key, e))?;
+    let accepted = session
+        .authenticate_publickey(server.user.to_owned(), Arc::new(key))
         .await
-        .expect("Unable to connect via SSH");
-    session
+        .map_err(|e| format!("unable to authenticate on {}: {}", server.host, e))?;
+    if !accepted {
+        return Err(format!(
+            "{}@{} refused the key '{}'",
+            server.user, server.host, server.ssh_key
+        ));
+    }
+    Ok(())
 }
@@ -0,0 +1,105 @@
+use crate::config::{Compression, RemoteShell};
+use flate2::write::GzEncoder;
+use std::io::{self, Write};
+
+use super::shell::quote;
+
+// compresses a file chunk by chunk, handing out what's ready to be sent
+pub enum Compressor {
+    Gzip(GzEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Compressor {
+    pub fn new(compression: Compression, level: Option<i32>) -> io::Result<Compressor> {
+        match compression {
+            Compression::Gzip => {
+                let level = level.map_or(flate2::Compression::default(), |level| {
+                    flate2::Compression::new(level.clamp(0, 9) as u32)
+                });
+                Ok(Compressor::Gzip(GzEncoder::new(Vec::new(), level)))
+            }
+            Compression::Zstd => Ok(Compressor::Zstd(zstd::stream::write::Encoder::new(
+                Vec::new(),
+                level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL),
+            )?)),
+        }
+    }
+
+    pub fn compress(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        let output = match self {
+            Compressor::Gzip(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.get_mut()
+            }
+            Compressor::Zstd(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.get_mut()
+            }
+        };
+        Ok(std::mem::take(output))
+    }
+
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Compressor::Gzip(encoder) => encoder.finish(),
+            Compressor::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+// suffix of the compressed copy uploaded next to each file
+pub fn extension(compression: Compression) -> &'static str {
+    match compression {
+        Compression::Gzip => ".gz",
+        Compression::Zstd => ".zst",
+    }
+}
+
+// unpacks an uploaded tar.zst into target and removes it
+pub fn extract_command(shell: RemoteShell, archive: &str, target: &str) -> String {
+    format!(
+        "tar -I zstd -xf {} -C {} && rm -f {}",
+        quote(shell, archive),
+        quote(shell, target),
+        quote(shell, archive)
+    )
+}
+
+// replaces the uploaded compressed copies by the original files, a hundred per command to keep
+// them short
+pub fn decompress_commands(
+    shell: RemoteShell,
+    compression: Compression,
+    paths: &[String],
+) -> Vec<String> {
+    paths
+        .chunks(100)
+        .map(|paths| {
+            let paths: Vec<String> = paths.iter().map(|path| quote(shell, path)).collect();
+            match compression {
+                Compression::Gzip => format!("gzip -d -f -- {}", paths.join(" ")),
+                Compression::Zstd => format!("zstd -d -f -q --rm -- {}", paths.join(" ")),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decompression_is_split_in_commands_of_a_hundred_files() {
+        let paths: Vec<String> = (0..250).map(|n| format!("/srv/site/{}.js.gz", n)).collect();
+        let commands = decompress_commands(RemoteShell::Sh, Compression::Gzip, &paths);
+        assert_eq!(commands.len(), 3);
+        assert!(commands[0].starts_with("gzip -d -f -- '/srv/site/0.js.gz' "));
+        assert!(commands[2].ends_with(" '/srv/site/249.js.gz'"));
+        let quoted: usize = commands
+            .iter()
+            .map(|command| command.matches(".gz'").count())
+            .sum();
+        assert_eq!(quoted, 250);
+    }
+}
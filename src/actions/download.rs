@@ -0,0 +1,158 @@
+use crate::actions::classify::deployignore;
+use crate::actions::upload::{expand_server_path, Progress, CHUNK_SIZE};
+use crate::config::Server;
+use crate::theme::THEME;
+use crate::Logger;
+use crate::{console, log};
+use async_std::fs::{self, File};
+use async_std::io::WriteExt;
+use colored::*;
+use russh_sftp::client::SftpSession;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+// the files under remote_folder with their size, by their path relative to it; symlinks, like the
+// linked_dirs of a release, aren't followed
+async fn list_files(sftp: &SftpSession, remote_folder: &str) -> Result<Vec<(String, u64)>, String> {
+    let remote_folder = remote_folder.trim_end_matches('/');
+    let mut files = Vec::new();
+    let mut folders = vec![String::new()];
+    while let Some(folder) = folders.pop() {
+        let path = match folder.as_str() {
+            "" => remote_folder.to_string(),
+            folder => format!("{}/{}", remote_folder, folder),
+        };
+        let entries = sftp
+            .read_dir(path.to_owned())
+            .await
+            .map_err(|err| format!("unable to list '{}': {}", path, err))?;
+        for entry in entries {
+            let name = entry.file_name();
+            if name == "." || name == ".." {
+                continue;
+            }
+            let relative_path = match folder.as_str() {
+                "" => name,
+                folder => format!("{}/{}", folder, name),
+            };
+            let file_type = entry.file_type();
+            if file_type.is_dir() {
+                folders.push(relative_path);
+            } else if file_type.is_file() {
+                files.push((relative_path, entry.metadata().len()));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// copies remote_path to local_path through a .part file, so a failed download leaves no truncated
+// file, calling on_chunk with the bytes of the file received so far
+async fn fetch(
+    sftp: &SftpSession,
+    remote_path: &str,
+    local_path: &Path,
+    mut on_chunk: impl FnMut(u64),
+) -> Result<(), String> {
+    if let Some(parent) = local_path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+    let mut remote_file = sftp
+        .open(remote_path.to_owned())
+        .await
+        .map_err(|err| err.to_string())?;
+    let part_path = format!("{}.part", local_path.display());
+    let mut local_file = File::create(&part_path)
+        .await
+        .map_err(|err| err.to_string())?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut received = 0;
+    loop {
+        let bytes_read = remote_file
+            .read(&mut buffer)
+            .await
+            .map_err(|err| err.to_string())?;
+        if bytes_read == 0 {
+            break;
+        }
+        local_file
+            .write_all(&buffer[..bytes_read])
+            .await
+            .map_err(|err| err.to_string())?;
+        received += bytes_read as u64;
+        on_chunk(received);
+    }
+    local_file.flush().await.map_err(|err| err.to_string())?;
+    drop(local_file);
+    fs::rename(&part_path, local_path)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+// downloads the files of remote_folder into local_folder, leaving out those the .deployignore of
+// ignore_folder matches; returns how many were downloaded out of how many were to be
+pub async fn download(
+    server: &Server,
+    logger: &mut Logger,
+    sftp: &SftpSession,
+    remote_folder: &str,
+    local_folder: &str,
+    ignore_folder: &str,
+) -> Result<(usize, usize), String> {
+    log!(
+        logger,
+        "{}{}{}{}{}",
+        "Downloading: '".bright_black(),
+        remote_folder.blue(),
+        "' to '".bright_black(),
+        local_folder.blue(),
+        "'".bright_black()
+    );
+    let remote_folder = expand_server_path(remote_folder, &server.user);
+    let remote_folder = remote_folder.trim_end_matches('/');
+    let matcher = deployignore(ignore_folder);
+    let (files, ignored): (Vec<_>, Vec<_>) = list_files(sftp, remote_folder)
+        .await?
+        .into_iter()
+        .partition(|(relative_path, _)| {
+            let path = Path::new(ignore_folder).join(relative_path);
+            !matcher.matched_path_or_any_parents(path, false).is_ignore()
+        });
+    if !ignored.is_empty() {
+        log!(
+            logger,
+            "{} {} files matching .deployignore patterns",
+            "Skipped:".bright_black(),
+            ignored.len()
+        );
+    }
+
+    let total_bytes = files.iter().map(|(_, size)| size).sum();
+    let mut progress = Progress::new(total_bytes, files.len(), logger);
+    let mut downloaded = 0;
+    progress.draw(None);
+    for (relative_path, size) in &files {
+        let remote_path = format!("{}/{}", remote_folder, relative_path);
+        let local_path = Path::new(local_folder).join(relative_path);
+        let mut received = 0;
+        let on_chunk = |file_received: u64| {
+            progress.update_file(received, file_received);
+            received = file_received;
+            progress.redraw(Some((relative_path.as_str(), received, *size)));
+        };
+        match fetch(sftp, &remote_path, &local_path, on_chunk).await {
+            Ok(()) => {
+                downloaded += 1;
+                progress.file_done();
+                logger.add_uploaded_file(relative_path.to_owned()).await;
+            }
+            Err(err) => console!("{} '{}': {}", THEME.error_label("Error:"), remote_path, err),
+        }
+        progress.draw(None);
+    }
+    logger.stop_files_display().await;
+    Ok((downloaded, files.len()))
+}
@@ -0,0 +1,164 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use openssl::ssl::{SslConnector, SslMethod, SslVersion};
+use tokio::time::{sleep, Instant};
+
+use crate::config::{Healthcheck, HttpUrl, TlsVersion};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(2);
+// a single attempt, so a hung service doesn't use up the whole timeout
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(10);
+// more of the body isn't needed to find expect_body
+const MAX_RESPONSE: usize = 1024 * 1024;
+
+// what is checked, for the log
+pub fn describe(healthcheck: &Healthcheck, server_host: &str) -> String {
+    match (&healthcheck.url, healthcheck.port) {
+        (Some(url), _) => url.to_string(),
+        (None, port) => format!(
+            "port {} of {}",
+            port.unwrap_or_default(),
+            healthcheck.host.as_deref().unwrap_or(server_host)
+        ),
+    }
+}
+
+// polls until the service is healthy, returns the attempts or the last reason it wasn't
+pub async fn wait_healthy(
+    healthcheck: &Healthcheck,
+    server_host: &str,
+    min_tls: TlsVersion,
+) -> Result<u32, String> {
+    let timeout = healthcheck.timeout.unwrap_or(DEFAULT_TIMEOUT);
+    let interval = healthcheck.interval.unwrap_or(DEFAULT_INTERVAL);
+    let start = Instant::now();
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let error = match check(healthcheck, server_host, min_tls).await {
+            Ok(()) => return Ok(attempts),
+            Err(error) => error,
+        };
+        if start.elapsed() + interval > timeout {
+            return Err(error);
+        }
+        sleep(interval).await;
+    }
+}
+
+// the status of a single GET of url
+pub async fn probe(url: &HttpUrl, min_tls: TlsVersion) -> Result<u16, String> {
+    let url = url.clone();
+    blocking(move || get(&url, min_tls))
+        .await
+        .map(|(status, _)| status)
+}
+
+async fn check(
+    healthcheck: &Healthcheck,
+    server_host: &str,
+    min_tls: TlsVersion,
+) -> Result<(), String> {
+    let Some(url) = healthcheck.url.clone() else {
+        let host = healthcheck
+            .host
+            .as_deref()
+            .unwrap_or(server_host)
+            .to_string();
+        let port = healthcheck.port.unwrap_or_default();
+        return blocking(move || connect(&host, port).map(|_| ())).await;
+    };
+    let (status, body) = blocking(move || get(&url, min_tls)).await?;
+    if status != healthcheck.expect_status {
+        return Err(format!(
+            "status {} instead of {}",
+            status, healthcheck.expect_status
+        ));
+    }
+    match &healthcheck.expect_body {
+        Some(expected) if !body.contains(expected.as_str()) => {
+            Err(format!("the body doesn't contain '{}'", expected))
+        }
+        _ => Ok(()),
+    }
+}
+
+// std sockets with timeouts, off the async runtime
+async fn blocking<T: Send + 'static>(
+    check: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> Result<T, String> {
+    match tokio::task::spawn_blocking(check).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let address = (host, port).to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("'{}' has no address", host),
+        )
+    })?;
+    let stream = TcpStream::connect_timeout(&address, ATTEMPT_TIMEOUT)?;
+    stream.set_read_timeout(Some(ATTEMPT_TIMEOUT))?;
+    stream.set_write_timeout(Some(ATTEMPT_TIMEOUT))?;
+    Ok(stream)
+}
+
+// the status and body of a GET of the url
+fn get(url: &HttpUrl, min_tls: TlsVersion) -> io::Result<(u16, String)> {
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: deploy\r\nConnection: close\r\n\r\n",
+        url.path, url.host
+    );
+    send(url, &request, min_tls)
+}
+
+// the status and body of the response to a request already formatted for the host of url
+pub fn send(url: &HttpUrl, request: &str, min_tls: TlsVersion) -> io::Result<(u16, String)> {
+    let stream = connect(&url.host, url.port)?;
+    let response = if url.https {
+        let mut connector = SslConnector::builder(SslMethod::tls())?;
+        connector.set_min_proto_version(Some(match min_tls {
+            TlsVersion::Tls12 => SslVersion::TLS1_2,
+            TlsVersion::Tls13 => SslVersion::TLS1_3,
+        }))?;
+        let connector = connector.build();
+        let mut stream = connector
+            .connect(&url.host, stream)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        exchange(&mut stream, request)?
+    } else {
+        exchange(&mut &stream, request)?
+    };
+
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not an http response"))?;
+    // a chunked body keeps its chunk sizes, which only matters for text spanning two chunks
+    Ok((status, body.to_string()))
+}
+
+fn exchange(stream: &mut (impl Read + Write), request: &str) -> io::Result<Vec<u8>> {
+    stream.write_all(request.as_bytes())?;
+    let mut response = Vec::new();
+    let mut buffer = [0; 8192];
+    while response.len() < MAX_RESPONSE {
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => response.extend_from_slice(&buffer[..read]),
+            // servers often close tls connections without a close_notify
+            Err(_) if !response.is_empty() => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(response)
+}
@@ -0,0 +1,41 @@
+use crate::log;
+use crate::Logger;
+use colored::Colorize;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+
+// runs commands one by one on this machine, stops at the first failure and returns its exit code
+pub async fn run_local(logger: &mut Logger, commands: &[String]) -> Option<i32> {
+    for command in commands {
+        log!(
+            logger,
+            "{}{}{}",
+            "Running locally: '".bright_black(),
+            command.cyan(),
+            "'".bright_black()
+        );
+        // stderr goes through the same pipe so both end up in the log in order
+        let mut child = match Command::new("sh")
+            .arg("-c")
+            .arg(format!("exec 2>&1\n{}", command))
+            .stdout(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) => {
+                log!(logger, "Unable to start '{}': {}", command, e);
+                return None;
+            }
+        };
+        if let Some(stdout) = child.stdout.take() {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                log!(logger, "{} {}", "$".bright_black(), line);
+            }
+        }
+        let status = child.wait().ok()?.code()?;
+        if status != 0 {
+            return Some(status);
+        }
+    }
+    Some(0)
+}
@@ -1,3 +1,14 @@
+pub mod backup;
+pub mod classify;
 pub mod commands;
+mod compression;
+mod download;
+pub mod health;
+mod local;
+mod release;
+mod retry;
 pub mod runner;
-mod upload;
+pub mod shell;
+pub mod status;
+pub mod upload;
+mod wait;
@@ -0,0 +1,75 @@
+use crate::config::RemoteShell;
+use chrono::Utc;
+use std::path::Path;
+
+use super::shell::quote;
+
+// every deployment gets its own folder under base_path/releases
+pub fn new_release(base_path: &str) -> String {
+    format!(
+        "{}/releases/{}",
+        base_path.trim_end_matches('/'),
+        Utc::now().format("%Y%m%d%H%M%S")
+    )
+}
+
+// upload target folders are relative to the release folder
+pub fn release_target(release: &str, target_folder: &str) -> String {
+    Path::new(release)
+        .join(target_folder.trim_start_matches("./"))
+        .to_string_lossy()
+        .to_string()
+}
+
+// the commit a release was deployed from, in a file of its folder
+pub const REVISION_FILE: &str = ".revision";
+
+pub fn revision_command(shell: RemoteShell, release: &str, revision: &str) -> String {
+    let path = format!("{}/{}", release.trim_end_matches('/'), REVISION_FILE);
+    format!(
+        "printf '%s\\n' {} > {}",
+        quote(shell, revision),
+        quote(shell, &path)
+    )
+}
+
+// marks a release made current once, the folders of deployments failing before the switch don't
+// have it and are never rolled back to
+pub const RELEASED_FILE: &str = ".released";
+
+pub fn mark_released_command(shell: RemoteShell, release: &str) -> String {
+    let path = format!("{}/{}", release.trim_end_matches('/'), RELEASED_FILE);
+    format!("touch {}", quote(shell, &path))
+}
+
+// the markers of the releases made current once, a path per line
+pub fn released_command(shell: RemoteShell, releases_path: &str) -> String {
+    format!(
+        "find {} -mindepth 2 -maxdepth 2 -name {}",
+        quote(shell, releases_path),
+        RELEASED_FILE
+    )
+}
+
+// the name of the release a readlink of current printed
+pub fn release_name(link: &str) -> &str {
+    link.trim()
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+}
+
+// points base_path/current to release, renaming over the old link makes the switch atomic
+pub fn switch_command(shell: RemoteShell, base_path: &str, release: &str) -> String {
+    let base_path = base_path.trim_end_matches('/');
+    let next = quote(shell, &format!("{}/current.next", base_path));
+    let current = quote(shell, &format!("{}/current", base_path));
+    format!(
+        "ln -sfn {} {} && mv -Tf {} {}",
+        quote(shell, release),
+        next,
+        next,
+        current
+    )
+}
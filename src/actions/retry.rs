@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// attempts after the first failed one, each waiting twice as long as the previous
+#[derive(Clone, Copy)]
+pub struct Retry {
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+impl Retry {
+    pub fn new(retries: u32, delay: Option<Duration>) -> Retry {
+        Retry {
+            retries,
+            delay: delay.unwrap_or(DEFAULT_RETRY_DELAY),
+        }
+    }
+
+    // the wait before retry number `attempt`, starting at 1
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.delay.saturating_mul(factor)
+    }
+}
+
+impl Default for Retry {
+    fn default() -> Retry {
+        Retry::new(0, None)
+    }
+}
@@ -1,55 +1,675 @@
-use crate::actions::upload::upload;
-use crate::config::Action;
+use crate::actions::backup::{
+    backup_command, backup_path, backups_folder, new_backup, restore_command,
+};
+use crate::actions::classify::{classify_files, unused_ignore_patterns, ContentFilter, Reason};
+use crate::actions::compression::{decompress_commands, extension, extract_command};
+use crate::actions::download::download;
+use crate::actions::health::{describe as describe_health, wait_healthy};
+use crate::actions::local::run_local;
+use crate::actions::release::{
+    mark_released_command, new_release, release_name, release_target, released_command,
+    revision_command, switch_command, REVISION_FILE,
+};
+use crate::actions::retry::Retry;
+use crate::actions::shell::{check_account_name, chown_commands, export_commands, quote};
+use crate::actions::upload::{
+    expand_server_path, expand_user_path, format_bytes, open_files_limit, open_files_warning,
+    upload, upload_archive, upload_concurrently, upload_secret_files, TransferOptions, Tuner,
+    ARCHIVE_NAME,
+};
+use crate::actions::wait::wait_for_port;
+use crate::cache::{self, Cache};
+use crate::config::{
+    action_dependencies, action_order, protected_matcher, secret_matcher, Action, Concurrency,
+    Condition, Conditions, Relay, RemoteShell, Server, Strategy, TransferMode,
+};
+use crate::lock::{hash_file, Lock};
+use crate::report::{DeploymentReport, Status};
+use crate::theme::THEME;
 use crate::{
-    actions::commands::{create_ssh_session, send_command},
+    actions::commands::{
+        create_ssh_session, read_command, send_command, send_command_with_timeout, SimpleHandler,
+    },
     config::Config,
-    logger::Logger,
+    logger::{Logger, TIMEOUT_STATUS},
 };
+use crate::{console, log, log_debug, log_error, log_warn};
 use chrono::Duration;
 use colored::Colorize;
-use russh::Disconnect;
-use std::collections::HashSet;
-use tokio::time::Instant;
+use futures::stream::{FuturesUnordered, StreamExt};
+use russh::{client, Disconnect};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
 use russh_sftp::client::SftpSession;
 
-pub async fn execute_actions(logger: &mut Logger, config: Config, skip: HashSet<String>) {
-    let start_time = Instant::now();
+pub struct Options {
+    // actions and hooks from the config file to skip
+    pub skip: HashSet<String>,
+    // exclude unreadable local files instead of aborting
+    pub skip_unreadable: bool,
+    // list .deployignore patterns that matched no file after each upload
+    pub report_unused_ignores: bool,
+    // print the remote commands and uploads instead of connecting
+    pub dry_run: bool,
+    // upload every file, even those the cache knows unchanged
+    pub force: bool,
+    // `--limit-rate`, replacing the max_bandwidth of the config
+    pub limit_rate: Option<u64>,
+    // release folder of the failed deployment `--resume` continues, instead of a new one
+    pub release: Option<String>,
+    // `--env` profile, for only_if_env
+    pub env: Option<String>,
+    // commit of the local checkout, written into each release for `--releases`
+    pub revision: Option<String>,
+    // `--delete`, removing the server files of each upload target that aren't local anymore
+    pub delete: bool,
+}
+
+// prints why each file of every upload is or isn't uploaded
+pub fn explain(config: &Config, skip: &HashSet<String>) {
     for action in &config.actions {
-        match action {
-            Action::Commands { name, commands } => {
-                if skip.contains(name) {
-                    continue;
+        let Action::Upload {
+            name,
+            source_folder,
+            skip_empty_files,
+            skip_first_line,
+            use_gitignore,
+            ..
+        } = action
+        else {
+            continue;
+        };
+        if skip.contains(name) {
+            continue;
+        }
+        println!("{} {}", "Explaining:".bright_black(), name);
+        let filter = match ContentFilter::new(*skip_empty_files, skip_first_line.as_deref()) {
+            Ok(filter) => filter,
+            Err(e) => {
+                println!("{} {}", THEME.error_label("Error:"), e);
+                continue;
+            }
+        };
+        let source_folder = expand_user_path(source_folder);
+        for (path, reason) in classify_files(&source_folder, &filter, *use_gitignore, true) {
+            let color = match reason {
+                Reason::Upload => THEME.success,
+                _ => THEME.muted,
+            };
+            println!(
+                "'{}': {}",
+                path.strip_prefix(&source_folder).unwrap().display(),
+                reason.to_string().color(color)
+            );
+        }
+    }
+}
+
+// prints the host, the steps in the order they run and the files each upload would send
+pub fn plan(config: &Config, skip: &HashSet<String>, force: bool) {
+    let server = &config.server;
+    println!(
+        "{} {}@{}:{}",
+        "Host:".bright_black(),
+        server.user,
+        server.host,
+        server.port
+    );
+    if let Some(relay) = &config.relay {
+        println!(
+            "{} uploads through {}",
+            "Relay:".bright_black(),
+            relay.server.host
+        );
+    }
+
+    let mut steps: Vec<(&str, String)> = Vec::new();
+    let hook = |hook: &'static str, host: &str| {
+        let commands = hook_commands(config, hook);
+        let step = format!("runs '{}' on {}", commands.join(" && ").cyan(), host);
+        (!commands.is_empty()).then_some((hook, step))
+    };
+    steps.extend(hook("pre_deploy_local", "localhost"));
+    if let Some(preflight) = &config.preflight {
+        let step = format!("runs '{}' on {}", preflight.cyan(), server.host);
+        steps.push(("preflight", step));
+    }
+    steps.extend(hook("pre_deploy_remote", &server.host));
+    let linked: Vec<&str> = config
+        .linked_dirs
+        .iter()
+        .chain(&config.linked_files)
+        .map(String::as_str)
+        .collect();
+    if !linked.is_empty() {
+        let step = format!("links {} from the shared folder", linked.join(", "));
+        steps.push(("link_shared", step));
+    }
+    let order = action_order(&config.actions).expect("dependencies checked with the config");
+    for action in order.iter().map(|index| &config.actions[*index]) {
+        let mut step = match action {
+            Action::Commands { commands, env, .. } => {
+                let mut step =
+                    format!("runs '{}' on {}", commands.join(" && ").cyan(), server.host);
+                if !env.is_empty() {
+                    let variables: Vec<&str> = env.keys().map(String::as_str).collect();
+                    step.push_str(&format!(" with {}", variables.join(", ")));
                 }
-                let session = create_ssh_session(&config).await;
-                send_command(&mut *logger, &session, commands).await;
-                session
-                    .disconnect(Disconnect::ByApplication, "", "English")
-                    .await
-                    .expect("Failed to close ssh session");
+                step
             }
             Action::Upload {
-                name,
                 source_folder,
                 target_folder,
+                backup,
+                secret_files,
+                ..
             } => {
-                if skip.contains(name) {
-                    continue;
+                let mut step = format!("uploads '{}' to '{}'", source_folder, target_folder);
+                if *backup {
+                    step.push_str(", backing up the target first");
+                }
+                if !secret_files.is_empty() {
+                    step.push_str(&format!(", {} with mode 600", secret_files.join(", ")));
                 }
-                let session = create_ssh_session(&config).await;
+                step
+            }
+            Action::WaitForPort { host, port, .. } => format!("waits for {}:{}", host, port),
+        };
+        if let Some(dependencies) = action.depends_on() {
+            match dependencies {
+                [] => step.push_str(", right away"),
+                dependencies => step.push_str(&format!(", after {}", dependencies.join(", "))),
+            }
+        }
+        for condition in describe_conditions(action.conditions()) {
+            step.push_str(&format!(", {}", condition));
+        }
+        steps.push((action.name(), step));
+    }
+    if let (Strategy::Releases, Some(base_path)) = (config.strategy, &config.base_path) {
+        let step = format!("makes a new release of {} current", base_path);
+        steps.push(("release", step));
+    }
+    steps.extend(hook("post_deploy_remote", &server.host));
+    if let Some(healthcheck) = &config.healthcheck {
+        let mut step = format!("checks {}", describe_health(healthcheck, &server.host));
+        if healthcheck.rollback && config.strategy == Strategy::Releases {
+            step.push_str(", rolls back when unhealthy");
+        }
+        steps.push(("healthcheck", step));
+    }
+    steps.extend(hook("post_deploy_local", "localhost"));
+    if let (Strategy::Releases, Some(keep)) = (config.strategy, config.keep_releases) {
+        let step = format!("removes the releases before the {} most recent", keep);
+        steps.push(("prune_releases", step));
+    }
+    println!("{}", "Steps:".bright_black());
+    for (number, (name, step)) in steps.iter().enumerate() {
+        let line = format!("  {}. {}: {}", number + 1, name, step);
+        if skip.contains(*name) {
+            println!("{}", format!("{} (skipped)", line).color(THEME.muted));
+        } else {
+            println!("{}", line);
+        }
+    }
 
-                let channel = session.channel_open_session().await.unwrap();
-                channel.request_subsystem(true, "sftp").await.unwrap();
-                let mut sftp = SftpSession::new(channel.into_stream()).await.expect("Unable to connect in SFTP");
-                upload(
-                    &config,
-                    &mut *logger,
-                    &mut sftp,
-                    source_folder,
-                    target_folder,
-                )
-                .await;
-                sftp.close().await.expect("Failed to close sftp session");
+    // every release starts from an empty folder, so nothing can be reused there
+    let use_cache = !force && config.strategy != Strategy::Releases;
+    let cache = if use_cache {
+        cache::load()
+    } else {
+        Cache::new()
+    };
+    for action in &config.actions {
+        let Action::Upload {
+            name,
+            source_folder,
+            target_folder,
+            skip_empty_files,
+            skip_first_line,
+            use_gitignore,
+            ..
+        } = action
+        else {
+            continue;
+        };
+        if skip.contains(name) {
+            continue;
+        }
+        println!("{} {}", "Files:".bright_black(), name);
+        let filter = match ContentFilter::new(*skip_empty_files, skip_first_line.as_deref()) {
+            Ok(filter) => filter,
+            Err(e) => {
+                println!("{} {}", THEME.error_label("Error:"), e);
+                continue;
+            }
+        };
+        let source_folder = expand_user_path(source_folder);
+        let files: Vec<PathBuf> = classify_files(&source_folder, &filter, *use_gitignore, false)
+            .into_iter()
+            .filter(|(_, reason)| matches!(reason, Reason::Upload))
+            .map(|(path, _)| path)
+            .collect();
+        let target = expand_server_path(target_folder, &server.user);
+        let previous = cache.get(&cache::key(server, &target));
+        let (files, unchanged) = split_unchanged(previous, &source_folder, files);
+        for path in &files {
+            let relative_path = path.strip_prefix(&source_folder).unwrap();
+            let relative_path = relative_path.to_string_lossy();
+            // sent before with other content, or never
+            if previous.is_some_and(|hashes| hashes.contains_key(relative_path.as_ref())) {
+                println!("  {} '{}'", "~".color(THEME.warning), relative_path);
+            } else {
+                println!("  {} '{}'", "+".color(THEME.success), relative_path);
+            }
+        }
+        println!(
+            "  {} to send, {} unchanged since the last upload",
+            files.len(),
+            unchanged.len()
+        );
+    }
+}
+
+// splits the files of an upload into those to send and the hashes of those the last upload to
+// the same destination, with previous as its hashes, already sent with the same content
+fn split_unchanged(
+    previous: Option<&BTreeMap<String, String>>,
+    source_folder: &str,
+    files: Vec<PathBuf>,
+) -> (Vec<PathBuf>, BTreeMap<String, String>) {
+    let mut changed = Vec::new();
+    let mut unchanged = BTreeMap::new();
+    for path in files {
+        let relative_path = path.strip_prefix(source_folder).unwrap();
+        let relative_path = relative_path.to_string_lossy().to_string();
+        let hash = hash_file(&path).ok();
+        match (hash, previous.and_then(|hashes| hashes.get(&relative_path))) {
+            (Some(hash), Some(previous_hash)) if hash == *previous_hash => {
+                unchanged.insert(relative_path, hash);
             }
+            _ => changed.push(path),
+        }
+    }
+    (changed, unchanged)
+}
+
+// runs the deployment, recording each phase in the report; returns the uploaded file hashes
+pub async fn execute_actions(
+    logger: &mut Logger,
+    config: Config,
+    options: Options,
+    report: &mut DeploymentReport,
+) -> Lock {
+    let skip = &options.skip;
+    let mut uploaded = Lock::new();
+    let release = match (config.strategy, &config.base_path) {
+        (Strategy::Releases, Some(base_path)) => Some(
+            options
+                .release
+                .clone()
+                .unwrap_or_else(|| new_release(base_path)),
+        ),
+        _ => None,
+    };
+    report.release = release.clone();
+    if !options.dry_run && !run_hook(logger, &config, "pre_deploy_local", skip, report).await {
+        return uploaded;
+    }
+    let start_time = report.start("prepare");
+
+    // resolve and check the files of every upload before connecting
+    let mut file_sets = HashMap::new();
+    let mut unreadable = BTreeSet::new();
+    // every release starts from an empty folder, so nothing can be reused there
+    let use_cache = !options.force && release.is_none();
+    let cache = if use_cache {
+        cache::load()
+    } else {
+        Cache::new()
+    };
+    let mut unchanged = HashMap::new();
+    for (index, action) in config.actions.iter().enumerate() {
+        let Action::Upload {
+            name,
+            source_folder,
+            target_folder,
+            skip_empty_files,
+            skip_first_line,
+            use_gitignore,
+            remote_owner,
+            remote_group,
+            ..
+        } = action
+        else {
+            continue;
+        };
+        if skip.contains(name) {
+            continue;
+        }
+        let filter = remote_owner
+            .iter()
+            .chain(remote_group)
+            .try_for_each(|account| check_account_name(account))
+            .and_then(|_| ContentFilter::new(*skip_empty_files, skip_first_line.as_deref()));
+        let filter = match filter {
+            Ok(filter) => filter,
+            Err(e) => {
+                log_error!(logger, "{} {}: {}", THEME.error_label("Error:"), name, e);
+                report.fail("prepare", start_time.elapsed(), format!("{}: {}", name, e));
+                return uploaded;
+            }
+        };
+        let source_folder = expand_user_path(source_folder);
+        let mut files = Vec::new();
+        for (path, reason) in classify_files(&source_folder, &filter, *use_gitignore, false) {
+            let relative_path = path.strip_prefix(&source_folder).unwrap().display();
+            if !matches!(reason, Reason::Upload) {
+                report.add_skipped(name, relative_path.to_string(), reason.to_string());
+            }
+            match reason {
+                Reason::Upload => files.push(path),
+                reason @ (Reason::Ignored(_) | Reason::GitIgnored) => {
+                    log_debug!(
+                        logger,
+                        "{} '{}' ({})",
+                        "Ignored:".bright_black(),
+                        relative_path,
+                        reason
+                    );
+                }
+                Reason::Skipped(reason) => {
+                    log!(
+                        logger,
+                        "{} '{}' ({})",
+                        "Skipped:".bright_black(),
+                        relative_path,
+                        reason
+                    );
+                }
+                Reason::Unreadable => {
+                    unreadable.insert(path);
+                }
+            }
+        }
+        if use_cache {
+            let target = expand_server_path(target_folder, &config.server.user);
+            let previous = cache.get(&cache::key(&config.server, &target));
+            let (changed, unchanged_hashes) = split_unchanged(previous, &source_folder, files);
+            for relative_path in unchanged_hashes.keys() {
+                log_debug!(
+                    logger,
+                    "{} '{}'",
+                    "Unchanged:".bright_black(),
+                    relative_path
+                );
+                let reason = "unchanged since the last upload".to_string();
+                report.add_skipped(name, relative_path.clone(), reason);
+            }
+            if !unchanged_hashes.is_empty() {
+                log!(
+                    logger,
+                    "{} {} files unchanged since the last upload, pass --force to send them",
+                    "Unchanged:".bright_black(),
+                    unchanged_hashes.len()
+                );
+            }
+            files = changed;
+            unchanged.insert(index, unchanged_hashes);
+        }
+        file_sets.insert(index, files);
+    }
+    if !unreadable.is_empty() {
+        for path in &unreadable {
+            log_error!(
+                logger,
+                "{} '{}'",
+                THEME.error_label("Unreadable:"),
+                path.display()
+            );
+        }
+        if !options.skip_unreadable {
+            log_error!(
+                logger,
+                "{} {} files can't be read, pass --skip-unreadable to exclude them",
+                THEME.error_label("Error:"),
+                unreadable.len()
+            );
+            let error = format!("{} files can't be read", unreadable.len());
+            report.fail("prepare", start_time.elapsed(), error);
+            return uploaded;
+        }
+        log_warn!(
+            logger,
+            "{} skipping {} unreadable files",
+            THEME.warning_label("Warning:"),
+            unreadable.len()
+        );
+    }
+    let concurrency = config
+        .upload_concurrency
+        .channels(config.upload_concurrency_max);
+    let uploads_concurrently =
+        config.transfer_mode == TransferMode::Files && concurrency > 1 && !file_sets.is_empty();
+    if uploads_concurrently {
+        match open_files_limit() {
+            Some(limit) => {
+                if let Some(warning) = open_files_warning(concurrency, limit) {
+                    log_warn!(logger, "{} {}", THEME.warning_label("Warning:"), warning);
+                }
+            }
+            None => {
+                log!(
+                    logger,
+                    "{} the open files check, the limit can't be read on this system",
+                    "Skipped:".bright_black()
+                );
+            }
+        }
+    }
+    report.record("prepare", start_time.elapsed(), None);
+
+    if options.dry_run {
+        let release = release.as_deref();
+        let revision = options.revision.as_deref();
+        let delete = options.delete.then_some(&unchanged);
+        dry_run(logger, &config, skip, &file_sets, release, revision, delete).await;
+        return uploaded;
+    }
+
+    // a server that can't be reached fails here, before any phase changes it
+    let phase_start = report.start("connect");
+    let Some(session) = phase_session(logger, &config.server, "connect", phase_start, report).await
+    else {
+        return uploaded;
+    };
+    let _ = session
+        .disconnect(Disconnect::ByApplication, "", "English")
+        .await;
+    report.record("connect", phase_start.elapsed(), None);
+
+    if let Some(preflight) = &config.preflight {
+        let phase_start = report.start("preflight");
+        let Some(session) =
+            phase_session(logger, &config.server, "preflight", phase_start, report).await
+        else {
+            return uploaded;
+        };
+        let status =
+            send_command(&mut *logger, &session, "preflight", &[preflight.to_owned()]).await;
+        session
+            .disconnect(Disconnect::ByApplication, "", "English")
+            .await
+            .expect("Failed to close ssh session");
+        if !preflight_passed(logger, status, phase_start, report).await {
+            return uploaded;
+        }
+    }
+    if !run_hook(logger, &config, "pre_deploy_remote", skip, report).await {
+        return uploaded;
+    }
+    if let Some(release) = &release {
+        if !link_shared(logger, &config, release, report).await {
+            return uploaded;
+        }
+    }
+    let dependencies =
+        action_dependencies(&config.actions).expect("dependencies checked with the config");
+    let order = action_order(&config.actions).expect("dependencies checked with the config");
+    // a chain of steps keeps the interactive console, branches running at once can't share it
+    let concurrent = order
+        .windows(2)
+        .any(|pair| !dependencies[pair[1]].contains(&pair[0]));
+    // what changed(...) conditions are matched against
+    let changed: Vec<PathBuf> = file_sets.values().flatten().cloned().collect();
+    let backup = new_backup();
+    let context = StepContext {
+        config: &config,
+        options: &options,
+        release: release.as_deref(),
+        backup: &backup,
+        changed: &changed,
+    };
+    let context = &context;
+    // skipped steps count as done for those depending on them
+    let mut done: HashSet<usize> = (0..config.actions.len())
+        .filter(|index| skip.contains(config.actions[*index].name()))
+        .collect();
+    let mut started = HashSet::new();
+    let mut running = FuturesUnordered::new();
+    let mut failed = false;
+    loop {
+        for &index in &order {
+            let ready = dependencies[index]
+                .iter()
+                .all(|dependency| done.contains(dependency));
+            // once a step failed, the running ones finish but no other starts
+            if failed || !ready || done.contains(&index) || !started.insert(index) {
+                continue;
+            }
+            let action = &config.actions[index];
+            let mut logger = logger.branch();
+            if concurrent {
+                logger.set_concurrent(action.name());
+            }
+            let mut report = report.branch();
+            let files = file_sets.remove(&index).unwrap_or_default();
+            let unchanged = unchanged.remove(&index).unwrap_or_default();
+            running.push(async move {
+                let hashes =
+                    run_action(&mut logger, context, action, files, unchanged, &mut report).await;
+                (index, report, hashes)
+            });
+        }
+        let Some((index, step_report, hashes)) = running.next().await else {
+            break;
+        };
+        done.insert(index);
+        if let Some((target_folder, hashes)) = hashes {
+            // a step failing after its upload left the files unpacked or unowned, they go again
+            if use_cache && step_report.error.is_none() {
+                let target = expand_server_path(&target_folder, &config.server.user);
+                let key = cache::key(&config.server, &target);
+                if let Err(e) = cache::update(key, hashes.clone()) {
+                    eprintln!(
+                        "Unable to write upload cache: {}",
+                        e.to_string().color(THEME.error)
+                    );
+                }
+            }
+            uploaded.insert(config.actions[index].name().to_owned(), hashes);
+        }
+        failed |= step_report.error.is_some();
+        report.merge(step_report);
+    }
+    if failed {
+        return uploaded;
+    }
+
+    // the release rolled back to when the new one turns out unhealthy
+    let mut previous_release = None;
+    if let Some(release) = &release {
+        let phase_start = report.start("release");
+        if report.phases.iter().any(|phase| phase.failure.is_some()) {
+            log_warn!(
+                logger,
+                "{} an action failed, '{}' is not made current",
+                THEME.warning_label("Warning:"),
+                release
+            );
+        } else {
+            let switch = release_switch(&config, release, options.revision.as_deref());
+            let Some(session) =
+                phase_session(logger, &config.server, "release", phase_start, report).await
+            else {
+                return uploaded;
+            };
+            let (_, current) = read_command(&session, &current_link(&config)).await;
+            previous_release = Some(release_name(&current).to_string()).filter(|r| !r.is_empty());
+            let status = send_command(&mut *logger, &session, "release", &[switch]).await;
+            session
+                .disconnect(Disconnect::ByApplication, "", "English")
+                .await
+                .expect("Failed to close ssh session");
+            if status != Some(0) {
+                log_error!(
+                    logger,
+                    "{} unable to make '{}' current",
+                    THEME.error_label("Error:"),
+                    release
+                );
+                let error = format!("switch to release '{}' failed", release);
+                report.fail("release", phase_start.elapsed(), error);
+                return uploaded;
+            }
+            log!(logger, "{} {}", "Released:".bright_black(), release);
+            report.record("release", phase_start.elapsed(), None);
+        }
+    }
+
+    if report.phases.iter().any(|phase| phase.failure.is_some()) {
+        log_warn!(
+            logger,
+            "{} an action failed, skipping the post deploy hooks",
+            THEME.warning_label("Warning:")
+        );
+    } else {
+        if !run_hook(logger, &config, "post_deploy_remote", skip, report).await {
+            return uploaded;
+        }
+        // once the remote steps are done, before telling anyone with post_deploy_local
+        if !check_health(logger, &config, skip, report).await {
+            // the release made current above is the unhealthy one, the one it replaced comes back
+            // and on_rollback restarts the service on it
+            let auto_rollback = config.healthcheck.as_ref().is_some_and(|h| h.rollback);
+            match previous_release.as_deref() {
+                Some(previous) if release.is_some() && auto_rollback => {
+                    log_warn!(
+                        logger,
+                        "{} rolling back to '{}'",
+                        THEME.warning_label("Warning:"),
+                        previous
+                    );
+                    if rollback(logger, &config, Some(previous), report).await {
+                        report.mark_rolled_back();
+                    }
+                }
+                None if release.is_some() && auto_rollback => {
+                    log_warn!(
+                        logger,
+                        "{} no release was current before this one, nothing to roll back to",
+                        THEME.warning_label("Warning:")
+                    );
+                }
+                _ => {}
+            }
+            return uploaded;
+        }
+        if !run_hook(logger, &config, "post_deploy_local", skip, report).await {
+            return uploaded;
+        }
+        if let (Some(_), Some(keep)) = (&release, config.keep_releases) {
+            prune_releases(logger, &config, keep, report).await;
         }
     }
 
@@ -63,5 +683,2513 @@ pub async fn execute_actions(logger: &mut Logger, config: Config, skip: HashSet<
         (_, _) => format!("{}h {}m {}s", hours, minutes, seconds),
     };
 
-    println!("{} finished in {}", "Done:".bright_black(), formatted_time);
+    console!("{} finished in {}", "Done:".bright_black(), formatted_time);
+    uploaded
+}
+
+// what the steps of a deployment share
+struct StepContext<'a> {
+    config: &'a Config,
+    options: &'a Options,
+    release: Option<&'a str>,
+    // folder of this deployment in ~/.deploy-backups, for the uploads with backup = true
+    backup: &'a str,
+    // local paths of the files the uploads send
+    changed: &'a [PathBuf],
+}
+
+// why the conditions of an action keep it from running, None when it runs
+async fn unmet_condition(
+    logger: &mut Logger,
+    context: &StepContext<'_>,
+    action: &Action,
+) -> Option<String> {
+    let conditions = action.conditions();
+    if let Some(env) = &conditions.only_if_env {
+        if context.options.env.as_ref() != Some(env) {
+            return Some(format!("only_if_env is '{}'", env));
+        }
+    }
+    if let Some(condition) = &conditions.only_if {
+        if !condition_holds(logger, context, condition).await {
+            return Some(format!("only_if {} is false", condition));
+        }
+    }
+    if let Some(condition) = &conditions.unless {
+        if condition_holds(logger, context, condition).await {
+            return Some(format!("unless {} is true", condition));
+        }
+    }
+    None
+}
+
+async fn condition_holds(
+    logger: &mut Logger,
+    context: &StepContext<'_>,
+    condition: &Condition,
+) -> bool {
+    match condition {
+        Condition::Changed { matcher, .. } => context.changed.iter().any(|path| {
+            let path = path.strip_prefix("./").unwrap_or(path);
+            !path.is_absolute() && matcher.matched_path_or_any_parents(path, false).is_ignore()
+        }),
+        Condition::Shell(command) => run_local(logger, &[command.to_owned()]).await == Some(0),
+    }
+}
+
+// runs an action when its conditions hold, recording its phase in report; uploads return
+// their target folder and the hashes of its files, sent or unchanged
+async fn run_action(
+    logger: &mut Logger,
+    context: &StepContext<'_>,
+    action: &Action,
+    files: Vec<PathBuf>,
+    unchanged: BTreeMap<String, String>,
+    report: &mut DeploymentReport,
+) -> Option<(String, BTreeMap<String, String>)> {
+    let StepContext {
+        config,
+        options,
+        release,
+        ..
+    } = *context;
+    if let Some(reason) = unmet_condition(logger, context, action).await {
+        log!(
+            logger,
+            "{} '{}', {}",
+            "Skipped:".bright_black(),
+            action.name(),
+            reason
+        );
+        return None;
+    }
+    match action {
+        Action::Commands {
+            name,
+            commands,
+            allow_failure,
+            retries,
+            retry_delay,
+            timeout,
+            env,
+            ..
+        } => {
+            let phase_start = report.start(name);
+            let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+            let commands = &[export_commands(shell, env), commands.clone()].concat();
+            let session = phase_session(logger, &config.server, name, phase_start, report).await?;
+            let retry = Retry::new(*retries, *retry_delay);
+            let status =
+                send_command_with_retries(logger, &session, name, commands, retry, *timeout).await;
+            session
+                .disconnect(Disconnect::ByApplication, "", "English")
+                .await
+                .expect("Failed to close ssh session");
+            // no exit status when the console was left with ESC
+            match status.filter(|status| *status != 0) {
+                // even allowed failures don't outlast the deployment
+                Some(TIMEOUT_STATUS) if logger.timed_out() => {
+                    let error = "deployment timed out".to_string();
+                    report.fail_command(name, phase_start.elapsed(), TIMEOUT_STATUS, error);
+                    return None;
+                }
+                Some(status) if *allow_failure => {
+                    log_warn!(
+                        logger,
+                        "{} '{}' failed with exit status {}, allowed to fail",
+                        THEME.warning_label("Warning:"),
+                        name,
+                        status
+                    );
+                    report.record_command(name, phase_start.elapsed(), Some(status), None);
+                }
+                Some(status) => {
+                    log_error!(
+                        logger,
+                        "{} '{}' failed with exit status {}",
+                        THEME.error_label("Error:"),
+                        name,
+                        status
+                    );
+                    let error = format!("exit status {}", status);
+                    report.fail_command(name, phase_start.elapsed(), status, error);
+                    return None;
+                }
+                None => report.record_command(name, phase_start.elapsed(), status, None),
+            }
+            None
+        }
+        Action::Upload {
+            name,
+            source_folder,
+            target_folder,
+            retries,
+            retry_delay,
+            backup,
+            secret_files,
+            ..
+        } => {
+            let phase_start = report.start(name);
+            let server = match &config.relay {
+                Some(relay) => &relay.server,
+                None => &config.server,
+            };
+            let target_folder = &match release {
+                Some(release) => release_target(release, target_folder),
+                None => target_folder.to_owned(),
+            };
+            let session = phase_session(logger, server, name, phase_start, report).await?;
+
+            if *backup {
+                let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+                let path = backup_path(&config.backup_key, context.backup, name);
+                let path = expand_server_path(&path, &server.user);
+                let target = expand_server_path(target_folder, &server.user);
+                let command = backup_command(shell, &path, target.trim_end_matches('/'));
+                let status = send_command(&mut *logger, &session, "backup", &[command]).await;
+                if status != Some(0) {
+                    log_error!(
+                        logger,
+                        "{} unable to back up '{}', nothing was uploaded",
+                        THEME.error_label("Error:"),
+                        target
+                    );
+                    session
+                        .disconnect(Disconnect::ByApplication, "", "English")
+                        .await
+                        .expect("Failed to close ssh session");
+                    report.fail(name, phase_start.elapsed(), "backup failed".to_string());
+                    return None;
+                }
+                log!(
+                    logger,
+                    "{} '{}' to '{}'",
+                    "Backed up:".bright_black(),
+                    target,
+                    path
+                );
+            }
+
+            // each concurrent transfer gets its own sftp channel on the session
+            let mut sftps = Vec::new();
+            let channels = match config.transfer_mode {
+                TransferMode::Files => config
+                    .upload_concurrency
+                    .channels(config.upload_concurrency_max),
+                TransferMode::Archive => 1,
+            };
+            for _ in 0..channels {
+                match open_sftp(&session).await {
+                    Ok(sftp) => sftps.push(sftp),
+                    Err(e) => {
+                        log_error!(logger, "{} {}", THEME.error_label("Error:"), e);
+                        report.fail(name, phase_start.elapsed(), e);
+                        return None;
+                    }
+                }
+            }
+            // what --delete keeps on the server: every local file, sent or unchanged
+            let delete = options.delete && release.is_none();
+            let local: Option<BTreeSet<String>> = delete.then(|| {
+                let source_folder = expand_user_path(source_folder);
+                files
+                    .iter()
+                    .filter_map(|path| path.strip_prefix(&source_folder).ok())
+                    .map(|path| path.to_string_lossy().to_string())
+                    .chain(unchanged.keys().cloned())
+                    .collect()
+            });
+            let secret =
+                secret_matcher(secret_files).expect("secret_files checked with the config");
+            let source = expand_user_path(source_folder);
+            let (secrets, files): (Vec<_>, Vec<_>) = files.into_iter().partition(|path| {
+                path.strip_prefix(&source).is_ok_and(|relative_path| {
+                    secret
+                        .matched_path_or_any_parents(relative_path, false)
+                        .is_ignore()
+                })
+            });
+            let transfer_options = TransferOptions {
+                compression: config.compression,
+                compression_level: config.compression_level,
+                max_bandwidth: options.limit_rate.or(config.max_bandwidth),
+                retry: Retry::new(*retries, *retry_delay),
+                tuner: (config.upload_concurrency == Concurrency::Auto).then(|| {
+                    let max = config.upload_concurrency_max;
+                    Tuner::new(config.upload_concurrency_min, max)
+                }),
+            };
+            let mut files = match sftps.as_mut_slice() {
+                [sftp] if config.transfer_mode == TransferMode::Archive => {
+                    let archived = upload_archive(
+                        server,
+                        &mut *logger,
+                        sftp,
+                        source_folder,
+                        target_folder,
+                        files,
+                        transfer_options,
+                    )
+                    .await;
+                    match archived {
+                        Ok(files) => files,
+                        Err(e) => {
+                            log_error!(logger, "{} {}", THEME.error_label("Error:"), e);
+                            session
+                                .disconnect(Disconnect::ByApplication, "", "English")
+                                .await
+                                .expect("Failed to close ssh session");
+                            report.fail(name, phase_start.elapsed(), e);
+                            return None;
+                        }
+                    }
+                }
+                [sftp] => {
+                    upload(
+                        server,
+                        &mut *logger,
+                        sftp,
+                        source_folder,
+                        target_folder,
+                        files,
+                        transfer_options,
+                    )
+                    .await
+                }
+                sftps => {
+                    upload_concurrently(
+                        server,
+                        &mut *logger,
+                        sftps,
+                        source_folder,
+                        target_folder,
+                        files,
+                        transfer_options,
+                    )
+                    .await
+                }
+            };
+            // the secret files are sent as they are, with nothing to decompress
+            let relative_paths = files.iter().map(|file| file.path.as_str());
+            let decompress = decompress_uploaded(config, server, target_folder, relative_paths);
+            if !secrets.is_empty() {
+                let retry = transfer_options.retry;
+                files.extend(
+                    upload_secret_files(
+                        server,
+                        &mut *logger,
+                        &mut sftps[0],
+                        source_folder,
+                        target_folder,
+                        secrets,
+                        retry,
+                    )
+                    .await,
+                );
+            }
+            let relative_paths = files.iter().map(|file| file.path.as_str());
+            let chown = chown_uploaded(config, action, target_folder, relative_paths);
+            let mut hashes = unchanged;
+            hashes.extend(
+                files
+                    .iter()
+                    .map(|file| (file.path.to_owned(), file.sha256.to_owned())),
+            );
+            let uploaded = Some((target_folder.to_owned(), hashes));
+            report.add_files(name, files);
+
+            if options.report_unused_ignores {
+                for pattern in unused_ignore_patterns(&expand_user_path(source_folder)) {
+                    log!(
+                        logger,
+                        "{} .deployignore pattern '{}' matches no file",
+                        "Unused:".bright_black(),
+                        pattern
+                    );
+                }
+            }
+            for sftp in sftps {
+                sftp.close().await.expect("Failed to close sftp session");
+            }
+
+            if !decompress.is_empty() {
+                let status = send_each(&mut *logger, &session, "decompress", &decompress).await;
+                if status != Some(0) {
+                    log_error!(
+                        logger,
+                        "{} unable to decompress the uploaded files",
+                        THEME.error_label("Error:")
+                    );
+                    report.fail(name, phase_start.elapsed(), "decompress failed".to_string());
+                    return uploaded;
+                }
+            }
+
+            if let Some(local) = &local {
+                // the files are removed from the server, not from the relay forwarding them
+                let server_session = match &config.relay {
+                    Some(_) => {
+                        let server = &config.server;
+                        match phase_session(logger, server, name, phase_start, report).await {
+                            Some(session) => Some(session),
+                            None => return uploaded,
+                        }
+                    }
+                    None => None,
+                };
+                let delete_session = server_session.as_ref().unwrap_or(&session);
+                let target = expand_server_path(target_folder, &config.server.user);
+                let deleted = match stale_files(delete_session, config, &target, local).await {
+                    Ok(stale) if stale.is_empty() => Ok(stale),
+                    Ok(stale) => {
+                        let commands = delete_commands(config, &target, &stale);
+                        match send_each(&mut *logger, delete_session, "delete", &commands).await {
+                            Some(0) => Ok(stale),
+                            _ => Err("removing the stale files failed".to_string()),
+                        }
+                    }
+                    Err(e) => Err(e),
+                };
+                if let Some(server_session) = server_session {
+                    server_session
+                        .disconnect(Disconnect::ByApplication, "", "English")
+                        .await
+                        .expect("Failed to close ssh session");
+                }
+                match deleted {
+                    Ok(stale) => {
+                        for path in stale {
+                            log!(logger, "{} '{}'", "Deleted:".bright_black(), path);
+                        }
+                    }
+                    Err(e) => {
+                        log_error!(logger, "{} {}", THEME.error_label("Error:"), e);
+                        report.fail(name, phase_start.elapsed(), e);
+                        return uploaded;
+                    }
+                }
+            }
+
+            if let Some(relay) = &config.relay {
+                let commands = fan_out_commands(config, relay, target_folder);
+                let status = send_command(&mut *logger, &session, "fan_out", &commands).await;
+                if status != Some(0) {
+                    let error = match status {
+                        Some(status) => format!("fan out exit status {}", status),
+                        None => "fan out ended without an exit status".to_string(),
+                    };
+                    log_error!(
+                        logger,
+                        "{} fan out from relay failed: {}",
+                        THEME.error_label("Error:"),
+                        error
+                    );
+                    report.fail(name, phase_start.elapsed(), error);
+                    return uploaded;
+                }
+            }
+            session
+                .disconnect(Disconnect::ByApplication, "", "English")
+                .await
+                .expect("Failed to close ssh session");
+
+            if !chown.is_empty() {
+                let Some(session) =
+                    phase_session(logger, &config.server, name, phase_start, report).await
+                else {
+                    return uploaded;
+                };
+                let status = send_each(&mut *logger, &session, "chown", &chown).await;
+                session
+                    .disconnect(Disconnect::ByApplication, "", "English")
+                    .await
+                    .expect("Failed to close ssh session");
+                if status != Some(0) {
+                    log_error!(
+                        logger,
+                        "{} unable to change the owner of uploaded files",
+                        THEME.error_label("Error:")
+                    );
+                    let error = match status {
+                        Some(status) => format!("chown exit status {}", status),
+                        None => "chown ended without an exit status".to_string(),
+                    };
+                    report.fail(name, phase_start.elapsed(), error);
+                    return uploaded;
+                }
+            }
+            report.record(name, phase_start.elapsed(), None);
+            uploaded
+        }
+        Action::WaitForPort {
+            name,
+            host,
+            port,
+            timeout,
+            ..
+        } => {
+            let phase_start = report.start(name);
+            log!(
+                logger,
+                "{} {}:{} to accept connections",
+                "Waiting:".bright_black(),
+                host,
+                port
+            );
+            let session = phase_session(logger, &config.server, name, phase_start, report).await?;
+            let timeout = std::time::Duration::from_secs(*timeout);
+            let attempts = wait_for_port(&session, host, *port, timeout).await;
+            session
+                .disconnect(Disconnect::ByApplication, "", "English")
+                .await
+                .expect("Failed to close ssh session");
+            match attempts {
+                Some(attempts) => {
+                    log!(
+                        logger,
+                        "{} {}:{} is open after {} attempts",
+                        THEME.success_label("Ready:"),
+                        host,
+                        port,
+                        attempts
+                    );
+                    report.record(name, phase_start.elapsed(), None);
+                }
+                None => {
+                    log_error!(
+                        logger,
+                        "{} {}:{} still closed after {}s",
+                        THEME.error_label("Error:"),
+                        host,
+                        port,
+                        timeout.as_secs()
+                    );
+                    let error = format!("{}:{} not open after {}s", host, port, timeout.as_secs());
+                    report.fail(name, phase_start.elapsed(), error);
+                    return None;
+                }
+            }
+            None
+        }
+    }
+}
+
+// a session to the server for the phase, which fails when the server can't be reached
+async fn phase_session(
+    logger: &mut Logger,
+    server: &Server,
+    phase: &str,
+    phase_start: std::time::Instant,
+    report: &mut DeploymentReport,
+) -> Option<client::Handle<SimpleHandler>> {
+    match create_ssh_session(server).await {
+        Ok(session) => Some(session),
+        Err(e) => {
+            log_error!(logger, "{} {}", THEME.error_label("Error:"), e);
+            report.fail(phase, phase_start.elapsed(), e);
+            None
+        }
+    }
+}
+
+async fn open_sftp(session: &client::Handle<SimpleHandler>) -> Result<SftpSession, String> {
+    let channel = session
+        .channel_open_session()
+        .await
+        .map_err(|e| format!("unable to open an sftp channel: {}", e))?;
+    channel
+        .request_subsystem(true, "sftp")
+        .await
+        .map_err(|e| format!("unable to start sftp: {}", e))?;
+    SftpSession::new(channel.into_stream())
+        .await
+        .map_err(|e| format!("unable to start sftp: {}", e))
+}
+
+// runs each command on its own, so that long lists of paths never make one too long command
+// line, and stops at the first failure
+async fn send_each(
+    logger: &mut Logger,
+    session: &client::Handle<SimpleHandler>,
+    name: &str,
+    commands: &[String],
+) -> Option<u32> {
+    let mut status = Some(0);
+    for command in commands {
+        status = send_command(&mut *logger, session, name, std::slice::from_ref(command)).await;
+        if status != Some(0) {
+            break;
+        }
+    }
+    status
+}
+
+// runs the commands again after a non-zero exit status, as many times as retry allows
+async fn send_command_with_retries(
+    logger: &mut Logger,
+    session: &client::Handle<SimpleHandler>,
+    name: &str,
+    commands: &[String],
+    retry: Retry,
+    timeout: Option<std::time::Duration>,
+) -> Option<u32> {
+    let mut attempt = 0;
+    loop {
+        let status =
+            send_command_with_timeout(&mut *logger, session, name, commands, timeout).await;
+        match status {
+            // past the deployment deadline, a retry would be killed right away
+            Some(status) if status != 0 && attempt < retry.retries && !logger.timed_out() => {
+                attempt += 1;
+                let delay = retry.backoff(attempt);
+                log_warn!(
+                    logger,
+                    "{} '{}' failed with exit status {}, retrying in {}s ({}/{})",
+                    THEME.warning_label("Retrying:"),
+                    name,
+                    status,
+                    delay.as_secs_f64(),
+                    attempt,
+                    retry.retries
+                );
+                tokio::time::sleep(delay).await;
+            }
+            status => return status,
+        }
+    }
+}
+
+// the server folder an upload was deployed to, in the current release with the releases strategy
+fn deployed_target(config: &Config, target_folder: &str) -> String {
+    let target = match (config.strategy, &config.base_path) {
+        (Strategy::Releases, Some(base_path)) => {
+            let current = format!("{}/current", base_path.trim_end_matches('/'));
+            release_target(&current, target_folder)
+        }
+        _ => target_folder.to_owned(),
+    };
+    expand_server_path(&target, &config.server.user)
+}
+
+// the sha256 of every file under target, by their path relative to it
+async fn remote_hashes(
+    session: &client::Handle<SimpleHandler>,
+    config: &Config,
+    target: &str,
+) -> Result<BTreeMap<String, String>, String> {
+    let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+    // run by sh whatever the login shell, a target that doesn't exist yet has no file
+    let script = "[ -d \"$1\" ] || exit 0; cd \"$1\" || exit; \
+                  if command -v sha256sum >/dev/null 2>&1; \
+                  then find . -type f -exec sha256sum {} +; \
+                  else find . -type f -exec shasum -a 256 {} +; fi";
+    let hash = format!("sh -c {} sh {}", quote(shell, script), quote(shell, target));
+    let (status, listing) = read_command(session, &hash).await;
+    if status != Some(0) {
+        return Err(format!("unable to hash the files of '{}'", target));
+    }
+    Ok(listing
+        .lines()
+        .filter_map(|line| line.split_once("  "))
+        .filter_map(|(hash, path)| Some((path.strip_prefix("./")?.to_string(), hash.to_string())))
+        .collect())
+}
+
+// checks that the files the lock records for each upload are on the server with the same sha256,
+// returns how many aren't
+pub async fn verify(config: &Config, lock: &Lock, skip: &HashSet<String>) -> Result<usize, String> {
+    let session = create_ssh_session(&config.server).await?;
+    let mut result = Ok(0);
+    for action in &config.actions {
+        let Action::Upload {
+            name,
+            target_folder,
+            ..
+        } = action
+        else {
+            continue;
+        };
+        if skip.contains(name) {
+            continue;
+        }
+        let target = deployed_target(config, target_folder);
+        println!(
+            "{} {} -> {}:{}",
+            "Verifying:".bright_black(),
+            name,
+            config.server.host,
+            target
+        );
+        let Some(locked) = lock.get(name) else {
+            println!(
+                "  {}",
+                "no upload recorded in the lock file".color(THEME.muted)
+            );
+            continue;
+        };
+        let remote = match remote_hashes(&session, config, &target).await {
+            Ok(remote) => remote,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        };
+        let mut mismatches = 0;
+        for (path, hash) in locked {
+            let (symbol, color, problem) = match remote.get(path) {
+                Some(remote_hash) if remote_hash == hash => continue,
+                Some(_) => ("~", THEME.warning, "modified"),
+                None => ("-", THEME.error, "missing"),
+            };
+            mismatches += 1;
+            println!("  {} '{}' {}", symbol.color(color), path, problem);
+        }
+        println!(
+            "  {} of {} files match",
+            locked.len() - mismatches,
+            locked.len()
+        );
+        result = result.map(|total| total + mismatches);
+    }
+    session
+        .disconnect(Disconnect::ByApplication, "", "English")
+        .await
+        .expect("Failed to close ssh session");
+    result
+}
+
+// what --clean removes: base_path with the releases strategy, the target folder of each upload
+// otherwise
+pub fn clean_paths(config: &Config) -> Vec<String> {
+    let user = &config.server.user;
+    match (config.strategy, &config.base_path) {
+        (Strategy::Releases, Some(base_path)) => vec![expand_server_path(base_path, user)],
+        _ => config
+            .actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::Upload { target_folder, .. } => {
+                    Some(expand_server_path(target_folder, user))
+                }
+                _ => None,
+            })
+            .collect(),
+    }
+}
+
+// removes the clean_paths from the server and forgets what the upload cache knows of them
+pub async fn clean(logger: &mut Logger, config: &Config, report: &mut DeploymentReport) {
+    let start_time = report.start("clean");
+    let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+    let paths = clean_paths(config);
+    if paths.is_empty() {
+        log!(logger, "{} no folder to remove", "Clean:".bright_black());
+        report.record("clean", start_time.elapsed(), None);
+        return;
+    }
+
+    let Some(session) = phase_session(logger, &config.server, "clean", start_time, report).await
+    else {
+        return;
+    };
+    // the real home, root's isn't in /home, along with the one `~/` paths are expanded to
+    let (_, home) = read_command(&session, "echo $HOME").await;
+    let homes = [
+        home.trim().to_string(),
+        format!("/home/{}", config.server.user),
+    ];
+    let unsafe_path = paths.iter().find(|path| {
+        homes
+            .iter()
+            .any(|home| !home.is_empty() && unsafe_to_remove(path, home))
+    });
+    if let Some(path) = unsafe_path {
+        log_error!(
+            logger,
+            "{} refusing to remove '{}'",
+            THEME.error_label("Error:"),
+            path
+        );
+        session
+            .disconnect(Disconnect::ByApplication, "", "English")
+            .await
+            .expect("Failed to close ssh session");
+        let error = format!("refused to remove '{}'", path);
+        report.fail("clean", start_time.elapsed(), error);
+        return;
+    }
+
+    let quoted: Vec<String> = paths.iter().map(|path| quote(shell, path)).collect();
+    let remove = format!("rm -rf -- {}", quoted.join(" "));
+    let status = send_command(&mut *logger, &session, "clean", &[remove]).await;
+    session
+        .disconnect(Disconnect::ByApplication, "", "English")
+        .await
+        .expect("Failed to close ssh session");
+    if status != Some(0) {
+        log_error!(
+            logger,
+            "{} unable to remove the deployment",
+            THEME.error_label("Error:")
+        );
+        report.fail("clean", start_time.elapsed(), "removal failed".to_string());
+        return;
+    }
+    for path in &paths {
+        log!(logger, "{} '{}'", "Removed:".bright_black(), path);
+        if let Err(e) = cache::forget(&cache::key(&config.server, path)) {
+            eprintln!(
+                "Unable to write upload cache: {}",
+                e.to_string().color(THEME.error)
+            );
+        }
+    }
+    report.record("clean", start_time.elapsed(), None);
+}
+
+// whether removing path would take more than a deployment with it: the home folder, a top level
+// folder like /var or /root, or anything going up with `..`; relative paths are in home
+fn unsafe_to_remove(path: &str, home: &str) -> bool {
+    let path = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", home, rest),
+        _ => path.to_string(),
+    };
+    let path = Path::new(home).join(path);
+    let mut depth = 0;
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::ParentDir => return true,
+            _ => {}
+        }
+    }
+    depth < 2 || path == Path::new(home)
+}
+
+// opens an interactive shell on the server with the ssh client, in the current release or the
+// target folder of the first upload; returns its exit status
+pub fn open_shell(config: &Config) -> Result<i32, String> {
+    let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+    let server = &config.server;
+    let folder = match (config.strategy, &config.base_path) {
+        (Strategy::Releases, Some(base_path)) => Some(format!(
+            "{}/current",
+            expand_server_path(base_path, &server.user).trim_end_matches('/')
+        )),
+        _ => config.actions.iter().find_map(|action| match action {
+            Action::Upload { target_folder, .. } => Some(deployed_target(config, target_folder)),
+            _ => None,
+        }),
+    };
+    // the home folder when it doesn't exist yet
+    let remote_command = match folder {
+        Some(folder) => format!(
+            "cd {} 2>/dev/null; exec \"$SHELL\" -l",
+            quote(shell, &folder)
+        ),
+        None => "exec \"$SHELL\" -l".to_string(),
+    };
+    let mut ssh = std::process::Command::new("ssh");
+    ssh.arg("-t")
+        .arg("-i")
+        .arg(expand_user_path(&server.ssh_key))
+        .arg("-p")
+        .arg(server.port.to_string());
+    if !server.jump_host.is_empty() {
+        ssh.arg("-J").arg(server.jump_host.join(","));
+    }
+    let status = ssh
+        .arg(format!("{}@{}", server.user, server.host))
+        .arg(remote_command)
+        .status()
+        .map_err(|e| format!("unable to start ssh: {}", e))?;
+    Ok(status.code().unwrap_or(1))
+}
+
+// runs command on the server, in the remote console like the actions
+pub async fn run_command(
+    logger: &mut Logger,
+    config: &Config,
+    command: &str,
+    report: &mut DeploymentReport,
+) {
+    let start_time = report.start("run");
+    let Some(session) = phase_session(logger, &config.server, "run", start_time, report).await
+    else {
+        return;
+    };
+    let status = send_command(&mut *logger, &session, "run", &[command.to_owned()]).await;
+    session
+        .disconnect(Disconnect::ByApplication, "", "English")
+        .await
+        .expect("Failed to close ssh session");
+    match status {
+        Some(0) => report.record_command("run", start_time.elapsed(), status, None),
+        Some(status) => {
+            log_error!(
+                logger,
+                "{} '{}' failed with exit status {}",
+                THEME.error_label("Error:"),
+                command,
+                status
+            );
+            let error = format!("exit status {}", status);
+            report.fail_command("run", start_time.elapsed(), status, error);
+        }
+        None => {
+            let error = "ended without an exit status".to_string();
+            report.fail("run", start_time.elapsed(), error);
+        }
+    }
+}
+
+// follows the remote_log in the remote console until it's left with ESC or Ctrl+C
+pub async fn follow_logs(logger: &mut Logger, config: &Config, report: &mut DeploymentReport) {
+    let start_time = report.start("logs");
+    let Some(remote_log) = &config.remote_log else {
+        log_error!(
+            logger,
+            "{} --logs requires remote_log in the config",
+            THEME.error_label("Error:")
+        );
+        report.fail("logs", start_time.elapsed(), "no remote_log".to_string());
+        return;
+    };
+    let command = if remote_log.starts_with('/') || remote_log.starts_with("~/") {
+        let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+        let path = expand_server_path(remote_log, &config.server.user);
+        format!("tail -n 100 -F {}", quote(shell, &path))
+    } else {
+        remote_log.to_owned()
+    };
+    let Some(session) = phase_session(logger, &config.server, "logs", start_time, report).await
+    else {
+        return;
+    };
+    let status = send_command(&mut *logger, &session, "logs", &[command]).await;
+    session
+        .disconnect(Disconnect::ByApplication, "", "English")
+        .await
+        .expect("Failed to close ssh session");
+    // 130 is the exit status of a command interrupted by Ctrl+C
+    match status {
+        None | Some(0) | Some(130) => report.record("logs", start_time.elapsed(), None),
+        Some(status) => {
+            log_error!(
+                logger,
+                "{} '{}' failed with exit status {}",
+                THEME.error_label("Error:"),
+                remote_log,
+                status
+            );
+            let error = format!("exit status {}", status);
+            report.fail_command("logs", start_time.elapsed(), status, error);
+        }
+    }
+}
+
+// downloads remote, or the target folder of each upload into a folder of into named after it,
+// leaving out what the .deployignore of the local folder, or of the upload's source folder, matches
+pub async fn pull(
+    logger: &mut Logger,
+    config: &Config,
+    remote: Option<&str>,
+    into: &str,
+    skip: &HashSet<String>,
+    report: &mut DeploymentReport,
+) {
+    let start_time = report.start("pull");
+    let into = into.trim_end_matches('/');
+    // remote folder, local folder and the folder of the .deployignore
+    let folders: Vec<(String, String, String)> = match remote {
+        Some(remote) => vec![(remote.to_string(), into.to_string(), into.to_string())],
+        None => config
+            .actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::Upload {
+                    name,
+                    source_folder,
+                    target_folder,
+                    ..
+                } if !skip.contains(name) => Some((
+                    deployed_target(config, target_folder),
+                    format!("{}/{}", into, name),
+                    expand_user_path(source_folder),
+                )),
+                _ => None,
+            })
+            .collect(),
+    };
+
+    let Some(session) = phase_session(logger, &config.server, "pull", start_time, report).await
+    else {
+        return;
+    };
+    let sftp = match open_sftp(&session).await {
+        Ok(sftp) => sftp,
+        Err(e) => {
+            log_error!(logger, "{} {}", THEME.error_label("Error:"), e);
+            report.fail("pull", start_time.elapsed(), e);
+            return;
+        }
+    };
+    let mut failure = None;
+    for (remote_folder, local_folder, ignore_folder) in &folders {
+        let result = download(
+            &config.server,
+            &mut *logger,
+            &sftp,
+            remote_folder,
+            local_folder,
+            ignore_folder,
+        )
+        .await;
+        match result {
+            Ok((downloaded, total)) if downloaded == total => {}
+            Ok((downloaded, total)) => {
+                failure = Some(format!(
+                    "{} of {} files of '{}' failed",
+                    total - downloaded,
+                    total,
+                    remote_folder
+                ));
+            }
+            Err(e) => {
+                log_error!(logger, "{} {}", THEME.error_label("Error:"), e);
+                failure = Some(e);
+            }
+        }
+    }
+    sftp.close().await.expect("Failed to close sftp session");
+    session
+        .disconnect(Disconnect::ByApplication, "", "English")
+        .await
+        .expect("Failed to close ssh session");
+    match failure {
+        None => report.record("pull", start_time.elapsed(), None),
+        Some(error) => report.fail("pull", start_time.elapsed(), error),
+    }
+}
+
+// compares the files every upload would send with those of its target on the server, by their
+// sha256, without changing anything
+pub async fn diff(config: &Config, skip: &HashSet<String>) -> Result<(), String> {
+    let session = create_ssh_session(&config.server).await?;
+    let mut result = Ok(());
+    for action in &config.actions {
+        let Action::Upload {
+            name,
+            source_folder,
+            target_folder,
+            skip_empty_files,
+            skip_first_line,
+            use_gitignore,
+            ..
+        } = action
+        else {
+            continue;
+        };
+        if skip.contains(name) {
+            continue;
+        }
+        let filter = match ContentFilter::new(*skip_empty_files, skip_first_line.as_deref()) {
+            Ok(filter) => filter,
+            Err(e) => {
+                result = Err(format!("{}: {}", name, e));
+                break;
+            }
+        };
+        let source_folder = expand_user_path(source_folder);
+        let mut local = BTreeMap::new();
+        for (path, reason) in classify_files(&source_folder, &filter, *use_gitignore, false) {
+            if !matches!(reason, Reason::Upload) {
+                continue;
+            }
+            let relative_path = path.strip_prefix(&source_folder).unwrap();
+            let relative_path = relative_path.to_string_lossy().to_string();
+            match hash_file(&path) {
+                Ok(hash) => local.insert(relative_path, hash),
+                Err(e) => {
+                    result = Err(format!("unable to read '{}': {}", path.display(), e));
+                    break;
+                }
+            };
+        }
+        if result.is_err() {
+            break;
+        }
+
+        let target = deployed_target(config, target_folder);
+        let remote = match remote_hashes(&session, config, &target).await {
+            Ok(remote) => remote,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        };
+
+        println!(
+            "{} {} -> {}:{}",
+            "Diff:".bright_black(),
+            name,
+            config.server.host,
+            target
+        );
+        let mut counts = [0; 3];
+        let paths: BTreeSet<&str> = local
+            .keys()
+            .chain(remote.keys())
+            .map(String::as_str)
+            .collect();
+        for path in paths {
+            let (symbol, color, count) = match (local.get(path), remote.get(path)) {
+                (Some(_), None) => ("+", THEME.success, &mut counts[0]),
+                (Some(hash), Some(remote_hash)) if hash != remote_hash => {
+                    ("~", THEME.warning, &mut counts[1])
+                }
+                (None, Some(_)) => ("-", THEME.error, &mut counts[2]),
+                _ => continue,
+            };
+            *count += 1;
+            println!("  {} '{}'", symbol.color(color), path);
+        }
+        println!(
+            "  {} only local, {} modified, {} only on the server",
+            counts[0], counts[1], counts[2]
+        );
+    }
+    session
+        .disconnect(Disconnect::ByApplication, "", "English")
+        .await
+        .expect("Failed to close ssh session");
+    result
+}
+
+// prints the releases on the server, newest first, with when they were made, their size, the
+// commit they were deployed from and which one is current
+pub async fn list_releases(config: &Config) -> Result<(), String> {
+    let base_path = match (config.strategy, &config.base_path) {
+        (Strategy::Releases, Some(base_path)) => expand_server_path(base_path, &config.server.user),
+        _ => return Err("--releases requires strategy = \"releases\"".to_string()),
+    };
+    let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+    let releases_path = format!("{}/releases", base_path.trim_end_matches('/'));
+    let current_path = format!("{}/current", base_path.trim_end_matches('/'));
+    // run by sh whatever the login shell, a tab separated line per release with its size in KB
+    let script = format!(
+        "cd \"$1\" || exit; for release in */; do [ -d \"$release\" ] || continue; \
+         release=${{release%/}}; printf '%s\\t%s\\t%s\\n' \"$release\" \
+         \"$(du -sk \"$release\" | cut -f1)\" \"$(cat \"$release/{}\" 2>/dev/null)\"; done",
+        REVISION_FILE
+    );
+    let list = format!(
+        "sh -c {} sh {}",
+        quote(shell, &script),
+        quote(shell, &releases_path)
+    );
+
+    let session = create_ssh_session(&config.server).await?;
+    let (status, listing) = read_command(&session, &list).await;
+    let (_, current) = read_command(
+        &session,
+        &format!("readlink {}", quote(shell, &current_path)),
+    )
+    .await;
+    session
+        .disconnect(Disconnect::ByApplication, "", "English")
+        .await
+        .expect("Failed to close ssh session");
+    if status != Some(0) {
+        return Err(format!("unable to list '{}'", releases_path));
+    }
+    let current = current
+        .trim()
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+
+    let mut releases: Vec<Vec<&str>> = listing
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.split('\t').collect())
+        .collect();
+    releases.sort_by(|a, b| b[0].cmp(a[0]));
+    println!(
+        "{} {} on {}",
+        "Releases:".bright_black(),
+        releases_path,
+        config.server.host
+    );
+    if releases.is_empty() {
+        println!("  {}", "no release yet".color(THEME.muted));
+    }
+    for release in releases {
+        let name = release[0];
+        // named after the UTC time of the deployment
+        let time = chrono::NaiveDateTime::parse_from_str(name, "%Y%m%d%H%M%S")
+            .map(|time| time.and_utc().with_timezone(&chrono::Local))
+            .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        let size = release.get(1).and_then(|kb| kb.parse::<u64>().ok());
+        let size = size.map(|kb| format_bytes(kb * 1024)).unwrap_or_default();
+        let revision = release.get(2).copied().unwrap_or_default();
+        let revision = match revision.get(..7) {
+            Some(short) => short,
+            None if revision.is_empty() => "-",
+            None => revision,
+        };
+        let line = format!("  {}  {:19}  {:>8}  {}", name, time, size, revision);
+        if name == current {
+            println!("{} {}", line.bold(), "(current)".color(THEME.success));
+        } else {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+// the folders under base_path/releases, oldest first, the ones made current once, and the one
+// current points to
+struct Releases {
+    all: Vec<String>,
+    released: BTreeSet<String>,
+    current: String,
+}
+
+impl Releases {
+    // the releases pruning removes: those made current once beyond the keep newest, and those older
+    // than current a failed deployment left, never current itself
+    fn to_prune(&self, keep: usize) -> Vec<&str> {
+        let kept: Vec<&String> = self.released.iter().rev().take(keep).collect();
+        self.all
+            .iter()
+            .filter(|release| **release != self.current && !kept.contains(release))
+            .filter(|release| self.released.contains(*release) || **release < self.current)
+            .map(String::as_str)
+            .collect()
+    }
+
+    // the last release made current before the current one, never one a failed deployment left
+    fn previous(&self) -> Option<&str> {
+        let mut newest_first = self.released.iter().rev();
+        let previous = newest_first.find(|release| **release < self.current);
+        previous.map(String::as_str)
+    }
+
+    // the release a rollback points current to: `to` if there is one, or else the previous one
+    fn target<'a>(&'a self, to: Option<&'a str>) -> Result<&'a str, String> {
+        match to {
+            Some(to) if self.all.iter().any(|release| release == to) => Ok(to),
+            Some(to) => Err(format!("no release '{}'", to)),
+            None => self
+                .previous()
+                .ok_or_else(|| format!("no release before '{}'", self.current)),
+        }
+    }
+}
+
+// the status is the one of listing the releases folder
+async fn read_releases(
+    session: &client::Handle<SimpleHandler>,
+    shell: RemoteShell,
+    base_path: &str,
+) -> (Option<u32>, Releases) {
+    let releases_path = format!("{}/releases", base_path.trim_end_matches('/'));
+    let current_path = format!("{}/current", base_path.trim_end_matches('/'));
+    let (status, listing) =
+        read_command(session, &format!("ls -1 {}", quote(shell, &releases_path))).await;
+    let (_, markers) = read_command(session, &released_command(shell, &releases_path)).await;
+    let (_, current) = read_command(session, &readlink_command(shell, &current_path)).await;
+    let mut all: Vec<String> = listing
+        .lines()
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .map(str::to_string)
+        .collect();
+    all.sort();
+    let released = markers
+        .lines()
+        .filter_map(|marker| Path::new(marker.trim()).parent()?.file_name())
+        .map(|release| release.to_string_lossy().to_string())
+        .collect();
+    let current = release_name(&current).to_string();
+    let releases = Releases {
+        all,
+        released,
+        current,
+    };
+    (status, releases)
+}
+
+// points current back to the release before it, or to `to`, then runs on_rollback; true once
+// both passed
+pub async fn rollback(
+    logger: &mut Logger,
+    config: &Config,
+    to: Option<&str>,
+    report: &mut DeploymentReport,
+) -> bool {
+    let start_time = report.start("rollback");
+    let base_path = match (config.strategy, &config.base_path) {
+        (Strategy::Releases, Some(base_path)) => expand_server_path(base_path, &config.server.user),
+        _ => {
+            log_error!(
+                logger,
+                "{} rollback requires strategy = \"releases\"",
+                THEME.error_label("Error:")
+            );
+            let error = "not using the releases strategy".to_string();
+            report.fail("rollback", start_time.elapsed(), error);
+            return false;
+        }
+    };
+    let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+    let releases_path = format!("{}/releases", base_path.trim_end_matches('/'));
+
+    let Some(session) = phase_session(logger, &config.server, "rollback", start_time, report).await
+    else {
+        return false;
+    };
+    let (status, releases) = read_releases(&session, shell, &base_path).await;
+    let current = releases.current.as_str();
+
+    let target = match releases.target(to) {
+        Ok(target) => Some(target),
+        Err(e) => {
+            log_error!(
+                logger,
+                "{} {} in '{}'",
+                THEME.error_label("Error:"),
+                e,
+                releases_path
+            );
+            None
+        }
+    };
+    let Some(target) = target.filter(|_| status == Some(0)) else {
+        session
+            .disconnect(Disconnect::ByApplication, "", "English")
+            .await
+            .expect("Failed to close ssh session");
+        report.fail(
+            "rollback",
+            start_time.elapsed(),
+            "no release to roll back to".to_string(),
+        );
+        return false;
+    };
+
+    let release = format!("{}/{}", releases_path, target);
+    let switch = switch_command(shell, &base_path, &release);
+    let mut status = send_command(&mut *logger, &session, "rollback", &[switch]).await;
+    if status == Some(0) {
+        log!(
+            logger,
+            "{} '{}' -> '{}'",
+            "Rolled back:".bright_black(),
+            current,
+            target
+        );
+        if !config.on_rollback.is_empty() {
+            status = send_command(&mut *logger, &session, "on_rollback", &config.on_rollback).await;
+        }
+    }
+    session
+        .disconnect(Disconnect::ByApplication, "", "English")
+        .await
+        .expect("Failed to close ssh session");
+    match status {
+        Some(0) => {
+            report.record("rollback", start_time.elapsed(), None);
+            true
+        }
+        _ => {
+            log_error!(
+                logger,
+                "{} rollback to '{}' failed",
+                THEME.error_label("Error:"),
+                target
+            );
+            let error = format!("rollback to '{}' failed", target);
+            report.fail("rollback", start_time.elapsed(), error);
+            false
+        }
+    }
+}
+
+// puts back the targets the uploads with backup = true of the config copied in the latest backup,
+// or the one named, before overwriting them
+pub async fn restore_backup(
+    logger: &mut Logger,
+    config: &Config,
+    backup: Option<&str>,
+    report: &mut DeploymentReport,
+) {
+    let start_time = report.start("restore_backup");
+    let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+    let backups_path = expand_server_path(&backups_folder(&config.backup_key), &config.server.user);
+
+    let Some(session) =
+        phase_session(logger, &config.server, "restore_backup", start_time, report).await
+    else {
+        return;
+    };
+    let (status, listing) =
+        read_command(&session, &format!("ls -1 {}", quote(shell, &backups_path))).await;
+    let backups: BTreeSet<&str> = listing
+        .lines()
+        .map(str::trim)
+        .filter(|b| !b.is_empty())
+        .collect();
+    let target = match backup {
+        Some(backup) if backups.contains(backup) => Some(backup),
+        Some(backup) => {
+            log_error!(
+                logger,
+                "{} no backup '{}' in '{}'",
+                THEME.error_label("Error:"),
+                backup,
+                backups_path
+            );
+            None
+        }
+        None => {
+            let latest = backups.last().copied();
+            if latest.is_none() {
+                log_error!(
+                    logger,
+                    "{} no backup in '{}'",
+                    THEME.error_label("Error:"),
+                    backups_path
+                );
+            }
+            latest
+        }
+    };
+    let Some(target) = target.filter(|_| status == Some(0)) else {
+        session
+            .disconnect(Disconnect::ByApplication, "", "English")
+            .await
+            .expect("Failed to close ssh session");
+        report.fail(
+            "restore_backup",
+            start_time.elapsed(),
+            "no backup to restore".to_string(),
+        );
+        return;
+    };
+
+    let backup_path = format!("{}/{}", backups_path, target);
+    let (_, listing) =
+        read_command(&session, &format!("ls -1 {}", quote(shell, &backup_path))).await;
+    // the uploads removed from the config since the backup stay as they are
+    let backed_up: Vec<&str> = config
+        .actions
+        .iter()
+        .filter(|action| matches!(action, Action::Upload { backup: true, .. }))
+        .map(Action::name)
+        .collect();
+    let commands: Vec<String> = listing
+        .lines()
+        .map(str::trim)
+        .filter(|action| backed_up.contains(action))
+        .map(|action| restore_command(shell, &format!("{}/{}", backup_path, action)))
+        .collect();
+    let status = if commands.is_empty() {
+        Some(0)
+    } else {
+        send_command(&mut *logger, &session, "restore_backup", &commands).await
+    };
+    session
+        .disconnect(Disconnect::ByApplication, "", "English")
+        .await
+        .expect("Failed to close ssh session");
+    match status {
+        Some(0) => {
+            log!(
+                logger,
+                "{} {} target(s) from '{}'",
+                "Restored:".bright_black(),
+                commands.len(),
+                target
+            );
+            // the restored files aren't those the cache knows as uploaded anymore
+            for action in &config.actions {
+                let Action::Upload {
+                    target_folder,
+                    backup: true,
+                    ..
+                } = action
+                else {
+                    continue;
+                };
+                let target = expand_server_path(target_folder, &config.server.user);
+                if let Err(e) = cache::forget(&cache::key(&config.server, &target)) {
+                    eprintln!(
+                        "Unable to write upload cache: {}",
+                        e.to_string().color(THEME.error)
+                    );
+                }
+            }
+            report.record("restore_backup", start_time.elapsed(), None);
+        }
+        _ => {
+            log_error!(
+                logger,
+                "{} restoring backup '{}' failed",
+                THEME.error_label("Error:"),
+                target
+            );
+            let error = format!("restoring backup '{}' failed", target);
+            report.fail("restore_backup", start_time.elapsed(), error);
+        }
+    }
+}
+
+// records the preflight by its exit status: 0 proceeds, 2 skips the deployment and any other
+// fails it; false when the deployment stops there
+async fn preflight_passed(
+    logger: &mut Logger,
+    status: Option<u32>,
+    phase_start: std::time::Instant,
+    report: &mut DeploymentReport,
+) -> bool {
+    match status {
+        Some(0) => {
+            log!(logger, "{} passed", "Preflight:".bright_black());
+            report.record_command("preflight", phase_start.elapsed(), Some(0), None);
+            true
+        }
+        Some(2) => {
+            log_warn!(
+                logger,
+                "{} vetoed, skipping deployment",
+                THEME.warning_label("Preflight:")
+            );
+            report.record_command("preflight", phase_start.elapsed(), Some(2), None);
+            report.status = Status::Skipped;
+            false
+        }
+        Some(status) => {
+            log_error!(
+                logger,
+                "{} failed with exit status {}",
+                THEME.error_label("Preflight:"),
+                status
+            );
+            let error = format!("exit status {}", status);
+            report.fail_command("preflight", phase_start.elapsed(), status, error);
+            false
+        }
+        None => {
+            log_error!(
+                logger,
+                "{} ended without an exit status",
+                THEME.error_label("Preflight:")
+            );
+            let error = "ended without an exit status".to_string();
+            report.fail("preflight", phase_start.elapsed(), error);
+            false
+        }
+    }
+}
+
+// links the linked_files and linked_dirs of a new release to base_path/shared, where they're kept
+// from one release to the next; false when it failed
+async fn link_shared(
+    logger: &mut Logger,
+    config: &Config,
+    release: &str,
+    report: &mut DeploymentReport,
+) -> bool {
+    let links = shared_links(config, release);
+    if links.is_empty() {
+        return true;
+    }
+    let phase_start = report.start("link_shared");
+    let Some(session) =
+        phase_session(logger, &config.server, "link_shared", phase_start, report).await
+    else {
+        return false;
+    };
+    let status = send_command(&mut *logger, &session, "link_shared", &links).await;
+    session
+        .disconnect(Disconnect::ByApplication, "", "English")
+        .await
+        .expect("Failed to close ssh session");
+    if status == Some(0) {
+        let count = config.linked_files.len() + config.linked_dirs.len();
+        log!(
+            logger,
+            "{} {} shared path(s)",
+            "Linked:".bright_black(),
+            count
+        );
+        report.record("link_shared", phase_start.elapsed(), None);
+        return true;
+    }
+    log_error!(
+        logger,
+        "{} unable to link the shared paths, linked_files must exist in '{}/shared'",
+        THEME.error_label("Error:"),
+        config.base_path.as_deref().unwrap_or_default()
+    );
+    let error = "linking the shared paths failed".to_string();
+    report.fail("link_shared", phase_start.elapsed(), error);
+    false
+}
+
+// shared folders are created when missing, shared files are put there by hand, like an .env
+fn shared_links(config: &Config, release: &str) -> Vec<String> {
+    let user = &config.server.user;
+    let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+    let base_path = config.base_path.as_deref().unwrap_or_default();
+    let shared = format!(
+        "{}/shared",
+        expand_server_path(base_path, user).trim_end_matches('/')
+    );
+    let release = expand_server_path(release, user);
+    let release = release.trim_end_matches('/');
+    let mut commands = Vec::new();
+    let dirs = config.linked_dirs.iter().map(|dir| (dir, true));
+    let files = config.linked_files.iter().map(|file| (file, false));
+    for (path, is_dir) in dirs.chain(files) {
+        let path = path.trim_start_matches("./").trim_matches('/');
+        let target = format!("{}/{}", shared, path);
+        let link = format!("{}/{}", release, path);
+        let parent = Path::new(&link).parent().unwrap_or(Path::new(release));
+        let parent = parent.to_string_lossy();
+        if is_dir {
+            commands.push(format!("mkdir -p {}", quote(shell, &target)));
+        } else {
+            commands.push(format!("test -e {}", quote(shell, &target)));
+        }
+        commands.push(format!(
+            "mkdir -p {} && rm -rf {} && ln -s {} {}",
+            quote(shell, &parent),
+            quote(shell, &link),
+            quote(shell, &target),
+            quote(shell, &link)
+        ));
+    }
+    commands
+}
+
+// removes the oldest releases beyond keep, never the current one; as the deployment already
+// succeeded, a failure is only a warning
+async fn prune_releases(
+    logger: &mut Logger,
+    config: &Config,
+    keep: usize,
+    report: &mut DeploymentReport,
+) {
+    let Some(base_path) = &config.base_path else {
+        return;
+    };
+    let phase_start = report.start("prune_releases");
+    let base_path = expand_server_path(base_path, &config.server.user);
+    let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+    let releases_path = format!("{}/releases", base_path.trim_end_matches('/'));
+
+    let session = match create_ssh_session(&config.server).await {
+        Ok(session) => session,
+        Err(e) => {
+            log_warn!(logger, "{} {}", THEME.warning_label("Warning:"), e);
+            report.record("prune_releases", phase_start.elapsed(), None);
+            return;
+        }
+    };
+    let (mut status, releases) = read_releases(&session, shell, &base_path).await;
+    let old = releases.to_prune(keep);
+    if status == Some(0) && !old.is_empty() {
+        let paths: Vec<String> = old
+            .iter()
+            .map(|release| quote(shell, &format!("{}/{}", releases_path, release)))
+            .collect();
+        let remove = format!("rm -rf {}", paths.join(" "));
+        status = send_command(&mut *logger, &session, "prune_releases", &[remove]).await;
+    }
+    session
+        .disconnect(Disconnect::ByApplication, "", "English")
+        .await
+        .expect("Failed to close ssh session");
+    if status == Some(0) {
+        if !old.is_empty() {
+            log!(
+                logger,
+                "{} {} old release(s), keeping {}",
+                "Removed:".bright_black(),
+                old.len(),
+                keep
+            );
+        }
+    } else {
+        log_warn!(
+            logger,
+            "{} unable to remove the old releases of '{}'",
+            THEME.warning_label("Warning:"),
+            releases_path
+        );
+    }
+    report.record("prune_releases", phase_start.elapsed(), None);
+}
+
+// runs the commands of a hook, locally for *_local ones, and records its phase; false when it failed
+async fn run_hook(
+    logger: &mut Logger,
+    config: &Config,
+    hook: &str,
+    skip: &HashSet<String>,
+    report: &mut DeploymentReport,
+) -> bool {
+    let commands = hook_commands(config, hook);
+    if commands.is_empty() || skip.contains(hook) {
+        return true;
+    }
+    let phase_start = report.start(hook);
+    let status = if hook.ends_with("_local") {
+        run_local(&mut *logger, commands)
+            .await
+            .map(|status| status as u32)
+    } else {
+        let Some(session) = phase_session(logger, &config.server, hook, phase_start, report).await
+        else {
+            return false;
+        };
+        let status = send_command(&mut *logger, &session, hook, commands).await;
+        session
+            .disconnect(Disconnect::ByApplication, "", "English")
+            .await
+            .expect("Failed to close ssh session");
+        status
+    };
+    match status {
+        Some(0) => {
+            report.record_command(hook, phase_start.elapsed(), Some(0), None);
+            true
+        }
+        Some(status) => {
+            log_error!(
+                logger,
+                "{} {} failed with exit status {}",
+                THEME.error_label("Error:"),
+                hook,
+                status
+            );
+            let error = format!("exit status {}", status);
+            report.fail_command(hook, phase_start.elapsed(), status, error);
+            false
+        }
+        None => {
+            log_error!(
+                logger,
+                "{} {} ended without an exit status",
+                THEME.error_label("Error:"),
+                hook
+            );
+            let error = "ended without an exit status".to_string();
+            report.fail(hook, phase_start.elapsed(), error);
+            false
+        }
+    }
+}
+
+// polls the healthcheck of the config, if any, and records its phase; false when it never passed
+async fn check_health(
+    logger: &mut Logger,
+    config: &Config,
+    skip: &HashSet<String>,
+    report: &mut DeploymentReport,
+) -> bool {
+    let Some(healthcheck) = &config.healthcheck else {
+        return true;
+    };
+    if skip.contains("healthcheck") {
+        return true;
+    }
+    let phase_start = report.start("healthcheck");
+    let target = describe_health(healthcheck, &config.server.host);
+    log!(logger, "{} {}", "Checking:".bright_black(), target);
+    match wait_healthy(healthcheck, &config.server.host, config.min_tls).await {
+        Ok(attempts) => {
+            log!(
+                logger,
+                "{} {} after {} attempt(s)",
+                "Healthy:".bright_black(),
+                target,
+                attempts
+            );
+            report.record("healthcheck", phase_start.elapsed(), None);
+            true
+        }
+        Err(e) => {
+            log_error!(
+                logger,
+                "{} {} never became healthy: {}",
+                THEME.error_label("Unhealthy:"),
+                target,
+                e
+            );
+            let error = format!("unhealthy: {}", e);
+            report.fail("healthcheck", phase_start.elapsed(), error);
+            false
+        }
+    }
+}
+
+fn hook_commands<'a>(config: &'a Config, hook: &str) -> &'a Vec<String> {
+    match hook {
+        "pre_deploy_local" => &config.pre_deploy_local,
+        "pre_deploy_remote" => &config.pre_deploy_remote,
+        "post_deploy_remote" => &config.post_deploy_remote,
+        _ => &config.post_deploy_local,
+    }
+}
+
+// the conditions of an action as the dry run prints them, which doesn't run shell ones
+fn describe_conditions(conditions: &Conditions) -> Vec<String> {
+    let mut described = Vec::new();
+    if let Some(env) = &conditions.only_if_env {
+        described.push(format!("with --env {}", env));
+    }
+    if let Some(condition) = &conditions.only_if {
+        described.push(format!("if {}", condition));
+    }
+    if let Some(condition) = &conditions.unless {
+        described.push(format!("unless {}", condition));
+    }
+    described
+}
+
+// logs what execute_actions would send to the server once the files are resolved
+// with delete, the unchanged files of each upload, which --delete keeps on the server
+// the commands of an action --dry-run prints, and the check commands it runs in their place, both
+// after the exports of env
+fn dry_run_commands(
+    shell: RemoteShell,
+    commands: &[String],
+    check: &[String],
+    env: &BTreeMap<String, String>,
+) -> (Vec<String>, Vec<String>) {
+    let exports = export_commands(shell, env);
+    let check = match check.is_empty() {
+        true => Vec::new(),
+        false => [exports.clone(), check.to_vec()].concat(),
+    };
+    ([exports, commands.to_vec()].concat(), check)
+}
+
+async fn dry_run(
+    logger: &mut Logger,
+    config: &Config,
+    skip: &HashSet<String>,
+    file_sets: &HashMap<usize, Vec<PathBuf>>,
+    release: Option<&str>,
+    revision: Option<&str>,
+    delete: Option<&HashMap<usize, BTreeMap<String, String>>>,
+) {
+    let would_run = |host: &str, commands: &[String]| {
+        format!(
+            "{} on {}: '{}'",
+            "Would run:".bright_black(),
+            host,
+            commands.join(" && ").cyan()
+        )
+    };
+    let commands = &config.pre_deploy_local;
+    if !commands.is_empty() && !skip.contains("pre_deploy_local") {
+        log!(logger, "{}", would_run("localhost", commands));
+    }
+    if let Some(preflight) = &config.preflight {
+        let preflight = [preflight.to_owned()];
+        log!(logger, "{}", would_run(&config.server.host, &preflight));
+    }
+    let commands = &config.pre_deploy_remote;
+    if !commands.is_empty() && !skip.contains("pre_deploy_remote") {
+        log!(logger, "{}", would_run(&config.server.host, commands));
+    }
+    let links = release.map(|release| shared_links(config, release));
+    if let Some(links) = links.filter(|links| !links.is_empty()) {
+        log!(logger, "{}", would_run(&config.server.host, &links));
+    }
+    let order = action_order(&config.actions).expect("dependencies checked with the config");
+    for index in order {
+        let action = &config.actions[index];
+        if skip.contains(action.name()) {
+            continue;
+        }
+        let conditions = describe_conditions(action.conditions());
+        if !conditions.is_empty() {
+            log!(
+                logger,
+                "{} '{}' only runs {}",
+                "Condition:".bright_black(),
+                action.name(),
+                conditions.join(" and ")
+            );
+        }
+        match action {
+            Action::Commands {
+                name,
+                commands,
+                check,
+                env,
+                ..
+            } => {
+                let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+                let (commands, check) = dry_run_commands(shell, commands, check, env);
+                log!(logger, "{}", would_run(&config.server.host, &commands));
+                if check.is_empty() {
+                    continue;
+                }
+                // the check is read-only, so it really runs, in place of the commands
+                let status = match create_ssh_session(&config.server).await {
+                    Ok(session) => {
+                        let status = send_command(&mut *logger, &session, name, &check).await;
+                        let _ = session
+                            .disconnect(Disconnect::ByApplication, "", "English")
+                            .await;
+                        status
+                    }
+                    Err(e) => {
+                        log_warn!(logger, "{} {}", THEME.warning_label("Warning:"), e);
+                        continue;
+                    }
+                };
+                if status != Some(0) {
+                    log_warn!(
+                        logger,
+                        "{} the check of '{}' exited with {}",
+                        THEME.warning_label("Warning:"),
+                        name,
+                        status.map_or("no status".to_string(), |status| status.to_string())
+                    );
+                }
+            }
+            Action::Upload {
+                name,
+                source_folder,
+                target_folder,
+                backup,
+                ..
+            } => {
+                let server = match &config.relay {
+                    Some(relay) => &relay.server,
+                    None => &config.server,
+                };
+                let source_folder = expand_user_path(source_folder);
+                let target_folder = &match release {
+                    Some(release) => release_target(release, target_folder),
+                    None => target_folder.to_owned(),
+                };
+                if *backup {
+                    let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+                    let path = backup_path(&config.backup_key, &new_backup(), name);
+                    let path = expand_server_path(&path, &server.user);
+                    let target = expand_server_path(target_folder, &server.user);
+                    let command = backup_command(shell, &path, target.trim_end_matches('/'));
+                    log!(logger, "{}", would_run(&server.host, &[command]));
+                }
+                let files = file_sets.get(&index).map(Vec::as_slice).unwrap_or_default();
+                log!(
+                    logger,
+                    "{} {} files from '{}' to '{}' on {}",
+                    "Would upload:".bright_black(),
+                    files.len(),
+                    source_folder,
+                    expand_server_path(target_folder, &server.user),
+                    server.host
+                );
+                let relative_paths: Vec<String> = files
+                    .iter()
+                    .map(|path| {
+                        let relative_path = path.strip_prefix(&source_folder).unwrap();
+                        relative_path.to_string_lossy().to_string()
+                    })
+                    .collect();
+                for relative_path in &relative_paths {
+                    log!(logger, "  '{}'", relative_path.color(THEME.muted));
+                }
+                if let Some(unchanged) = delete.filter(|_| release.is_none()) {
+                    let unchanged = unchanged.get(&index).into_iter().flat_map(BTreeMap::keys);
+                    let local: BTreeSet<String> =
+                        relative_paths.iter().chain(unchanged).cloned().collect();
+                    // the files are removed from the server, not from the relay forwarding them
+                    let target = expand_server_path(target_folder, &config.server.user);
+                    // listing the target changes nothing on the server
+                    let stale = match create_ssh_session(&config.server).await {
+                        Ok(session) => {
+                            let stale = stale_files(&session, config, &target, &local).await;
+                            session
+                                .disconnect(Disconnect::ByApplication, "", "English")
+                                .await
+                                .expect("Failed to close ssh session");
+                            stale
+                        }
+                        Err(e) => Err(e),
+                    };
+                    match stale {
+                        Ok(stale) => {
+                            for path in stale {
+                                log!(logger, "{} '{}'", "Would delete:".bright_black(), path);
+                            }
+                        }
+                        Err(e) => {
+                            log_warn!(logger, "{} {}", THEME.warning_label("Warning:"), e);
+                        }
+                    }
+                }
+                let paths = relative_paths.iter().map(String::as_str);
+                let decompress = decompress_uploaded(config, server, target_folder, paths);
+                if !decompress.is_empty() {
+                    log!(logger, "{}", would_run(&server.host, &decompress));
+                }
+                if let Some(relay) = &config.relay {
+                    let commands = fan_out_commands(config, relay, target_folder);
+                    log!(logger, "{}", would_run(&relay.server.host, &commands));
+                }
+                let relative_paths = relative_paths.iter().map(String::as_str);
+                let chown = chown_uploaded(config, action, target_folder, relative_paths);
+                if !chown.is_empty() {
+                    log!(logger, "{}", would_run(&config.server.host, &chown));
+                }
+            }
+            Action::WaitForPort {
+                host,
+                port,
+                timeout,
+                ..
+            } => {
+                log!(
+                    logger,
+                    "{} up to {}s for {}:{} from {}",
+                    "Would wait:".bright_black(),
+                    timeout,
+                    host,
+                    port,
+                    config.server.host
+                );
+            }
+        }
+    }
+    if let Some(release) = release {
+        let switch = [release_switch(config, release, revision)];
+        log!(logger, "{}", would_run(&config.server.host, &switch));
+    }
+    let commands = &config.post_deploy_remote;
+    if !commands.is_empty() && !skip.contains("post_deploy_remote") {
+        log!(logger, "{}", would_run(&config.server.host, commands));
+    }
+    if let Some(healthcheck) = &config.healthcheck {
+        if !skip.contains("healthcheck") {
+            let target = describe_health(healthcheck, &config.server.host);
+            log!(logger, "{} {}", "Would check:".bright_black(), target);
+        }
+    }
+    let commands = &config.post_deploy_local;
+    if !commands.is_empty() && !skip.contains("post_deploy_local") {
+        log!(logger, "{}", would_run("localhost", commands));
+    }
+    if let (Some(_), Some(keep)) = (release, config.keep_releases) {
+        log!(
+            logger,
+            "{} the releases before the {} most recent",
+            "Would remove:".bright_black(),
+            keep
+        );
+    }
+    log!(
+        logger,
+        "{} nothing was sent to the server",
+        "Dry run:".bright_black()
+    );
+}
+
+// prints where base_path/current points to, nothing before the first release
+fn current_link(config: &Config) -> String {
+    let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+    let base_path = config.base_path.as_deref().unwrap_or_default();
+    let base_path = expand_server_path(base_path, &config.server.user);
+    let current_path = format!("{}/current", base_path.trim_end_matches('/'));
+    readlink_command(shell, &current_path)
+}
+
+fn readlink_command(shell: RemoteShell, path: &str) -> String {
+    format!("readlink {}", quote(shell, path))
+}
+
+// links base_path/current to the release on the server
+fn release_switch(config: &Config, release: &str, revision: Option<&str>) -> String {
+    let user = &config.server.user;
+    let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+    let base_path = config.base_path.as_deref().unwrap_or_default();
+    let release = expand_server_path(release, user);
+    let mut commands = vec![mark_released_command(shell, &release)];
+    if let Some(revision) = revision {
+        commands.push(revision_command(shell, &release, revision));
+    }
+    commands.push(switch_command(
+        shell,
+        &expand_server_path(base_path, user),
+        &release,
+    ));
+    commands.join(" && ")
+}
+
+// relay commands forwarding an upload, with the final server filled in
+fn fan_out_commands(config: &Config, relay: &Relay, target_folder: &str) -> Vec<String> {
+    let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+    let quoted = |value: &str| quote(shell, value);
+    let path = quoted(&expand_server_path(target_folder, &relay.server.user));
+    let target = quoted(&expand_server_path(target_folder, &config.server.user));
+    let host = quoted(&config.server.host);
+    let user = quoted(&config.server.user);
+    relay
+        .fan_out
+        .iter()
+        .map(|command| {
+            command
+                .replace("{{path}}", &path)
+                .replace("{{target}}", &target)
+                .replace("{{host}}", &host)
+                .replace("{{port}}", &config.server.port.to_string())
+                .replace("{{user}}", &user)
+        })
+        .collect()
+}
+
+// the files under target that aren't in local, relative to it, leaving out the protected_paths
+async fn stale_files(
+    session: &client::Handle<SimpleHandler>,
+    config: &Config,
+    target: &str,
+    local: &BTreeSet<String>,
+) -> Result<Vec<String>, String> {
+    let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+    let protected = protected_matcher(&config.protected_paths)
+        .expect("protected_paths checked with the config");
+    let list = format!("cd {} && find . -type f", quote(shell, target));
+    let (status, listing) = read_command(session, &list).await;
+    if status != Some(0) {
+        return Err(format!("unable to list the files of '{}'", target));
+    }
+    let mut stale: Vec<String> = listing
+        .lines()
+        .filter_map(|line| line.strip_prefix("./"))
+        .filter(|path| !local.contains(*path))
+        .filter(|path| {
+            !protected
+                .matched_path_or_any_parents(path, false)
+                .is_ignore()
+        })
+        .map(str::to_string)
+        .collect();
+    stale.sort();
+    Ok(stale)
+}
+
+// rm of the stale files, a hundred per command to keep them short
+fn delete_commands(config: &Config, target: &str, stale: &[String]) -> Vec<String> {
+    let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+    let target = target.trim_end_matches('/');
+    stale
+        .chunks(100)
+        .map(|paths| {
+            let paths: Vec<String> = paths
+                .iter()
+                .map(|path| quote(shell, &format!("{}/{}", target, path)))
+                .collect();
+            format!("rm -f -- {}", paths.join(" "))
+        })
+        .collect()
+}
+
+// unpacking of what was uploaded to server, for archives and compressed files, none without
+fn decompress_uploaded<'a>(
+    config: &Config,
+    server: &Server,
+    target_folder: &str,
+    relative_paths: impl Iterator<Item = &'a str>,
+) -> Vec<String> {
+    let mut relative_paths = relative_paths.peekable();
+    if relative_paths.peek().is_none() {
+        return Vec::new();
+    }
+    let target = expand_server_path(target_folder, &server.user);
+    let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+    if config.transfer_mode == TransferMode::Archive {
+        let archive = Path::new(&target).join(ARCHIVE_NAME);
+        return vec![extract_command(shell, &archive.to_string_lossy(), &target)];
+    }
+    let Some(compression) = config.compression else {
+        return Vec::new();
+    };
+    let paths: Vec<String> = relative_paths
+        .map(|relative_path| {
+            let path = Path::new(&target).join(relative_path);
+            format!("{}{}", path.to_string_lossy(), extension(compression))
+        })
+        .collect();
+    decompress_commands(shell, compression, &paths)
+}
+
+// chown of the uploaded files and their folders below the target, when the upload sets an owner;
+// the target itself may be a system folder and keeps its owner
+fn chown_uploaded<'a>(
+    config: &Config,
+    action: &Action,
+    target_folder: &str,
+    relative_paths: impl Iterator<Item = &'a str>,
+) -> Vec<String> {
+    let Action::Upload {
+        remote_owner,
+        remote_group,
+        ..
+    } = action
+    else {
+        return Vec::new();
+    };
+    if remote_owner.is_none() && remote_group.is_none() {
+        return Vec::new();
+    }
+    let target = expand_server_path(target_folder, &config.server.user);
+    let target = Path::new(&target);
+    let mut owned_paths = BTreeSet::new();
+    for relative_path in relative_paths {
+        let path = target.join(relative_path);
+        owned_paths.extend(
+            path.ancestors()
+                .take_while(|ancestor| *ancestor != target && ancestor.starts_with(target))
+                .map(|ancestor| ancestor.to_string_lossy().to_string()),
+        );
+    }
+    let paths: Vec<String> = owned_paths.into_iter().collect();
+    chown_commands(
+        config.remote_shell.unwrap_or(RemoteShell::Sh),
+        remote_owner.as_deref(),
+        remote_group.as_deref(),
+        &paths,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn temp_folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("deploy-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    // a config uploading source_folder, followed by the given actions
+    fn upload_config(source_folder: &Path, actions: &str) -> Config {
+        let contents = format!(
+            r#"
+            [server]
+            host = "localhost"
+            port = 22
+            user = "ubuntu"
+            ssh_key = "~/.ssh/id_ed25519"
+
+            [[actions]]
+            type = "upload"
+            name = "site"
+            source_folder = "{}"
+            target_folder = "~/site/"
+            {}
+            "#,
+            source_folder.display(),
+            actions
+        );
+        toml::from_str(&contents).unwrap()
+    }
+
+    fn options(dry_run: bool) -> Options {
+        Options {
+            skip: HashSet::new(),
+            skip_unreadable: false,
+            report_unused_ignores: false,
+            dry_run,
+            force: true,
+            limit_rate: None,
+            release: None,
+            env: None,
+            revision: None,
+            delete: false,
+        }
+    }
+
+    fn run(config: Config, options: Options) -> DeploymentReport {
+        let mut logger = Logger::new(None, options.dry_run);
+        let mut report = DeploymentReport::new("deploy.toml", &config.server.host);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(execute_actions(&mut logger, config, options, &mut report));
+        report
+    }
+
+    #[test]
+    fn an_unreachable_server_fails_the_connect_phase() {
+        let folder = temp_folder("unreachable");
+        fs::write(folder.join("index.html"), "hello").unwrap();
+        let mut config = upload_config(&folder, "");
+        // nothing listens on port 1
+        config.server.host = "127.0.0.1".to_string();
+        config.server.port = 1;
+        let report = run(config, options(false));
+        fs::remove_dir_all(&folder).unwrap();
+        let phases: Vec<&str> = report.phases.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(phases, ["prepare", "connect"]);
+        let failure = report.phases.last().unwrap().failure.as_deref().unwrap();
+        assert!(failure.starts_with("unable to connect to 127.0.0.1:1"));
+        assert!(report.error.is_some());
+        assert!(report.files.is_empty());
+    }
+
+    #[test]
+    fn junit_has_the_phases_the_deployment_ran() {
+        let folder = temp_folder("unreachable-junit");
+        fs::write(folder.join("index.html"), "hello").unwrap();
+        let mut config = upload_config(&folder, "");
+        config.server.host = "127.0.0.1".to_string();
+        config.server.port = 1;
+        let report = run(config, options(false));
+        let path = folder.join("report.xml");
+        crate::report::write_junit(path.to_str().unwrap(), &[report]).unwrap();
+        let xml = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&folder).unwrap();
+        assert!(xml.contains(r#"tests="2" failures="1""#));
+        assert!(xml.contains(r#"<testcase name="prepare" classname="deploy.toml""#));
+        assert!(xml.contains(r#"<testcase name="connect" classname="deploy.toml""#));
+        assert!(xml.contains("<failure message=\"unable to connect to 127.0.0.1:1"));
+    }
+
+    #[test]
+    fn dry_run_runs_the_check_instead_of_the_commands() {
+        let folder = temp_folder("dry-run-check");
+        let actions = r#"
+            [[actions]]
+            type = "commands"
+            name = "restart"
+            commands = ["sudo systemctl restart app"]
+            check = ["systemctl is-active app"]
+            env = { APP = "web" }
+            "#;
+        let config = upload_config(&folder, actions);
+        let Action::Commands {
+            commands,
+            check,
+            env,
+            ..
+        } = &config.actions[1]
+        else {
+            panic!("expected a commands action");
+        };
+        let (printed, checked) = dry_run_commands(RemoteShell::Sh, commands, check, env);
+        assert_eq!(printed, ["export APP='web'", "sudo systemctl restart app"]);
+        assert_eq!(checked, ["export APP='web'", "systemctl is-active app"]);
+        let (_, checked) = dry_run_commands(RemoteShell::Sh, commands, &[], env);
+        assert!(checked.is_empty());
+
+        // a host the check can't reach leaves the dry run with a warning
+        let mut config = config;
+        config.server.host = "127.0.0.1".to_string();
+        config.server.port = 1;
+        let report = run(config, options(true));
+        fs::remove_dir_all(&folder).unwrap();
+        assert!(report.error.is_none());
+    }
+
+    #[test]
+    fn unreadable_files_abort_before_connecting() {
+        let folder = temp_folder("unreadable");
+        fs::write(folder.join("index.html"), "hello").unwrap();
+        let secret = folder.join("secret.key");
+        fs::write(&secret, "key").unwrap();
+        fs::set_permissions(&secret, fs::Permissions::from_mode(0o000)).unwrap();
+        // root reads any file
+        if fs::File::open(&secret).is_ok() {
+            fs::remove_dir_all(&folder).unwrap();
+            return;
+        }
+
+        // a deployment that got past the prepare phase would fail to connect to the server
+        let report = run(upload_config(&folder, ""), options(false));
+        assert_eq!(report.error.as_deref(), Some("1 files can't be read"));
+        assert_eq!(report.phases.last().unwrap().name, "prepare");
+
+        let mut options = options(true);
+        options.skip_unreadable = true;
+        let report = run(upload_config(&folder, ""), options);
+        fs::remove_dir_all(&folder).unwrap();
+        assert!(report.error.is_none());
+        let skipped: Vec<(&str, &str)> = report
+            .skipped
+            .iter()
+            .map(|file| (file.path.as_str(), file.reason.as_str()))
+            .collect();
+        assert_eq!(skipped, [("secret.key", "unreadable")]);
+    }
+
+    #[test]
+    fn preflight_status_proceeds_skips_or_fails() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let preflight = |status| {
+            let mut logger = Logger::new(None, false);
+            let mut report = DeploymentReport::new("deploy.toml", "localhost");
+            let phase_start = report.start("preflight");
+            let passed = preflight_passed(&mut logger, status, phase_start, &mut report);
+            let passed = runtime.block_on(passed);
+            (passed, report)
+        };
+
+        let (passed, report) = preflight(Some(0));
+        assert!(passed);
+        assert!(report.status == Status::Success && report.error.is_none());
+
+        let (passed, report) = preflight(Some(2));
+        assert!(!passed);
+        assert!(report.status == Status::Skipped && report.error.is_none());
+
+        let (passed, report) = preflight(Some(1));
+        assert!(!passed);
+        assert!(report.status == Status::Failed);
+        assert_eq!(report.error.as_deref(), Some("exit status 1"));
+        assert_eq!(report.phases[0].exit_status, Some(1));
+    }
+
+    #[test]
+    fn fan_out_targets_the_final_server() {
+        let mut config = upload_config(Path::new("dist"), "");
+        config.server.host = "app.example.com".to_string();
+        config.server.user = "deploy".to_string();
+        let relay: Relay = toml::from_str(
+            r#"
+            host = "relay.example.com"
+            port = 22
+            user = "ubuntu"
+            ssh_key = "~/.ssh/relay"
+            fan_out = ["scp -P {{port}} -r {{path}} {{user}}@{{host}}:{{target}}"]
+            "#,
+        )
+        .unwrap();
+        // the files reach the relay once, then the relay copies them to the server
+        assert_eq!(
+            fan_out_commands(&config, &relay, "~/site/"),
+            ["scp -P 22 -r '/home/ubuntu/site/' 'deploy'@'app.example.com':'/home/deploy/site/'"]
+        );
+    }
+
+    #[test]
+    fn report_lists_skipped_files_with_their_reason() {
+        let folder = temp_folder("skipped");
+        fs::write(folder.join(".deployignore"), "*.tmp\n").unwrap();
+        fs::write(folder.join("index.html"), "hello").unwrap();
+        fs::write(folder.join("empty.txt"), "").unwrap();
+        fs::write(folder.join("cache.tmp"), "cache").unwrap();
+
+        let config = upload_config(&folder, "skip_empty_files = true");
+        let report = run(config, options(true));
+        fs::remove_dir_all(&folder).unwrap();
+        let mut skipped: Vec<(&str, &str)> = report
+            .skipped
+            .iter()
+            .map(|file| (file.path.as_str(), file.reason.as_str()))
+            .collect();
+        skipped.sort();
+        assert!(report.skipped.iter().all(|file| file.action == "site"));
+        assert_eq!(
+            skipped,
+            [
+                ("cache.tmp", "ignored by .deployignore pattern '*.tmp'"),
+                ("empty.txt", "skipped, empty file"),
+            ]
+        );
+    }
+
+    #[test]
+    fn uploaded_files_are_chowned_to_the_owner_and_group() {
+        let uploaded = ["index.html", "css/site.css"];
+        let config = upload_config(Path::new("site"), "");
+        let chown = chown_uploaded(&config, &config.actions[0], "~/site/", uploaded.into_iter());
+        assert!(chown.is_empty());
+
+        let config = upload_config(
+            Path::new("site"),
+            "remote_owner = \"www-data\"\nremote_group = \"www\"",
+        );
+        let chown = chown_uploaded(&config, &config.actions[0], "~/site/", uploaded.into_iter());
+        // the target folder keeps its owner
+        assert_eq!(
+            chown,
+            [
+                "chown www-data:www -- '/home/ubuntu/site/css' '/home/ubuntu/site/css/site.css' \
+                 '/home/ubuntu/site/index.html'"
+            ]
+        );
+    }
+
+    #[test]
+    fn clean_refuses_homes_top_level_folders_and_parents() {
+        let refused = ["", ".", "~", "~/", "/", "/root/", "/home", "/var"];
+        for path in refused.into_iter().chain(["/srv/site/../.."]) {
+            assert!(unsafe_to_remove(path, "/root"), "'{}' is removed", path);
+        }
+        let removed = ["/srv/site", "/var/www/site/", "site", "~/site"];
+        for path in removed {
+            assert!(!unsafe_to_remove(path, "/root"), "'{}' is refused", path);
+        }
+    }
+
+    fn release_folders(all: &[&str], released: &[&str], current: &str) -> Releases {
+        Releases {
+            all: all.iter().map(|release| release.to_string()).collect(),
+            released: released.iter().map(|release| release.to_string()).collect(),
+            current: current.to_string(),
+        }
+    }
+
+    #[test]
+    fn rollback_skips_the_releases_of_failed_deployments() {
+        // B failed before its switch, C is current
+        let releases = release_folders(&["A", "B", "C"], &["A", "C"], "C");
+        assert_eq!(releases.previous(), Some("A"));
+        // a first deployment has nothing before it
+        let releases = release_folders(&["A"], &["A"], "A");
+        assert_eq!(releases.previous(), None);
+    }
+
+    #[test]
+    fn an_unhealthy_release_rolls_back_to_the_one_it_replaced() {
+        // A was rolled back to after B, then C was deployed and failed its healthcheck
+        let previous = release_name("/srv/app/releases/A\n");
+        let releases = release_folders(&["A", "B", "C"], &["A", "B", "C"], "C");
+        assert_eq!(releases.target(Some(previous)), Ok("A"));
+        assert_eq!(releases.target(None), Ok("B"));
+        assert!(releases.target(Some("D")).is_err());
+
+        let mut report = DeploymentReport::new("deploy.toml", "web-1");
+        let error = "unhealthy: status 502".to_string();
+        report.fail("healthcheck", std::time::Duration::from_secs(3), error);
+        report.record("rollback", std::time::Duration::from_secs(1), None);
+        report.mark_rolled_back();
+        assert_eq!(report.status, Status::RolledBack);
+        let json: serde_json::Value =
+            serde_json::from_str(&crate::report::to_json(&[report]).unwrap()).unwrap();
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["deployments"][0]["status"], "rolled back");
+        assert_eq!(json["deployments"][0]["error"], "unhealthy: status 502");
+    }
+
+    #[test]
+    fn pruning_keeps_the_releases_made_current() {
+        // B and D failed before their switch, E is the one being deployed
+        let releases = release_folders(&["A", "B", "C", "D", "E"], &["A", "C", "E"], "E");
+        assert_eq!(releases.to_prune(2), ["A", "B", "D"]);
+        assert_eq!(releases.to_prune(3), ["B", "D"]);
+    }
 }
@@ -0,0 +1,91 @@
+use crate::config::RemoteShell;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::BTreeMap;
+
+lazy_static! {
+    static ref ACCOUNT_NAME: Regex = Regex::new(r"^[A-Za-z0-9_][A-Za-z0-9_.-]*$").unwrap();
+}
+
+// quotes value so the remote shell reads it as a single literal word
+pub fn quote(shell: RemoteShell, value: &str) -> String {
+    match shell {
+        RemoteShell::Sh | RemoteShell::Bash => format!("'{}'", value.replace('\'', r"'\''")),
+        // fish single quotes still give a meaning to \\ and \'
+        RemoteShell::Fish => format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'")),
+    }
+}
+
+// user and group names end up in a remote command, only plain names or ids are accepted
+pub fn check_account_name(name: &str) -> Result<(), String> {
+    if ACCOUNT_NAME.is_match(name) {
+        Ok(())
+    } else {
+        Err(format!("invalid user or group name '{}'", name))
+    }
+}
+
+// chown of paths to owner, group or both, which must have been checked, a hundred paths per
+// command to keep them short
+pub fn chown_commands(
+    shell: RemoteShell,
+    owner: Option<&str>,
+    group: Option<&str>,
+    paths: &[String],
+) -> Vec<String> {
+    let spec = match (owner, group) {
+        (Some(owner), Some(group)) => format!("{}:{}", owner, group),
+        (Some(owner), None) => owner.to_string(),
+        (None, Some(group)) => format!(":{}", group),
+        (None, None) => String::new(),
+    };
+    paths
+        .chunks(100)
+        .map(|paths| {
+            let paths: Vec<String> = paths.iter().map(|path| quote(shell, path)).collect();
+            format!("chown {} -- {}", spec, paths.join(" "))
+        })
+        .collect()
+}
+
+// sets the variables of env for the commands joined after these ones
+pub fn export_commands(shell: RemoteShell, env: &BTreeMap<String, String>) -> Vec<String> {
+    if env.is_empty() {
+        return Vec::new();
+    }
+    match shell {
+        RemoteShell::Sh | RemoteShell::Bash => {
+            let assignments: Vec<String> = env
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, quote(shell, value)))
+                .collect();
+            vec![format!("export {}", assignments.join(" "))]
+        }
+        RemoteShell::Fish => env
+            .iter()
+            .map(|(name, value)| format!("set -gx {} {}", name, quote(shell, value)))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chown_is_split_in_commands_of_a_hundred_paths() {
+        let paths: Vec<String> = (0..150).map(|n| format!("/srv/site/{}.html", n)).collect();
+        let commands = chown_commands(RemoteShell::Sh, Some("www"), None, &paths);
+        assert_eq!(commands.len(), 2);
+        assert!(commands[0].starts_with("chown www -- '/srv/site/0.html' "));
+        assert_eq!(commands[1].matches(".html'").count(), 50);
+    }
+
+    #[test]
+    fn quote_keeps_the_value_literal() {
+        let value = r#"it's "$HOME""#;
+        assert_eq!(quote(RemoteShell::Sh, value), r#"'it'\''s "$HOME"'"#);
+        assert_eq!(quote(RemoteShell::Bash, value), r#"'it'\''s "$HOME"'"#);
+        assert_eq!(quote(RemoteShell::Fish, value), r#"'it\'s "$HOME"'"#);
+    }
+}
@@ -0,0 +1,139 @@
+use crate::actions::commands::{create_ssh_session, read_command};
+use crate::actions::health::probe;
+use crate::actions::release::REVISION_FILE;
+use crate::actions::shell::quote;
+use crate::actions::upload::expand_server_path;
+use crate::config::{Config, RemoteShell, Strategy};
+use crate::theme::THEME;
+use chrono::{DateTime, Utc};
+use colored::Colorize;
+use russh::Disconnect;
+
+// 3d 4h, 2h 5m or 4m 10s
+fn format_uptime(seconds: u64) -> String {
+    let (days, hours, minutes) = (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60);
+    match (days, hours) {
+        (0, 0) => format!("{}m {}s", minutes, seconds % 60),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h", days, hours),
+    }
+}
+
+fn print_check(name: &str, up: bool, details: &str) {
+    let state = if up {
+        "up".color(THEME.success)
+    } else {
+        "down".color(THEME.error)
+    };
+    println!("  {}: {}, {}", name, state, details);
+}
+
+// prints whether the service of the [status] config is up, since when, and the deployed revision;
+// returns false when something is down
+pub async fn status(config: &Config) -> Result<bool, String> {
+    let Some(status) = &config.status else {
+        return Err("--status requires a [status] section in the config".to_string());
+    };
+    let shell = config.remote_shell.unwrap_or(RemoteShell::Sh);
+    let mut all_up = true;
+    println!("{} {}", "Status:".bright_black(), config.server.host);
+    let session = create_ssh_session(&config.server).await?;
+
+    if let Some(unit) = &status.systemd_unit {
+        // both times are counted from the boot, which keeps the server's timezone out of it
+        let show = format!(
+            "systemctl show {} --property=ActiveState --property=SubState \
+             --property=ActiveEnterTimestampMonotonic && cat /proc/uptime",
+            quote(shell, unit)
+        );
+        let (exit_status, output) = read_command(&session, &show).await;
+        let property = |name: &str| {
+            output
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix('='))
+                .unwrap_or_default()
+        };
+        let state = property("ActiveState");
+        let up = exit_status == Some(0) && state == "active";
+        let mut details = match state {
+            "" => "unknown unit".to_string(),
+            state => format!("{} ({})", state, property("SubState")),
+        };
+        let entered = property("ActiveEnterTimestampMonotonic").parse::<u64>();
+        let booted = output
+            .lines()
+            .last()
+            .and_then(|line| line.split_whitespace().next())
+            .and_then(|seconds| seconds.parse::<f64>().ok());
+        if let (true, Ok(entered), Some(booted)) = (up, entered, booted) {
+            let seconds = (booted - entered as f64 / 1_000_000.).max(0.);
+            details.push_str(&format!(" for {}", format_uptime(seconds as u64)));
+        }
+        print_check(unit, up, &details);
+        all_up &= up;
+    }
+
+    if let Some(container) = &status.docker_container {
+        let inspect = format!(
+            "docker inspect -f '{{{{.State.Status}}}} {{{{.State.StartedAt}}}}' {}",
+            quote(shell, container)
+        );
+        let (exit_status, output) = read_command(&session, &inspect).await;
+        let (state, started) = output.trim().split_once(' ').unwrap_or((output.trim(), ""));
+        let up = exit_status == Some(0) && state == "running";
+        let mut details = match exit_status {
+            Some(0) => state.to_string(),
+            _ => "no such container".to_string(),
+        };
+        if let (true, Ok(started)) = (up, DateTime::parse_from_rfc3339(started)) {
+            let seconds = (Utc::now() - started.with_timezone(&Utc)).num_seconds();
+            details.push_str(&format!(" for {}", format_uptime(seconds.max(0) as u64)));
+        }
+        print_check(&format!("{} container", container), up, &details);
+        all_up &= up;
+    }
+
+    if let Some(url) = &status.url {
+        let (up, details) = match probe(url, config.min_tls).await {
+            Ok(code) if code == status.expect_status => (true, format!("answered {}", code)),
+            Ok(code) => (
+                false,
+                format!("answered {} instead of {}", code, status.expect_status),
+            ),
+            Err(e) => (false, e),
+        };
+        print_check(&url.to_string(), up, &details);
+        all_up &= up;
+    }
+
+    match (config.strategy, &config.base_path) {
+        (Strategy::Releases, Some(base_path)) => {
+            let base_path = expand_server_path(base_path, &config.server.user);
+            let current = format!("{}/current", base_path.trim_end_matches('/'));
+            let revision = format!("{}/{}", current, REVISION_FILE);
+            let (_, release) =
+                read_command(&session, &format!("readlink {}", quote(shell, &current))).await;
+            let (_, revision) =
+                read_command(&session, &format!("cat {}", quote(shell, &revision))).await;
+            let release = release.trim().trim_end_matches('/').rsplit('/').next();
+            let release = release.filter(|release| !release.is_empty());
+            match (release, revision.trim()) {
+                (None, _) => println!("  revision: {}", "nothing deployed yet".color(THEME.muted)),
+                (Some(release), "") => println!("  revision: unknown, release {}", release),
+                (Some(release), revision) => {
+                    let revision = revision.get(..7).unwrap_or(revision);
+                    println!("  revision: {}, release {}", revision, release)
+                }
+            }
+        }
+        _ => println!(
+            "  revision: {}",
+            "only recorded with strategy = \"releases\"".color(THEME.muted)
+        ),
+    }
+    session
+        .disconnect(Disconnect::ByApplication, "", "English")
+        .await
+        .expect("Failed to close ssh session");
+    Ok(all_up)
+}
@@ -1,6 +1,16 @@
+use crate::actions::compression::{extension, Compressor};
+use crate::actions::retry::Retry;
+use crate::cache;
+use crate::config::Compression;
+use crate::lock::hash_file;
+use crate::logger::Verbosity;
+use crate::report::UploadedFile;
+use crate::resume;
+use crate::theme::THEME;
 use crate::Logger;
-use crate::{config::Config, log};
+use crate::{config::Server, console, log, log_debug};
 use async_std::fs::File;
+use async_std::io::prelude::SeekExt;
 use async_std::io::ReadExt;
 use colored::*;
 use crossterm::style::{Color, Print, SetForegroundColor};
@@ -12,13 +22,22 @@ use crossterm::{
     execute,
 };
 use dirs_next::home_dir;
-use ignore::WalkBuilder;
+use futures::channel::mpsc;
+use futures::future::{join, join_all};
+use futures::StreamExt;
 use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::{FileAttributes, OpenFlags};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::fmt;
 use std::future::Future;
-use std::io::{stdout, Error, ErrorKind, Write};
+use std::io::{stdout, Error, ErrorKind, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
-use tokio::io::AsyncWriteExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 
 pub fn expand_user_path(user_path: &str) -> String {
     if user_path.starts_with("~/") {
@@ -32,7 +51,7 @@ pub fn expand_user_path(user_path: &str) -> String {
     }
 }
 
-fn expand_server_path(server_path: &str, username: &str) -> String {
+pub fn expand_server_path(server_path: &str, username: &str) -> String {
     if server_path.starts_with("~/") {
         server_path.replacen("~", &format!("/home/{}", username), 1)
     } else {
@@ -40,7 +59,62 @@ fn expand_server_path(server_path: &str, username: &str) -> String {
     }
 }
 
-const CHUNK_SIZE: usize = 8 * 1024;
+pub const CHUNK_SIZE: usize = 8 * 1024;
+
+// how file contents go over the wire
+#[derive(Clone, Copy, Default)]
+pub struct TransferOptions {
+    // files are uploaded compressed with this suffix, then decompressed on the server
+    pub compression: Option<Compression>,
+    pub compression_level: Option<i32>,
+    // bytes per second over all the transfers of an upload
+    pub max_bandwidth: Option<u64>,
+    // of each file whose transfer failed
+    pub retry: Retry,
+    // of the concurrent uploads with upload_concurrency = "auto"
+    pub tuner: Option<Tuner>,
+}
+
+// how far a throttled upload can lag behind its rate, before the lost time is written off
+const THROTTLE_MAX_LAG: Duration = Duration::from_secs(1);
+
+// delays the reads of the transfers it's shared by, to keep their total rate under max_bandwidth
+struct Throttle {
+    max_bandwidth: Option<u64>,
+    // start of the current measure and bytes read since
+    window: Mutex<(Instant, u64)>,
+}
+
+impl Throttle {
+    fn new(max_bandwidth: Option<u64>) -> Throttle {
+        Throttle {
+            max_bandwidth,
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    async fn wait(&self, bytes: usize) {
+        let Some(max_bandwidth) = self.max_bandwidth else {
+            return;
+        };
+        let ahead = {
+            let mut window = self.window.lock().unwrap();
+            let (started, read) = &mut *window;
+            let elapsed = started.elapsed();
+            *read += bytes as u64;
+            let due = Duration::from_secs_f64(*read as f64 / max_bandwidth as f64);
+            // a pause, like the time between two uploads, doesn't allow a burst afterwards
+            if elapsed > due + THROTTLE_MAX_LAG {
+                *window = (Instant::now(), bytes as u64);
+                return;
+            }
+            due.saturating_sub(elapsed)
+        };
+        if !ahead.is_zero() {
+            tokio::time::sleep(ahead).await;
+        }
+    }
+}
 
 async fn ensure_directory_exists(sftp: &mut SftpSession, file_path: &PathBuf) -> Result<(), Error> {
     if let Some(parent_path) = file_path.parent() {
@@ -103,13 +177,216 @@ fn progress_str(progress: f64) -> String {
     }
 }
 
-pub async fn upload(
-    config: &Config,
+const ETA_SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+const ETA_MIN_SAMPLES: u32 = 4;
+const ETA_SMOOTHING: f64 = 0.2;
+
+// estimates the remaining time from a moving average of the throughput
+struct Eta {
+    remaining: u64,
+    rate: f64,
+    samples: u32,
+    sampled_bytes: u64,
+    last_sample: Instant,
+}
+
+impl Eta {
+    fn new(total: u64) -> Eta {
+        Eta {
+            remaining: total,
+            rate: 0.,
+            samples: 0,
+            sampled_bytes: 0,
+            last_sample: Instant::now(),
+        }
+    }
+
+    fn update(&mut self, bytes: u64) {
+        self.update_at(bytes, Instant::now());
+    }
+
+    fn update_at(&mut self, bytes: u64, now: Instant) {
+        self.remaining = self.remaining.saturating_sub(bytes);
+        self.sampled_bytes += bytes;
+        let elapsed = now.duration_since(self.last_sample);
+        if elapsed < ETA_SAMPLE_INTERVAL {
+            return;
+        }
+        let rate = self.sampled_bytes as f64 / elapsed.as_secs_f64();
+        self.rate = if self.samples == 0 {
+            rate
+        } else {
+            ETA_SMOOTHING * rate + (1. - ETA_SMOOTHING) * self.rate
+        };
+        self.samples += 1;
+        self.sampled_bytes = 0;
+        self.last_sample = now;
+    }
+
+    // bytes per second, once there are enough samples to trust it
+    fn rate(&self) -> Option<f64> {
+        if self.samples < ETA_MIN_SAMPLES || self.rate <= 0. {
+            return None;
+        }
+        Some(self.rate)
+    }
+
+    fn remaining_time(&self) -> Option<Duration> {
+        let rate = self.rate()?;
+        Some(Duration::from_secs_f64(self.remaining as f64 / rate))
+    }
+}
+
+impl fmt::Display for Eta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.remaining_time() {
+            Some(remaining) => {
+                let seconds = remaining.as_secs();
+                write!(
+                    f,
+                    "ETA {:02}:{:02}:{:02}",
+                    seconds / 3600,
+                    seconds / 60 % 60,
+                    seconds % 60
+                )
+            }
+            None => write!(f, "ETA --"),
+        }
+    }
+}
+
+// in powers of 1024, like max_bandwidth
+pub fn format_bytes(bytes: u64) -> String {
+    match bytes as f64 {
+        b if b < 1024. => format!("{}B", bytes),
+        b if b < 1024. * 1024. => format!("{:.1}KB", b / 1024.),
+        b if b < 1024. * 1024. * 1024. => format!("{:.1}MB", b / 1024. / 1024.),
+        b => format!("{:.1}GB", b / 1024. / 1024. / 1024.),
+    }
+}
+
+const PROGRESS_BAR_WIDTH: usize = 20;
+const PROGRESS_REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+// files from this size get their own percentage next to the overall progress
+const PROGRESS_FILE_MIN_SIZE: u64 = 1024 * 1024;
+
+fn progress_bar(ratio: f64) -> String {
+    let cells = ratio.clamp(0., 1.) * PROGRESS_BAR_WIDTH as f64;
+    let full = cells as usize;
+    let mut bar = "\u{2588}".repeat(full);
+    if full < PROGRESS_BAR_WIDTH {
+        bar.push_str(&progress_str(cells.fract()));
+        bar.push_str(&" ".repeat(PROGRESS_BAR_WIDTH - full - 1));
+    }
+    bar
+}
+
+// overall progress of an upload, kept on the line below the uploaded files
+pub struct Progress {
+    total_bytes: u64,
+    sent_bytes: u64,
+    total_files: usize,
+    done_files: usize,
+    eta: Eta,
+    last_draw: Instant,
+    // nothing is drawn with --quiet
+    visible: bool,
+    // redrawn in place, instead of printed once complete
+    interactive: bool,
+    prefix: String,
+}
+
+impl Progress {
+    pub fn new(total_bytes: u64, total_files: usize, logger: &Logger) -> Progress {
+        Progress {
+            total_bytes,
+            sent_bytes: 0,
+            total_files,
+            done_files: 0,
+            eta: Eta::new(total_bytes),
+            last_draw: Instant::now(),
+            visible: logger.verbosity() != Verbosity::Quiet,
+            interactive: logger.interactive(),
+            prefix: logger.prefix(),
+        }
+    }
+
+    fn update(&mut self, bytes: u64) {
+        self.sent_bytes += bytes;
+        self.eta.update(bytes);
+    }
+
+    // a file's sent bytes went from previous to sent, back down when its transfer restarted
+    pub fn update_file(&mut self, previous: u64, sent: u64) {
+        match sent.checked_sub(previous) {
+            Some(bytes) => self.update(bytes),
+            None => self.sent_bytes = self.sent_bytes.saturating_sub(previous - sent),
+        }
+    }
+
+    pub fn file_done(&mut self) {
+        self.done_files += 1;
+    }
+
+    // current is the name of the file being sent, its sent bytes and its size
+    fn line(&self, current: Option<(&str, u64, u64)>) -> String {
+        let ratio = match self.total_bytes {
+            0 => 1.,
+            total => self.sent_bytes as f64 / total as f64,
+        };
+        let rate = match self.eta.rate() {
+            Some(rate) => format!("{}/s", format_bytes(rate as u64)),
+            None => "--/s".to_string(),
+        };
+        let mut line = format!(
+            "{} {}/{} {} {} {}/{} files",
+            progress_bar(ratio).bright_cyan(),
+            format_bytes(self.sent_bytes),
+            format_bytes(self.total_bytes),
+            rate.bright_black(),
+            self.eta.to_string().bright_black(),
+            self.done_files,
+            self.total_files
+        );
+        if let Some((name, sent, size)) =
+            current.filter(|(_, _, size)| *size >= PROGRESS_FILE_MIN_SIZE)
+        {
+            let file_ratio = sent as f64 / size as f64;
+            line.push_str(&format!(
+                " '{}' {:.0}%",
+                name.bright_black(),
+                file_ratio * 100.
+            ));
+        }
+        line
+    }
+
+    // prints the progress on a new line, only once complete when it can't be redrawn
+    pub fn draw(&mut self, current: Option<(&str, u64, u64)>) {
+        if !self.visible || (!self.interactive && self.done_files < self.total_files) {
+            return;
+        }
+        println!("{}{}", self.prefix, self.line(current));
+        self.last_draw = Instant::now();
+    }
+
+    // replaces the progress line, at most every PROGRESS_REDRAW_INTERVAL
+    pub fn redraw(&mut self, current: Option<(&str, u64, u64)>) {
+        if !self.visible || !self.interactive || self.last_draw.elapsed() < PROGRESS_REDRAW_INTERVAL
+        {
+            return;
+        }
+        rewrite(self.line(current));
+        self.last_draw = Instant::now();
+    }
+}
+
+// logs the upload and returns the expanded source folder
+async fn start_upload(
     logger: &mut Logger,
-    sftp: &mut SftpSession,
     source_folder: &String,
     target_folder: &String,
-) {
+) -> String {
     log!(
         logger,
         "{}{}{}{}{}",
@@ -120,11 +397,13 @@ pub async fn upload(
         "'".bright_black()
     );
     let source_folder = expand_user_path(&source_folder);
-    let mut ignore_path = Path::new(&source_folder).to_path_buf();
-    ignore_path.push(".deployignore");
-    let mut builder = WalkBuilder::new(&source_folder);
-    builder.standard_filters(false);
-    if ignore_path.exists() {
+    let ignore_path = Path::new(&source_folder).join(".deployignore");
+    if !ignore_path.exists() || logger.verbosity() == Verbosity::Quiet {
+        return source_folder;
+    }
+    if !logger.interactive() {
+        println!("{}Found: '{}'", logger.prefix(), ignore_path.display());
+    } else {
         execute!(
             stdout(),
             Clear(ClearType::CurrentLine),
@@ -139,89 +418,761 @@ pub async fn upload(
             MoveToColumn(0),
         )
         .unwrap();
+    }
+    source_folder
+}
+
+fn total_bytes(files: &[PathBuf]) -> u64 {
+    files
+        .iter()
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
 
-        builder.add_ignore(ignore_path);
+// files at least this large are uploaded to a .part file that a later run can resume
+const RESUME_MIN_SIZE: u64 = 16 * 1024 * 1024;
+// resumed uploads restart at the first block not recorded as written, or changed since
+const RESUME_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+// hashes the blocks of source_file already on the server, returning the offset to resume from
+async fn verified_offset(
+    source_file: &mut File,
+    blocks: &mut Vec<String>,
+    remote_size: u64,
+    hasher: &mut Sha256,
+) -> std::io::Result<u64> {
+    let mut block = vec![0; RESUME_BLOCK_SIZE];
+    let mut verified = 0;
+    while verified < blocks.len() && (verified + 1) * RESUME_BLOCK_SIZE <= remote_size as usize {
+        // a shorter file can't have this block anymore
+        if source_file.read_exact(&mut block).await.is_err() {
+            break;
+        }
+        if format!("{:x}", Sha256::digest(&block)) != blocks[verified] {
+            break;
+        }
+        hasher.update(&block);
+        verified += 1;
     }
+    blocks.truncate(verified);
+    let offset = (verified * RESUME_BLOCK_SIZE) as u64;
+    source_file.seek(SeekFrom::Start(offset)).await?;
+    Ok(offset)
+}
 
-    let target_folder = expand_server_path(target_folder, &config.server.user);
-    for result in builder.build() {
+// transfer, tried again after each failure while options.retry allows
+async fn transfer_with_retries(
+    server: &Server,
+    sftp: &mut SftpSession,
+    path: &Path,
+    target_path: &Path,
+    options: TransferOptions,
+    throttle: &Throttle,
+    mut on_chunk: impl FnMut(u64, u64),
+) -> Result<UploadedFile, String> {
+    let mut attempt = 0;
+    loop {
+        let result = transfer(
+            server,
+            sftp,
+            path,
+            target_path,
+            options,
+            throttle,
+            &mut on_chunk,
+        )
+        .await;
         match result {
-            Ok(entry) => {
-                let path = entry.path();
-                if path.is_file() {
-                    // Compute relative path
-                    let relative_path = path.strip_prefix(&source_folder).unwrap();
-                    let target_path = Path::new(&target_folder).join(relative_path);
-
-                    // Log the file transfer
-                    println!(
-                        "{} '{}'",
-                        "▏".bright_cyan(),
-                        relative_path.display().to_string().bright_black()
+            Err(err) if attempt < options.retry.retries => {
+                attempt += 1;
+                let delay = options.retry.backoff(attempt);
+                console!(
+                    "{} '{}': {}, retrying in {}s ({}/{})",
+                    THEME.warning_label("Retrying:"),
+                    path.display(),
+                    err,
+                    delay.as_secs_f64(),
+                    attempt,
+                    options.retry.retries
+                );
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+// copies path to target_path, calling on_chunk with the bytes of the file sent so far
+async fn transfer(
+    server: &Server,
+    sftp: &mut SftpSession,
+    path: &Path,
+    target_path: &Path,
+    options: TransferOptions,
+    throttle: &Throttle,
+    mut on_chunk: impl FnMut(u64, u64),
+) -> Result<UploadedFile, String> {
+    if let Err(err) = ensure_directory_exists(sftp, &target_path.to_path_buf()).await {
+        return Err(format!("Failed to ensure directory exists: {}", err));
+    }
+    let Ok(mut source_file) = File::open(path).await else {
+        return Err(format!("Unable to open source file, {}", path.display()));
+    };
+    let metadata = source_file
+        .metadata()
+        .await
+        .expect("Unable to read file metadata");
+    let total_size = metadata.len();
+
+    let mut compressor = options
+        .compression
+        .map(|compression| Compressor::new(compression, options.compression_level))
+        .transpose()
+        .map_err(|err| format!("Unable to start compression: {}", err))?;
+    let mut target_path = target_path.to_string_lossy().to_string();
+    if let Some(compression) = options.compression {
+        target_path.push_str(extension(compression));
+    }
+    // a compressed stream can't be resumed midway
+    let resumable = compressor.is_none() && total_size >= RESUME_MIN_SIZE;
+    let part_path = format!("{}.part", target_path);
+    let resume_key = cache::key(server, &part_path);
+    let mut hasher = Sha256::new();
+    let mut blocks = if resumable {
+        resume::blocks(&resume_key)
+    } else {
+        Vec::new()
+    };
+    let mut uploaded = 0;
+    let mut target_file = if resumable {
+        let remote_size = match sftp.metadata(part_path.to_owned()).await {
+            Ok(metadata) if metadata.len() <= total_size => metadata.len(),
+            _ => 0,
+        };
+        let offset = verified_offset(&mut source_file, &mut blocks, remote_size, &mut hasher)
+            .await
+            .map_err(|err| err.to_string())?;
+        if offset > 0 {
+            let mut target_file = sftp
+                .open_with_flags(part_path.to_owned(), OpenFlags::WRITE)
+                .await
+                .map_err(|err| err.to_string())?;
+            target_file
+                .seek(SeekFrom::Start(offset))
+                .await
+                .map_err(|err| err.to_string())?;
+            uploaded = offset as usize;
+            on_chunk(uploaded as u64, total_size);
+            target_file
+        } else {
+            sftp.create(part_path.to_owned())
+                .await
+                .map_err(|err| err.to_string())?
+        }
+    } else {
+        sftp.create(target_path.to_owned())
+            .await
+            .map_err(|err| err.to_string())?
+    };
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut block_hasher = Sha256::new();
+
+    // Read and write in chunks
+    while let Ok(bytes_read) = source_file.read(&mut buffer).await {
+        if bytes_read == 0 {
+            break;
+        };
+        throttle.wait(bytes_read).await;
+        let chunk = &buffer[..bytes_read];
+        let compressed = match &mut compressor {
+            Some(compressor) => compressor.compress(chunk).map_err(|err| err.to_string())?,
+            None => chunk.to_vec(),
+        };
+        target_file
+            .write_all(&compressed)
+            .await
+            .map_err(|err| err.to_string())?;
+        hasher.update(chunk);
+        uploaded += bytes_read;
+        on_chunk(uploaded as u64, total_size);
+        if resumable {
+            block_hasher.update(chunk);
+            if uploaded % RESUME_BLOCK_SIZE == 0 {
+                target_file.flush().await.map_err(|err| err.to_string())?;
+                blocks.push(format!("{:x}", block_hasher.finalize_reset()));
+                if let Err(err) = resume::record(&resume_key, &blocks) {
+                    console!(
+                        "{} Unable to record the upload progress: {}",
+                        THEME.warning_label("Warning:"),
+                        err
                     );
+                }
+            }
+        }
+    }
+    if let Some(compressor) = compressor {
+        let remaining = compressor.finish().map_err(|err| err.to_string())?;
+        target_file
+            .write_all(&remaining)
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+    target_file
+        .sync_all()
+        .await
+        .map_err(|err| err.to_string())?;
 
-                    if let Err(err) = ensure_directory_exists(sftp, &target_path).await {
-                        rewrite(format!(
-                            "{} Failed to ensure directory exists: {}",
-                            "Error:".bright_red(),
-                            err
-                        ))
-                    }
+    if resumable {
+        drop(target_file);
+        // sftp renames don't replace an existing file
+        let _ = sftp.remove_file(target_path.to_owned()).await;
+        sftp.rename(part_path, target_path)
+            .await
+            .map_err(|err| err.to_string())?;
+        if let Err(err) = resume::forget(&resume_key) {
+            console!(
+                "{} Unable to record the upload progress: {}",
+                THEME.warning_label("Warning:"),
+                err
+            );
+        }
+    }
+
+    Ok(UploadedFile {
+        path: String::new(),
+        bytes: uploaded as u64,
+        sha256: format!("{:x}", hasher.finalize()),
+    })
+}
+
+pub async fn upload(
+    server: &Server,
+    logger: &mut Logger,
+    sftp: &mut SftpSession,
+    source_folder: &String,
+    target_folder: &String,
+    files: Vec<PathBuf>,
+    options: TransferOptions,
+) -> Vec<UploadedFile> {
+    let source_folder = start_upload(logger, source_folder, target_folder).await;
+    let target_folder = expand_server_path(target_folder, &server.user);
+    let mut uploaded_files = Vec::new();
+    let mut progress = Progress::new(total_bytes(&files), files.len(), logger);
+    let throttle = Throttle::new(options.max_bandwidth);
+    progress.draw(None);
+    for path in files {
+        // Compute relative path
+        let relative_path = path.strip_prefix(&source_folder).unwrap();
+        let target_path = Path::new(&target_folder).join(relative_path);
+        let file_name = relative_path.display().to_string();
+
+        let mut sent = 0;
+        let on_chunk = |file_sent: u64, total_size: u64| {
+            progress.update_file(sent, file_sent);
+            sent = file_sent;
+            progress.redraw(Some((file_name.as_str(), sent, total_size)));
+        };
+        match transfer_with_retries(
+            server,
+            sftp,
+            &path,
+            &target_path,
+            options,
+            &throttle,
+            on_chunk,
+        )
+        .await
+        {
+            Ok(mut file) => {
+                file.path = relative_path.to_string_lossy().to_string();
+                uploaded_files.push(file);
+                progress.file_done();
+                logger.add_uploaded_file(file_name).await;
+            }
+            Err(err) => console!("{} {}", THEME.error_label("Error:"), err),
+        }
+        progress.draw(None);
+    }
+    logger.stop_files_display().await;
+    uploaded_files
+}
+
+// how long the transfers run at a concurrency before the tuner compares their throughput
+const TUNE_INTERVAL: Duration = Duration::from_secs(2);
+// how much more throughput is worth one more transfer, so that noise doesn't raise it
+const TUNE_GAIN: f64 = 1.1;
+// how often the transfers above the concurrency check whether they may start again
+const TUNE_IDLE: Duration = Duration::from_millis(100);
+
+// the concurrency of upload_concurrency = "auto": one more transfer while the throughput grows
+// with it, one less once it drops, half as many after errors, and never again as many as were
+// backed off from
+#[derive(Clone, Copy)]
+pub struct Tuner {
+    level: usize,
+    min: usize,
+    ceiling: usize,
+    // of the window before, to tell whether the last change paid off
+    last_throughput: Option<f64>,
+}
+
+impl Tuner {
+    // starts conservative, at min
+    pub fn new(min: usize, max: usize) -> Tuner {
+        Tuner {
+            level: min,
+            min,
+            ceiling: max,
+            last_throughput: None,
+        }
+    }
+
+    // the concurrency after a window with this throughput in bytes per second, and errors files
+    // that failed
+    pub fn adjust(&mut self, throughput: f64, errors: usize) -> usize {
+        let backed_off = if errors > 0 {
+            Some(self.level / 2)
+        } else {
+            match self.last_throughput {
+                Some(last) if throughput * TUNE_GAIN < last => Some(self.level - 1),
+                Some(last) if throughput < last * TUNE_GAIN => None,
+                _ => {
+                    self.level = (self.level + 1).min(self.ceiling);
+                    None
+                }
+            }
+        };
+        if let Some(level) = backed_off {
+            self.ceiling = (self.level - 1).max(self.min);
+            self.level = level.max(self.min);
+        }
+        self.last_throughput = Some(throughput);
+        self.level
+    }
+}
+
+// spreads the files over one sftp session each, the display is only updated as files complete;
+// with a tuner, only as many sessions as its concurrency transfer at once
+pub async fn upload_concurrently(
+    server: &Server,
+    logger: &mut Logger,
+    sftps: &mut [SftpSession],
+    source_folder: &String,
+    target_folder: &String,
+    files: Vec<PathBuf>,
+    options: TransferOptions,
+) -> Vec<UploadedFile> {
+    let mut tuner = options.tuner;
+    let source_folder = start_upload(logger, source_folder, target_folder).await;
+    let target_folder = expand_server_path(target_folder, &server.user);
+    let progress = Mutex::new(Progress::new(total_bytes(&files), files.len(), logger));
+    let queue = Mutex::new(VecDeque::from(files));
+    let throttle = Throttle::new(options.max_bandwidth);
+    let (sender, mut receiver) = mpsc::unbounded();
+    let concurrency = AtomicUsize::new(tuner.as_ref().map_or(sftps.len(), |tuner| tuner.level));
+    if tuner.is_some() {
+        let level = concurrency.load(Ordering::Relaxed);
+        log_debug!(logger, "Concurrency: starting at {}", level);
+    }
 
-                    match sftp.create(target_path.as_path().to_string_lossy()).await {
-                        Ok(mut target_file) => {
-                            // Open the source file
-                            if let Ok(mut source_file) = File::open(&path).await {
-                                let metadata = source_file
-                                    .metadata()
-                                    .await
-                                    .expect("Unable to read file metadata");
-
-                                let total_size = metadata.len() as usize;
-                                let mut buffer = vec![0; CHUNK_SIZE];
-                                let mut uploaded = 0;
-
-                                // Read and write in chunks
-                                while let Ok(bytes_read) = source_file.read(&mut buffer).await {
-                                    if bytes_read == 0 {
-                                        break;
-                                    };
-                                    target_file
-                                        .write_all(&buffer[..bytes_read])
-                                        .await
-                                        .expect("Error writing to file");
-                                    uploaded += bytes_read;
-
-                                    let upload_ratio = uploaded as f64 / total_size as f64;
-                                    rewrite(format!(
-                                        "{} '{}' ({:.2}%)",
-                                        progress_str(upload_ratio).bright_cyan(),
-                                        relative_path.display().to_string().bright_black(),
-                                        upload_ratio * 100.
-                                    ));
-                                }
-                                target_file
-                                    .sync_all()
-                                    .await
-                                    .expect("Unable to sync file write");
-
-                                logger
-                                    .add_uploaded_file(relative_path.display().to_string())
-                                    .await;
-                            } else {
-                                println!(
-                                    "{} Unable to open source file, {}",
-                                    "Error:".bright_red(),
-                                    path.display()
-                                );
-                            }
-                        }
-                        Err(err) => println!("{} {}", "Error:".bright_red(), err),
+    let workers = join_all(sftps.iter_mut().enumerate().map(|(index, sftp)| {
+        let sender = sender.clone();
+        let (queue, throttle, progress) = (&queue, &throttle, &progress);
+        let (source_folder, target_folder, concurrency) =
+            (&source_folder, &target_folder, &concurrency);
+        async move {
+            loop {
+                if index >= concurrency.load(Ordering::Relaxed) {
+                    if queue.lock().unwrap().is_empty() {
+                        break;
                     }
+                    tokio::time::sleep(TUNE_IDLE).await;
+                    continue;
                 }
+                let Some(path) = queue.lock().unwrap().pop_front() else {
+                    break;
+                };
+                let relative_path = path.strip_prefix(source_folder).unwrap();
+                let target_path = Path::new(target_folder).join(relative_path);
+                let mut sent = 0;
+                let on_chunk = |file_sent: u64, _: u64| {
+                    let mut progress = progress.lock().unwrap();
+                    progress.update_file(sent, file_sent);
+                    sent = file_sent;
+                    progress.redraw(None);
+                };
+                let result = transfer_with_retries(
+                    server,
+                    sftp,
+                    &path,
+                    &target_path,
+                    options,
+                    throttle,
+                    on_chunk,
+                )
+                .await;
+                let result = result.map(|mut file| {
+                    file.path = relative_path.to_string_lossy().to_string();
+                    file
+                });
+                // the receiver outlives the workers
+                let _ = sender.unbounded_send(result);
+            }
+        }
+    }));
+    drop(sender);
+
+    // completions arrive one at a time, so the rolling display is never drawn concurrently
+    let display = async {
+        let mut uploaded_files = Vec::new();
+        let (mut window_start, mut window_bytes, mut window_errors) = (Instant::now(), 0, 0);
+        progress.lock().unwrap().draw(None);
+        while let Some(result) = receiver.next().await {
+            match result {
+                Ok(file) => {
+                    progress.lock().unwrap().file_done();
+                    logger.add_uploaded_file(file.path.to_owned()).await;
+                    window_bytes += file.bytes;
+                    uploaded_files.push(file);
+                }
+                Err(err) => {
+                    console!("{} {}", THEME.error_label("Error:"), err);
+                    window_errors += 1;
+                }
+            }
+            progress.lock().unwrap().draw(None);
+            let elapsed = window_start.elapsed();
+            if let (Some(tuner), true) = (&mut tuner, elapsed >= TUNE_INTERVAL) {
+                let throughput = window_bytes as f64 / elapsed.as_secs_f64();
+                let level = tuner.adjust(throughput, window_errors);
+                concurrency.store(level, Ordering::Relaxed);
+                log_debug!(
+                    logger,
+                    "Concurrency: {}/s over {:.1}s with {} errors, now {}",
+                    format_bytes(throughput as u64),
+                    elapsed.as_secs_f64(),
+                    window_errors,
+                    level
+                );
+                (window_start, window_bytes, window_errors) = (Instant::now(), 0, 0);
             }
-            Err(err) => println!("{} {}", "Error:".bright_red(), err),
         }
+        uploaded_files
+    };
+    let (_, uploaded_files) = join(workers, display).await;
+    logger.stop_files_display().await;
+    uploaded_files
+}
+
+// the descriptors open besides the transfers: standard streams, log files, the ssh connection
+const RESERVED_DESCRIPTORS: u64 = 64;
+
+// the soft limit of open files of the process, None where /proc/self/limits isn't available
+pub fn open_files_limit() -> Option<u64> {
+    let limits = std::fs::read_to_string("/proc/self/limits").ok()?;
+    parse_open_files_limit(&limits)
+}
+
+fn parse_open_files_limit(limits: &str) -> Option<u64> {
+    let line = limits
+        .lines()
+        .find(|line| line.starts_with("Max open files"))?;
+    match line["Max open files".len()..].split_whitespace().next()? {
+        "unlimited" => Some(u64::MAX),
+        soft => soft.parse().ok(),
+    }
+}
+
+// a warning when each concurrent transfer holding its local file open could run out of
+// descriptors, with the concurrency that fits
+pub fn open_files_warning(concurrency: usize, limit: u64) -> Option<String> {
+    let needed = concurrency as u64 + RESERVED_DESCRIPTORS;
+    if needed <= limit {
+        return None;
+    }
+    let fits = limit.saturating_sub(RESERVED_DESCRIPTORS).max(1);
+    Some(format!(
+        "upload_concurrency = {} may need {} open files, over the limit of {} (ulimit -n), use at most {}",
+        concurrency, needed, limit, fits
+    ))
+}
+
+// name of the archive in the target folder until it's extracted
+pub const ARCHIVE_NAME: &str = ".deploy-upload.tar.zst";
+
+// numbers the local archives, so uploads running at once don't share one
+static ARCHIVE_COUNT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+// packs files into a local tar.zst, returning its path and what it contains
+fn create_archive(
+    source_folder: &str,
+    files: &[PathBuf],
+    level: Option<i32>,
+) -> std::io::Result<(PathBuf, Vec<UploadedFile>)> {
+    let n = ARCHIVE_COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let file_name = format!("deploy-{}-{}.tar.zst", std::process::id(), n);
+    let archive_path = std::env::temp_dir().join(file_name);
+    let archive = std::fs::File::create(&archive_path)?;
+    let level = level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL);
+    let mut builder = tar::Builder::new(zstd::stream::write::Encoder::new(archive, level)?);
+    let mut contents = Vec::new();
+    for path in files {
+        let relative_path = path.strip_prefix(source_folder).unwrap();
+        builder.append_path_with_name(path, relative_path)?;
+        contents.push(UploadedFile {
+            path: relative_path.to_string_lossy().to_string(),
+            bytes: std::fs::metadata(path)?.len(),
+            sha256: hash_file(path)?,
+        });
+    }
+    builder.into_inner()?.finish()?;
+    Ok((archive_path, contents))
+}
+
+// uploads the files as a single archive to target_folder/ARCHIVE_NAME, to be extracted there
+pub async fn upload_archive(
+    server: &Server,
+    logger: &mut Logger,
+    sftp: &mut SftpSession,
+    source_folder: &String,
+    target_folder: &String,
+    files: Vec<PathBuf>,
+    options: TransferOptions,
+) -> Result<Vec<UploadedFile>, String> {
+    let source_folder = start_upload(logger, source_folder, target_folder).await;
+    let target_folder = expand_server_path(target_folder, &server.user);
+    if files.is_empty() {
+        return Ok(Vec::new());
+    }
+    let (archive_path, contents) =
+        match create_archive(&source_folder, &files, options.compression_level) {
+            Ok(archive) => archive,
+            Err(err) => {
+                logger.stop_files_display().await;
+                return Err(format!("Unable to create archive: {}", err));
+            }
+        };
+    let archive_size = std::fs::metadata(&archive_path).map_or(0, |metadata| metadata.len());
+    let mut progress = Progress::new(archive_size, contents.len(), logger);
+    progress.draw(None);
+
+    let mut sent = 0;
+    let on_chunk = |file_sent: u64, total_size: u64| {
+        progress.update_file(sent, file_sent);
+        sent = file_sent;
+        progress.redraw(Some((ARCHIVE_NAME, sent, total_size)));
+    };
+    let target_path = Path::new(&target_folder).join(ARCHIVE_NAME);
+    // the archive is already compressed
+    let options = TransferOptions {
+        compression: None,
+        ..options
+    };
+    let throttle = Throttle::new(options.max_bandwidth);
+    let result = transfer_with_retries(
+        server,
+        sftp,
+        &archive_path,
+        &target_path,
+        options,
+        &throttle,
+        on_chunk,
+    )
+    .await;
+    if let Err(err) = std::fs::remove_file(&archive_path) {
+        console!(
+            "{} Unable to remove the local archive: {}",
+            THEME.warning_label("Warning:"),
+            err
+        );
+    }
+    if let Err(err) = result {
+        logger.stop_files_display().await;
+        return Err(err);
     }
+    for file in &contents {
+        progress.file_done();
+        logger.add_uploaded_file(file.path.to_owned()).await;
+    }
+    progress.draw(None);
     logger.stop_files_display().await;
+    Ok(contents)
+}
+
+// the mode of secret files, set before any of the contents is written
+const SECRET_MODE: u32 = 0o600;
+
+// copies path to target_path, readable by the ssh user only, as a whole and uncompressed
+async fn transfer_secret(
+    sftp: &mut SftpSession,
+    path: &Path,
+    target_path: &Path,
+) -> Result<UploadedFile, String> {
+    if let Err(err) = ensure_directory_exists(sftp, &target_path.to_path_buf()).await {
+        return Err(format!("Failed to ensure directory exists: {}", err));
+    }
+    let contents = std::fs::read(path)
+        .map_err(|err| format!("Unable to read source file {}: {}", path.display(), err))?;
+    let target_path = target_path.to_string_lossy().to_string();
+    let attributes = FileAttributes {
+        permissions: Some(SECRET_MODE),
+        ..Default::default()
+    };
+    let mut target_file = sftp
+        .open_with_flags_and_attributes(
+            target_path.to_owned(),
+            OpenFlags::CREATE | OpenFlags::TRUNCATE | OpenFlags::WRITE,
+            attributes.clone(),
+        )
+        .await
+        .map_err(|err| err.to_string())?;
+    // the mode given on open only applies to a new file, and is reduced by the umask
+    sftp.set_metadata(target_path, attributes)
+        .await
+        .map_err(|err| err.to_string())?;
+    target_file
+        .write_all(&contents)
+        .await
+        .map_err(|err| err.to_string())?;
+    target_file
+        .sync_all()
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(UploadedFile {
+        path: String::new(),
+        bytes: contents.len() as u64,
+        sha256: format!("{:x}", Sha256::digest(&contents)),
+    })
+}
+
+// uploads the files matching secret_files with mode 0600, one at a time and left out of the
+// uploaded files display
+pub async fn upload_secret_files(
+    server: &Server,
+    logger: &mut Logger,
+    sftp: &mut SftpSession,
+    source_folder: &str,
+    target_folder: &str,
+    files: Vec<PathBuf>,
+    retry: Retry,
+) -> Vec<UploadedFile> {
+    let source_folder = expand_user_path(source_folder);
+    let target_folder = expand_server_path(target_folder, &server.user);
+    let mut uploaded_files = Vec::new();
+    for path in &files {
+        let relative_path = path.strip_prefix(&source_folder).unwrap();
+        let target_path = Path::new(&target_folder).join(relative_path);
+        let mut attempt = 0;
+        let result = loop {
+            match transfer_secret(sftp, path, &target_path).await {
+                Err(err) if attempt < retry.retries => {
+                    attempt += 1;
+                    let delay = retry.backoff(attempt);
+                    console!(
+                        "{} secret file: {}, retrying in {}s ({}/{})",
+                        THEME.warning_label("Retrying:"),
+                        err,
+                        delay.as_secs_f64(),
+                        attempt,
+                        retry.retries
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => break result,
+            }
+        };
+        match result {
+            Ok(mut file) => {
+                file.path = relative_path.to_string_lossy().to_string();
+                uploaded_files.push(file);
+            }
+            Err(err) => console!("{} secret file: {}", THEME.error_label("Error:"), err),
+        }
+    }
+    log!(
+        logger,
+        "{} {} of {} secret files, with mode {:o}",
+        "Uploaded:".bright_black(),
+        uploaded_files.len(),
+        files.len(),
+        SECRET_MODE
+    );
+    uploaded_files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_tuner_backs_off_below_the_concurrency_that_degrades() {
+        // 10MB/s per transfer up to 4, past which the server slows down, and fails files past 6
+        let server = |level: usize| match level {
+            1..=4 => (level as f64 * 10e6, 0),
+            5..=6 => ((8 - level) as f64 * 10e6, 0),
+            _ => (10e6, 3),
+        };
+        let mut tuner = Tuner::new(1, 8);
+        let mut levels = Vec::new();
+        for _ in 0..12 {
+            let (throughput, errors) = server(tuner.level);
+            levels.push(tuner.adjust(throughput, errors));
+        }
+        assert_eq!(levels[..5], [2, 3, 4, 5, 4]);
+        assert!(levels[5..].iter().all(|level| *level == 4));
+
+        // errors halve the concurrency right away
+        let mut tuner = Tuner::new(1, 16);
+        for _ in 0..7 {
+            tuner.adjust(tuner.level as f64 * 10e6, 0);
+        }
+        assert_eq!(tuner.level, 8);
+        assert_eq!(tuner.adjust(80e6, 2), 4);
+    }
+
+    #[test]
+    fn concurrency_over_the_open_files_limit_is_warned() {
+        let limits = "Limit                     Soft Limit           Hard Limit           Units
+Max processes             63429                63429                processes
+Max open files            100                  4096                 files
+";
+        let limit = parse_open_files_limit(limits).unwrap();
+        assert_eq!(limit, 100);
+        let warning = open_files_warning(200, limit).unwrap();
+        assert!(warning.contains("over the limit of 100"));
+        assert!(warning.ends_with("use at most 36"));
+        assert_eq!(open_files_warning(4, limit), None);
+        let unlimited = "Max open files            unlimited            unlimited            files";
+        let limit = parse_open_files_limit(unlimited).unwrap();
+        assert_eq!(open_files_warning(200, limit), None);
+    }
+
+    #[test]
+    fn eta_converges_on_a_steady_rate() {
+        // 1MB/s over 100MB, sampled every half second
+        let mut eta = Eta::new(100_000_000);
+        let mut now = eta.last_sample;
+        assert_eq!(eta.to_string(), "ETA --");
+        for _ in 0..20 {
+            now += Duration::from_millis(500);
+            eta.update_at(500_000, now);
+        }
+        assert_eq!(eta.remaining, 90_000_000);
+        assert_eq!(eta.to_string(), "ETA 00:01:30");
+    }
+
+    #[test]
+    fn eta_waits_for_enough_samples() {
+        let mut eta = Eta::new(10_000_000);
+        let mut now = eta.last_sample;
+        for _ in 1..ETA_MIN_SAMPLES {
+            now += ETA_SAMPLE_INTERVAL;
+            eta.update_at(1_000, now);
+        }
+        assert_eq!(eta.to_string(), "ETA --");
+        now += ETA_SAMPLE_INTERVAL;
+        eta.update_at(1_000, now);
+        assert_ne!(eta.to_string(), "ETA --");
+    }
 }
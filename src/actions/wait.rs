@@ -0,0 +1,82 @@
+use std::future::Future;
+use std::time::Duration;
+
+use russh::client;
+use tokio::time::{sleep, Instant};
+
+use super::commands::SimpleHandler;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// polls host:port through the ssh session until it accepts a connection, returns the attempts
+pub async fn wait_for_port(
+    session: &client::Handle<SimpleHandler>,
+    host: &str,
+    port: u16,
+    timeout: Duration,
+) -> Option<u32> {
+    let open = || async {
+        // the server opens the connection, so the port is checked from its side
+        match session
+            .channel_open_direct_tcpip(host, port.into(), "127.0.0.1", 0)
+            .await
+        {
+            Ok(channel) => {
+                let _ = channel.close().await;
+                true
+            }
+            Err(_) => false,
+        }
+    };
+    poll(open, POLL_INTERVAL, timeout).await
+}
+
+// calls open every interval until it succeeds, returns the attempts or None past the timeout
+async fn poll<F: Future<Output = bool>>(
+    mut open: impl FnMut() -> F,
+    interval: Duration,
+    timeout: Duration,
+) -> Option<u32> {
+    let start = Instant::now();
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        if open().await {
+            return Some(attempts);
+        }
+        if start.elapsed() + interval > timeout {
+            return None;
+        }
+        sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn the_port_is_open_on_the_third_poll() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let polls = Cell::new(0);
+        // closed twice, then open
+        let open = || {
+            polls.set(polls.get() + 1);
+            let open = polls.get() > 2;
+            async move { open }
+        };
+        let interval = Duration::from_millis(1);
+        let attempts = runtime.block_on(poll(open, interval, Duration::from_secs(5)));
+        assert_eq!(attempts, Some(3));
+        assert_eq!(polls.get(), 3);
+    }
+
+    #[test]
+    fn a_port_that_stays_closed_times_out() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let interval = Duration::from_millis(1);
+        let attempts = runtime.block_on(poll(|| async { false }, interval, interval * 3));
+        assert_eq!(attempts, None);
+    }
+}
@@ -0,0 +1,57 @@
+use crate::config::Server;
+use crate::theme::THEME;
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+const CACHE_PATH: &str = ".deploycache";
+
+// upload destination -> relative file path -> sha256 of what was last uploaded there
+pub type Cache = BTreeMap<String, BTreeMap<String, String>>;
+
+// the same folder on another host or as another user is a different destination
+pub fn key(server: &Server, target_folder: &str) -> String {
+    format!(
+        "{}@{}:{}:{}",
+        server.user, server.host, server.port, target_folder
+    )
+}
+
+// an unreadable cache only costs a full upload
+pub fn load() -> Cache {
+    if !Path::new(CACHE_PATH).exists() {
+        return Cache::new();
+    }
+    let cache = fs::read_to_string(CACHE_PATH)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| toml::from_str(&contents).map_err(|e| e.to_string()));
+    cache.unwrap_or_else(|e| {
+        eprintln!(
+            "{} ignoring {}: {}",
+            THEME.warning_label("Warning:"),
+            CACHE_PATH,
+            e
+        );
+        Cache::new()
+    })
+}
+
+// reloaded first, so deployments running at once don't drop each other's uploads
+pub fn update(key: String, hashes: BTreeMap<String, String>) -> io::Result<()> {
+    let mut cache = load();
+    cache.insert(key, hashes);
+    save(&cache)
+}
+
+// once the files of the destination were removed or replaced on the server
+pub fn forget(key: &str) -> io::Result<()> {
+    let mut cache = load();
+    if cache.remove(key).is_none() {
+        return Ok(());
+    }
+    save(&cache)
+}
+
+fn save(cache: &Cache) -> io::Result<()> {
+    let contents =
+        toml::to_string(cache).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(CACHE_PATH, contents)
+}
@@ -0,0 +1,70 @@
+use crate::theme::THEME;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+const CHECKPOINT_PATH: &str = ".deploycheckpoint";
+
+// what a failed deployment got done, for --resume to carry on from there
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Checkpoint {
+    // actions and hooks that succeeded
+    pub completed: Vec<String>,
+    // the release folder being filled, with the releases strategy
+    pub release: Option<String>,
+}
+
+// config path and host -> checkpoint of its last failed deployment
+type Checkpoints = BTreeMap<String, Checkpoint>;
+
+pub fn key(config_path: &str, host: &str) -> String {
+    format!("{}@{}", config_path, host)
+}
+
+// an unreadable file only costs deploying from the start
+fn load() -> Checkpoints {
+    if !Path::new(CHECKPOINT_PATH).exists() {
+        return Checkpoints::new();
+    }
+    let checkpoints = fs::read_to_string(CHECKPOINT_PATH)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| toml::from_str(&contents).map_err(|e| e.to_string()));
+    checkpoints.unwrap_or_else(|e| {
+        eprintln!(
+            "{} ignoring {}: {}",
+            THEME.warning_label("Warning:"),
+            CHECKPOINT_PATH,
+            e
+        );
+        Checkpoints::new()
+    })
+}
+
+fn save(checkpoints: &Checkpoints) -> io::Result<()> {
+    if checkpoints.is_empty() {
+        return match fs::remove_file(CHECKPOINT_PATH) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    let contents =
+        toml::to_string(checkpoints).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(CHECKPOINT_PATH, contents)
+}
+
+pub fn get(key: &str) -> Option<Checkpoint> {
+    load().remove(key)
+}
+
+pub fn record(key: &str, checkpoint: Checkpoint) -> io::Result<()> {
+    let mut checkpoints = load();
+    checkpoints.insert(key.to_string(), checkpoint);
+    save(&checkpoints)
+}
+
+pub fn forget(key: &str) -> io::Result<()> {
+    let mut checkpoints = load();
+    if checkpoints.remove(key).is_none() {
+        return Ok(());
+    }
+    save(&checkpoints)
+}
@@ -1,45 +1,1442 @@
-use serde::Deserialize;
-use std::fs;
+use crate::actions::backup;
+use crate::secrets;
+use crate::ssh_config;
+use crate::vault::Vault;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value as JsonValue;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, fs};
 
 macro_rules! pub_struct {
-    ($($derive:path),*; $name:ident {$($field:ident: $t:ty),* $(,)?}) => {
+    ($($derive:path),*; $name:ident {$($(#[$attr:meta])* $field:ident: $t:ty),* $(,)?}) => {
         #[derive($($derive),*)]
         pub struct $name {
-            $(pub $field: $t),*
+            $($(#[$attr])* pub $field: $t),*
         }
     }
 }
 
+// the settings load leaves out are taken from the Host entry of ~/.ssh/config matching host
 pub_struct!(Clone, Deserialize; Server {
     host: String,
     port : u16,
     user: String,
     ssh_key: String,
+    // bastions the connection is tunneled through in turn, `[user@]host[:port]` each, as a list or
+    // separated by commas like a ProxyJump
+    #[serde(default, deserialize_with = "deserialize_jump_host")]
+    jump_host: Vec<String>,
 });
 
+fn deserialize_jump_host<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum JumpHost {
+        Chain(String),
+        List(Vec<String>),
+    }
+    let hosts = match JumpHost::deserialize(deserializer)? {
+        JumpHost::Chain(chain) => chain.split(',').map(str::to_string).collect(),
+        JumpHost::List(hosts) => hosts,
+    };
+    Ok(hosts
+        .iter()
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .collect())
+}
+
+pub_struct!(Clone, Deserialize; Relay {
+    #[serde(flatten)]
+    server: Server,
+    // commands run on the relay after each upload, with {{path}}, {{target}},
+    // {{host}}, {{port}} and {{user}} describing the final server
+    fan_out: Vec<String>,
+});
+
+// an action runs only when all of these hold
+pub_struct!(Clone, Default, Deserialize; Conditions {
+    #[serde(default, deserialize_with = "deserialize_condition")]
+    only_if: Option<Condition>,
+    #[serde(default, deserialize_with = "deserialize_condition")]
+    unless: Option<Condition>,
+    // the `--env` profile the deployment was started with
+    only_if_env: Option<String>,
+});
+
+#[derive(Clone)]
+pub enum Condition {
+    // `changed(pattern)`: the deployment uploads a file matching this .deployignore pattern
+    Changed { pattern: String, matcher: Gitignore },
+    // any other text, a local shell command exiting with 0
+    Shell(String),
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Condition::Changed { pattern, .. } => write!(f, "changed({})", pattern),
+            Condition::Shell(command) => write!(f, "'{}'", command),
+        }
+    }
+}
+
 #[derive(Clone, Deserialize)]
 #[serde(tag = "type")]
 pub enum Action {
     #[serde(rename = "commands")]
-    Commands { name: String, commands: Vec<String> },
+    Commands {
+        name: String,
+        commands: Vec<String>,
+        // read-only commands `--dry-run` runs instead, like `systemctl is-active app` for a restart
+        #[serde(default)]
+        check: Vec<String>,
+        // a non-zero exit status is only a warning instead of stopping the deployment
+        #[serde(default)]
+        allow_failure: bool,
+        // attempts after a failed one, waiting retry_delay then twice as long each time
+        #[serde(default)]
+        retries: u32,
+        #[serde(default, deserialize_with = "deserialize_duration")]
+        retry_delay: Option<Duration>,
+        // the commands are killed once they run longer, each attempt on its own
+        #[serde(default, deserialize_with = "deserialize_duration")]
+        timeout: Option<Duration>,
+        // variables exported before the commands run, the values are quoted for the remote shell
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+        // steps to finish first, the previous action by default and none with `[]`
+        depends_on: Option<Vec<String>>,
+        #[serde(flatten)]
+        conditions: Conditions,
+    },
     #[serde(rename = "upload")]
     Upload {
         name: String,
         source_folder: String,
         target_folder: String,
+        #[serde(default)]
+        skip_empty_files: bool,
+        // regex matched against the first line of each file
+        skip_first_line: Option<String>,
+        // also leave out what the .gitignore files ignore
+        #[serde(default)]
+        use_gitignore: bool,
+        // uploaded files and their folders below the target are chowned to these on the server
+        remote_owner: Option<String>,
+        remote_group: Option<String>,
+        // attempts after a failed transfer of a file
+        #[serde(default)]
+        retries: u32,
+        #[serde(default, deserialize_with = "deserialize_duration")]
+        retry_delay: Option<Duration>,
+        // copy the target folder to ~/.deploy-backups before overwriting it, for --restore-backup
+        #[serde(default)]
+        backup: bool,
+        // files sent with mode 0600 and left out of the uploaded files display, .deployignore
+        // patterns relative to source_folder
+        #[serde(default)]
+        secret_files: Vec<String>,
+        // steps to finish first, the previous action by default and none with `[]`
+        depends_on: Option<Vec<String>>,
+        #[serde(flatten)]
+        conditions: Conditions,
     },
+    #[serde(rename = "wait_for_port")]
+    WaitForPort {
+        name: String,
+        // resolved by the server, so `localhost` is the server itself
+        #[serde(default = "default_wait_host")]
+        host: String,
+        port: u16,
+        // seconds before giving up
+        #[serde(default = "default_wait_timeout")]
+        timeout: u64,
+        // steps to finish first, the previous action by default and none with `[]`
+        depends_on: Option<Vec<String>>,
+        #[serde(flatten)]
+        conditions: Conditions,
+    },
+}
+
+impl Action {
+    pub fn name(&self) -> &str {
+        match self {
+            Action::Commands { name, .. }
+            | Action::Upload { name, .. }
+            | Action::WaitForPort { name, .. } => name,
+        }
+    }
+
+    pub fn conditions(&self) -> &Conditions {
+        match self {
+            Action::Commands { conditions, .. }
+            | Action::Upload { conditions, .. }
+            | Action::WaitForPort { conditions, .. } => conditions,
+        }
+    }
+
+    pub fn depends_on(&self) -> Option<&[String]> {
+        match self {
+            Action::Commands { depends_on, .. }
+            | Action::Upload { depends_on, .. }
+            | Action::WaitForPort { depends_on, .. } => depends_on.as_deref(),
+        }
+    }
+}
+
+// indices of the actions each action waits for
+pub fn action_dependencies(actions: &[Action]) -> Result<Vec<Vec<usize>>, String> {
+    let mut dependencies = Vec::new();
+    for (index, action) in actions.iter().enumerate() {
+        let Some(names) = action.depends_on() else {
+            dependencies.push(index.checked_sub(1).into_iter().collect());
+            continue;
+        };
+        let mut indices = Vec::new();
+        for name in names {
+            match actions.iter().position(|action| action.name() == name) {
+                Some(dependency) => indices.push(dependency),
+                None => {
+                    return Err(format!(
+                        "'{}' depends on '{}', which is no action",
+                        action.name(),
+                        name
+                    ))
+                }
+            }
+        }
+        dependencies.push(indices);
+    }
+    Ok(dependencies)
+}
+
+// the actions in an order where each comes after its dependencies, in config order otherwise
+pub fn action_order(actions: &[Action]) -> Result<Vec<usize>, String> {
+    let dependencies = action_dependencies(actions)?;
+    let mut order: Vec<usize> = Vec::new();
+    while order.len() < actions.len() {
+        let next = (0..actions.len()).find(|index| {
+            !order.contains(index)
+                && dependencies[*index]
+                    .iter()
+                    .all(|dependency| order.contains(dependency))
+        });
+        match next {
+            Some(index) => order.push(index),
+            None => {
+                let cycle: Vec<&str> = (0..actions.len())
+                    .filter(|index| !order.contains(index))
+                    .map(|index| actions[index].name())
+                    .collect();
+                return Err(format!(
+                    "actions depend on each other: {}",
+                    cycle.join(", ")
+                ));
+            }
+        }
+    }
+    Ok(order)
+}
+
+// steps that aren't actions, named like their command lists
+pub const HOOKS: [&str; 4] = [
+    "pre_deploy_local",
+    "pre_deploy_remote",
+    "post_deploy_remote",
+    "post_deploy_local",
+];
+
+fn default_wait_host() -> String {
+    "localhost".to_string()
+}
+
+fn default_wait_timeout() -> u64 {
+    30
+}
+
+// files transferred at once: a number, or "auto" to adapt it to the throughput
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Concurrency {
+    Fixed(usize),
+    Auto,
+}
+
+impl Concurrency {
+    // the sftp channels opened for the upload, every one the tuner can use with "auto"
+    pub fn channels(self, max: usize) -> usize {
+        match self {
+            Concurrency::Fixed(concurrency) => concurrency.max(1),
+            Concurrency::Auto => max.max(1),
+        }
+    }
+}
+
+fn default_upload_concurrency() -> Concurrency {
+    Concurrency::Fixed(1)
+}
+
+fn default_upload_concurrency_min() -> usize {
+    1
+}
+
+fn default_upload_concurrency_max() -> usize {
+    8
+}
+
+fn deserialize_concurrency<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Concurrency, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Fixed(usize),
+        Named(String),
+    }
+    match Value::deserialize(deserializer)? {
+        Value::Fixed(concurrency) => Ok(Concurrency::Fixed(concurrency)),
+        Value::Named(name) if name == "auto" => Ok(Concurrency::Auto),
+        Value::Named(name) => Err(serde::de::Error::custom(format!(
+            "expected a number or \"auto\", got '{}'",
+            name
+        ))),
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogDurability {
+    // flush after each write
+    #[default]
+    Fast,
+    // also fsync after each flush
+    Synced,
+}
+
+// which logs of .deployments are kept after each run, the others are removed or compressed
+pub_struct!(Clone, Deserialize; LogRetention {
+    // most recent logs kept
+    keep: Option<usize>,
+    // logs last written longer ago are dropped
+    max_age_days: Option<u64>,
+    // gzip the dropped logs into .deployments/archive instead of removing them
+    #[serde(default)]
+    compress: bool,
+});
+
+// polled from this machine once the remote steps finished, the deployment fails if it never passes
+pub_struct!(Clone, Deserialize; Healthcheck {
+    // answered with expect_status, and a body containing expect_body when set
+    #[serde(default, deserialize_with = "deserialize_url")]
+    url: Option<HttpUrl>,
+    #[serde(default = "default_expect_status")]
+    expect_status: u16,
+    expect_body: Option<String>,
+    // accepting tcp connections instead, on host or else the server
+    port: Option<u16>,
+    host: Option<String>,
+    // 60s by default
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    timeout: Option<Duration>,
+    // between attempts, 2s by default
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    interval: Option<Duration>,
+    // with the releases strategy, put the previous release back and run on_rollback when it fails
+    #[serde(default = "default_auto_rollback")]
+    rollback: bool,
+});
+
+// what `--status` reports on, any of them
+pub_struct!(Clone, Deserialize; StatusCheck {
+    // whether the unit is active and since when, from `systemctl show`
+    systemd_unit: Option<String>,
+    // whether the container is running and since when, from `docker inspect`
+    docker_container: Option<String>,
+    // requested once from this machine, the service is up when it answers with expect_status
+    #[serde(default, deserialize_with = "deserialize_url")]
+    url: Option<HttpUrl>,
+    #[serde(default = "default_expect_status")]
+    expect_status: u16,
+});
+
+// an encrypted file whose values are variables like those of [vars], decrypted in memory by load
+pub_struct!(Clone, Deserialize; Secrets {
+    // decrypted by age when it ends with .age, which then reads .json, .yaml and dotenv contents by
+    // the extension before it, and by sops otherwise
+    file: String,
+    // age key file, required for age and given to sops as SOPS_AGE_KEY_FILE
+    identity: Option<String>,
+});
+
+fn default_expect_status() -> u16 {
+    200
+}
+
+// the oldest protocol the https requests accept
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+pub enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+fn default_require_https() -> bool {
+    true
+}
+
+// refuses a plain http url, unless require_https = false
+pub fn check_https(url: &HttpUrl, require_https: bool, used_by: &str) -> Result<(), String> {
+    if url.https || !require_https {
+        return Ok(());
+    }
+    Err(format!(
+        "'{}' of {} isn't https, set require_https = false to allow plain http",
+        url, used_by
+    ))
+}
+
+fn default_auto_rollback() -> bool {
+    true
+}
+
+#[derive(Clone)]
+pub struct HttpUrl {
+    pub https: bool,
+    pub host: String,
+    pub port: u16,
+    // with the query, "/" when the url has none
+    pub path: String,
+}
+
+impl fmt::Display for HttpUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scheme = if self.https { "https" } else { "http" };
+        write!(f, "{}://{}:{}{}", scheme, self.host, self.port, self.path)
+    }
+}
+
+// http://host[:port][/path] or https://
+pub fn parse_url(url: &str) -> Result<HttpUrl, String> {
+    let (https, rest) = if let Some(rest) = url.strip_prefix("https://") {
+        (true, rest)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        (false, rest)
+    } else {
+        return Err(format!(
+            "expected an http:// or https:// url, got '{}'",
+            url
+        ));
+    };
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(start) => (&rest[..start], &rest[start..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port
+                .parse()
+                .map_err(|_| format!("invalid port in '{}'", url))?;
+            (host, port)
+        }
+        None => (authority, if https { 443 } else { 80 }),
+    };
+    if host.is_empty() {
+        return Err(format!("no host in '{}'", url));
+    }
+    let path = match path.strip_prefix('?') {
+        Some(query) => format!("/?{}", query),
+        None => path.to_string(),
+    };
+    Ok(HttpUrl {
+        https,
+        host: host.to_string(),
+        port,
+        path,
+    })
+}
+
+fn deserialize_url<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<HttpUrl>, D::Error> {
+    let url: Option<String> = Option::deserialize(deserializer)?;
+    url.map(|url| parse_url(&url))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+// with [[hosts]], the first hosts are deployed alone, then the run pauses before the others
+pub_struct!(Clone, Deserialize; Canary {
+    #[serde(default = "default_canary_first")]
+    first: usize,
+    // "manual" to wait for a confirmation, or a duration like "30s"
+    #[serde(deserialize_with = "deserialize_pause")]
+    pause: Pause,
+});
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pause {
+    Manual,
+    Delay(Duration),
+}
+
+fn default_canary_first() -> usize {
+    1
+}
+
+fn deserialize_pause<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pause, D::Error> {
+    let pause = String::deserialize(deserializer)?;
+    match pause.as_str() {
+        "manual" => Ok(Pause::Manual),
+        delay => parse_duration(delay)
+            .map(Pause::Delay)
+            .map_err(|_| {
+                format!(
+                    "expected \"manual\" or a duration like 30s, got '{}'",
+                    delay
+                )
+            })
+            .map_err(serde::de::Error::custom),
+    }
+}
+
+// what `--watch` reruns once the uploaded files changed, besides the uploads
+pub_struct!(Clone, Deserialize; Watch {
+    // actions and hooks, like a restart
+    #[serde(default)]
+    reload: Vec<String>,
+    // quiet time after the last change before deploying, so a burst of changes deploys once
+    #[serde(default = "default_watch_debounce")]
+    debounce_ms: u64,
+});
+
+fn default_watch_debounce() -> u64 {
+    300
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    // only the plain text deployment_N.txt
+    #[default]
+    Text,
+    // also line-delimited json events in deployment_N.jsonl
+    Json,
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoteShell {
+    Sh,
+    Bash,
+    Fish,
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferMode {
+    // every file is sent on its own
+    #[default]
+    Files,
+    // the files are sent as one tar.zst, extracted on the remote host
+    Archive,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    // uploads overwrite the target folders
+    #[default]
+    InPlace,
+    // uploads go to a new release folder, `current` points to it once all actions succeeded
+    Releases,
 }
 
 pub_struct!(Clone, Deserialize; Config {
     server: Server,
+    // servers deployed one after the other, instead of [server]
+    #[serde(default)]
+    hosts: Vec<Server>,
+    canary: Option<Canary>,
     actions: Vec<Action>,
+    // uploads go to this host, which forwards them to the server
+    relay: Option<Relay>,
+    #[serde(default)]
+    log_durability: LogDurability,
+    // folder of the logs, .deployments by default
+    log_dir: Option<String>,
+    // log file name with {{n}}, {{date}}, {{env}} and {{kind}}, "{{kind}}_{{n}}.txt" by default
+    log_name: Option<String>,
+    #[serde(default)]
+    log_format: LogFormat,
+    log_retention: Option<LogRetention>,
+    // lines of remote output shown by the interactive console, half the terminal by default
+    console_height: Option<usize>,
+    // files transferred at once, over as many sftp channels
+    #[serde(
+        default = "default_upload_concurrency",
+        deserialize_with = "deserialize_concurrency"
+    )]
+    upload_concurrency: Concurrency,
+    // the bounds of upload_concurrency = "auto"
+    #[serde(default = "default_upload_concurrency_min")]
+    upload_concurrency_min: usize,
+    #[serde(default = "default_upload_concurrency_max")]
+    upload_concurrency_max: usize,
+    #[serde(default)]
+    transfer_mode: TransferMode,
+    // uploads are sent compressed and decompressed on the remote host, which needs gzip or zstd
+    compression: Option<Compression>,
+    // 0 to 9 for gzip, 1 to 22 for zstd
+    compression_level: Option<i32>,
+    // bytes per second over all the transfers of an upload, like "5MB/s"
+    #[serde(default, deserialize_with = "deserialize_rate")]
+    max_bandwidth: Option<u64>,
+    // regexes whose matches are masked in the console and the log file
+    #[serde(default)]
+    redact: Vec<String>,
+    // remote command run before any action: 0 proceeds, 2 skips the deployment, other fails
+    preflight: Option<String>,
+    // other configs to deploy first, relative to this file
+    #[serde(default)]
+    depends_on: Vec<String>,
+    // login shell of the remote hosts, values substituted into commands are quoted for it
+    remote_shell: Option<RemoteShell>,
+    #[serde(default)]
+    strategy: Strategy,
+    // server folder holding releases/ and the current link, required by the releases strategy
+    base_path: Option<String>,
+    // releases left on the server after a successful deployment, the oldest are removed
+    keep_releases: Option<usize>,
+    // paths of each release linked to base_path/shared, so they outlive the release
+    #[serde(default)]
+    linked_files: Vec<String>,
+    #[serde(default)]
+    linked_dirs: Vec<String>,
+    // remote commands run after `--rollback` or an unhealthy release switched the current link
+    // back, to restart the service on it
+    #[serde(default)]
+    on_rollback: Vec<String>,
+    // what `--logs` shows: a command following a log like `journalctl -u app -f`, or the path of a
+    // log file starting with / or ~/, which is tailed
+    remote_log: Option<String>,
+    // server files `--delete` never removes, .deployignore patterns relative to each target folder
+    #[serde(default)]
+    protected_paths: Vec<String>,
+    // run on this machine before the files are resolved, and after a successful deployment
+    #[serde(default)]
+    pre_deploy_local: Vec<String>,
+    #[serde(default)]
+    post_deploy_local: Vec<String>,
+    // run on the server after the preflight, and after a successful deployment
+    #[serde(default)]
+    pre_deploy_remote: Vec<String>,
+    #[serde(default)]
+    post_deploy_remote: Vec<String>,
+    watch: Option<Watch>,
+    healthcheck: Option<Healthcheck>,
+    status: Option<StatusCheck>,
+    // http:// urls are refused, in [healthcheck], [status] and VAULT_ADDR
+    #[serde(default = "default_require_https")]
+    require_https: bool,
+    #[serde(default)]
+    min_tls: TlsVersion,
+    // values of [secrets], vault and 1Password, masked like the redact matches
+    #[serde(skip)]
+    secret_values: Vec<String>,
+    // folder of this config's backups in ~/.deploy-backups, so restoring never touches the targets
+    // of another config deploying to the same user
+    #[serde(skip)]
+    backup_key: String,
 });
 
-pub fn load(config_path: &str) -> Config {
-    let file_contents = fs::read_to_string(config_path).expect("error: unable to read file");
+// what load fills into the config files besides their own contents
+#[derive(Clone, Copy, Default)]
+pub struct LoadOptions<'a> {
+    // `--data` file
+    pub data: Option<&'a JsonValue>,
+    // `--env` profile, taken from the [env.<name>] table
+    pub env: Option<&'a str>,
+    // `--set` values, which take precedence over [vars]
+    pub set: &'a [(String, String)],
+}
+
+lazy_static! {
+    static ref DATA_VARIABLE: Regex = Regex::new(r"\{\{\s*data\.([\w.-]+)\s*\}\}").unwrap();
+    // `$${NAME}` is kept as a literal `${NAME}` for the remote shell
+    static ref VARIABLE: Regex = Regex::new(r"\$(\$?)\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap();
+    // `${vault:<path>#<key>}`, escaped the same way
+    static ref VAULT_VARIABLE: Regex = Regex::new(r"\$(\$?)\{vault:([^#}]+)#([^}]+)\}").unwrap();
+}
+
+pub fn load(config_path: &str, options: LoadOptions) -> Result<Config, String> {
+    let file_contents =
+        fs::read_to_string(config_path).map_err(|e| format!("unable to read file: {}", e))?;
+
+    let mut value: toml::Value = toml::from_str(&file_contents)
+        .map_err(|e| format!("unable to deserialize config: {}", e))?;
+    apply_env(&mut value, options.env)?;
+    let mut vars = match value.as_table_mut().and_then(|table| table.remove("vars")) {
+        Some(toml::Value::Table(vars)) => vars,
+        Some(_) => return Err("vars must be a table".to_string()),
+        None => toml::Table::new(),
+    };
+    for (name, set) in options.set {
+        vars.insert(name.to_owned(), toml::Value::String(set.to_owned()));
+    }
+    // the secrets fill in the names [vars] and `--set` leave undefined
+    let mut secret_values = Vec::new();
+    let section = value
+        .as_table_mut()
+        .and_then(|table| table.remove("secrets"));
+    if let Some(mut section) = section {
+        substitute_vars(&mut section, &vars)?;
+        let section: Secrets = section
+            .try_into()
+            .map_err(|e| format!("invalid secrets: {}", e))?;
+        for (name, secret) in secrets::decrypt(&section)? {
+            if !secret.is_empty() {
+                secret_values.push(secret.clone());
+            }
+            vars.entry(name).or_insert(toml::Value::String(secret));
+        }
+    }
+    substitute_vars(&mut value, &vars)?;
+    // after the variables, which can make up the paths, so the vault values are left as they are
+    let setting = |key| value.as_table().and_then(|table| table.get(key)).cloned();
+    let require_https = setting("require_https").and_then(|value| value.as_bool());
+    let min_tls = setting("min_tls")
+        .map(TlsVersion::deserialize)
+        .transpose()
+        .map_err(|e| format!("invalid min_tls: {}", e))?;
+    let mut vault = Vault::new(require_https.unwrap_or(true), min_tls.unwrap_or_default());
+    substitute_vault(&mut value, &mut vault)?;
+    secret_values.extend(vault.values);
+    let mut op_fields = HashMap::new();
+    substitute_op_references(&mut value, &mut op_fields)?;
+    secret_values.extend(op_fields.into_values().filter(|field| !field.is_empty()));
+    if let Some(data) = options.data {
+        substitute_data(&mut value, data)?;
+    }
+    if let Some(table) = value.as_table_mut() {
+        let connection = match table.remove("connection") {
+            Some(toml::Value::Table(connection)) => connection,
+            Some(_) => return Err("connection must be a table".to_string()),
+            None => toml::Table::new(),
+        };
+        if let Some(hosts) = table.get_mut("hosts").and_then(toml::Value::as_array_mut) {
+            for host in hosts {
+                complete_server(host, &connection);
+            }
+        }
+        if let Some(relay) = table.get_mut("relay") {
+            // the relay is what the servers are reached through, not one of them
+            let mut connection = connection.clone();
+            connection.remove("jump_host");
+            complete_server(relay, &connection);
+        }
+        if let Some(server) = table.get_mut("server") {
+            complete_server(server, &connection);
+        }
+        // the first host stands in for [server] until the config is split per host
+        let first_host = table
+            .get("hosts")
+            .and_then(|hosts| hosts.as_array())
+            .and_then(|hosts| hosts.first())
+            .cloned();
+        if let Some(first_host) = first_host {
+            if table.contains_key("server") {
+                return Err("set either [server] or [[hosts]], not both".to_string());
+            }
+            table.insert("server".to_string(), first_host);
+        }
+    }
 
-    let config: Config =
-        toml::from_str(&file_contents).expect("error: unable to deserialize config");
+    let mut config: Config = value
+        .try_into()
+        .map_err(|e| format!("unable to deserialize config: {}", e))?;
+    config.secret_values = secret_values;
+    config.backup_key = backup::config_key(config_path);
+    let healthcheck_url = config.healthcheck.as_ref().and_then(|h| h.url.as_ref());
+    let status_url = config.status.as_ref().and_then(|s| s.url.as_ref());
+    for (url, used_by) in [(healthcheck_url, "[healthcheck]"), (status_url, "[status]")] {
+        if let Some(url) = url {
+            check_https(url, config.require_https, used_by)?;
+        }
+    }
+    let (min, max) = (config.upload_concurrency_min, config.upload_concurrency_max);
+    if config.upload_concurrency == Concurrency::Auto && (min == 0 || min > max) {
+        return Err(
+            "upload_concurrency_min must be at least 1 and at most upload_concurrency_max"
+                .to_string(),
+        );
+    }
+    if config.strategy == Strategy::Releases && config.base_path.is_none() {
+        return Err("strategy \"releases\" requires a base_path".to_string());
+    }
+    match config.keep_releases {
+        Some(_) if config.strategy != Strategy::Releases => {
+            return Err("keep_releases requires strategy = \"releases\"".to_string());
+        }
+        Some(0) => return Err("keep_releases must be at least 1".to_string()),
+        _ => {}
+    }
+    let linked = !config.linked_files.is_empty() || !config.linked_dirs.is_empty();
+    if linked && config.strategy != Strategy::Releases {
+        return Err("linked_files and linked_dirs require strategy = \"releases\"".to_string());
+    }
+    let backup = config
+        .actions
+        .iter()
+        .any(|action| matches!(action, Action::Upload { backup: true, .. }));
+    if backup && config.strategy == Strategy::Releases {
+        return Err("backup isn't needed with strategy = \"releases\", use --rollback".to_string());
+    }
+    if backup && config.relay.is_some() {
+        return Err("backup isn't supported for uploads through a relay".to_string());
+    }
+    protected_matcher(&config.protected_paths)?;
+    for action in &config.actions {
+        if let Action::Upload { secret_files, .. } = action {
+            secret_matcher(secret_files)?;
+        }
+    }
+    for action in &config.actions {
+        let Action::Commands { name, env, .. } = action else {
+            continue;
+        };
+        if let Some(variable) = env.keys().find(|variable| !is_variable_name(variable)) {
+            return Err(format!(
+                "invalid env variable name '{}' in '{}'",
+                variable, name
+            ));
+        }
+    }
+    action_order(&config.actions)?;
+    if let Some(healthcheck) = &config.healthcheck {
+        if healthcheck.url.is_some() == healthcheck.port.is_some() {
+            return Err("healthcheck needs either a url or a port".to_string());
+        }
+    }
+    if let Some(status) = &config.status {
+        let service = status.systemd_unit.is_some() || status.docker_container.is_some();
+        if !service && status.url.is_none() {
+            return Err("status needs a systemd_unit, a docker_container or a url".to_string());
+        }
+    }
+    for step in config.watch.iter().flat_map(|watch| &watch.reload) {
+        let known = config.actions.iter().any(|action| action.name() == step);
+        let healthcheck = step == "healthcheck" && config.healthcheck.is_some();
+        if !known && !healthcheck && !HOOKS.contains(&step.as_str()) {
+            return Err(format!(
+                "watch reloads '{}', which is no action or hook",
+                step
+            ));
+        }
+    }
+    Ok(config)
+}
 
+// one config per host, each with its host as the server
+pub fn for_each_host(config: Config) -> Vec<Config> {
+    if config.hosts.is_empty() {
+        return vec![config];
+    }
     config
+        .hosts
+        .iter()
+        .map(|host| Config {
+            server: host.clone(),
+            ..config.clone()
+        })
+        .collect()
+}
+
+// loads config_path and its depends_on configs, dependencies first
+pub fn load_with_dependencies(
+    config_path: &str,
+    options: LoadOptions,
+) -> Result<Vec<(String, Config)>, String> {
+    let mut ordered = Vec::new();
+    visit_dependencies(
+        PathBuf::from(config_path),
+        options,
+        &mut Vec::new(),
+        &mut HashSet::new(),
+        &mut ordered,
+    )?;
+    Ok(ordered)
+}
+
+fn visit_dependencies(
+    config_path: PathBuf,
+    options: LoadOptions,
+    visiting: &mut Vec<PathBuf>,
+    visited: &mut HashSet<PathBuf>,
+    ordered: &mut Vec<(String, Config)>,
+) -> Result<(), String> {
+    let canonical = fs::canonicalize(&config_path)
+        .map_err(|e| format!("unable to read '{}': {}", config_path.display(), e))?;
+    if visited.contains(&canonical) {
+        return Ok(());
+    }
+    if let Some(start) = visiting.iter().position(|path| *path == canonical) {
+        let cycle: Vec<String> = visiting[start..]
+            .iter()
+            .chain([&canonical])
+            .map(|path| path.display().to_string())
+            .collect();
+        return Err(format!("dependency cycle: {}", cycle.join(" -> ")));
+    }
+
+    let path = config_path.to_string_lossy().to_string();
+    let config = load(&path, options).map_err(|e| format!("{}: {}", path, e))?;
+    visiting.push(canonical.clone());
+    let parent = config_path.parent().unwrap_or(Path::new("")).to_path_buf();
+    for dependency in &config.depends_on {
+        visit_dependencies(parent.join(dependency), options, visiting, visited, ordered)?;
+    }
+    visiting.pop();
+
+    visited.insert(canonical);
+    ordered.push((path, config));
+    Ok(())
+}
+
+// parses a `--set name=value` argument
+pub fn parse_assignment(assignment: &str) -> Result<(String, String), String> {
+    match assignment.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected name=value, got '{}'", assignment)),
+    }
+}
+
+// "5MB/s", "500K" or "100000" in bytes per second, the units are powers of 1024
+pub fn parse_rate(rate: &str) -> Result<u64, String> {
+    let invalid = || format!("expected a rate like 5MB/s, got '{}'", rate);
+    let amount = rate.trim();
+    let amount = amount.strip_suffix("/s").unwrap_or(amount);
+    let unit_start = amount
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(amount.len());
+    let (number, unit) = amount.split_at(unit_start);
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        _ => return Err(invalid()),
+    };
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let rate = (number * multiplier as f64) as u64;
+    if rate == 0 {
+        return Err(invalid());
+    }
+    Ok(rate)
+}
+
+fn deserialize_rate<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    let rate: Option<String> = Option::deserialize(deserializer)?;
+    rate.map(|rate| parse_rate(&rate))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+// a number of ms, s, m or h like "10s" or "1h30m", plain numbers are seconds
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let invalid = || format!("expected a duration like 10s or 5m, got '{}'", duration);
+    let mut rest = duration.trim();
+    if !rest.is_empty() && rest.chars().all(|c| c.is_ascii_digit()) {
+        return rest.parse().map(Duration::from_secs).map_err(|_| invalid());
+    }
+    let mut total = Duration::ZERO;
+    while !rest.is_empty() {
+        let unit_start = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let (number, tail) = rest.split_at(unit_start);
+        let number: u64 = number.parse().map_err(|_| invalid())?;
+        let unit_end = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_end);
+        total += match unit {
+            "ms" => Duration::from_millis(number),
+            "s" => Duration::from_secs(number),
+            "m" => Duration::from_secs(number * 60),
+            "h" => Duration::from_secs(number * 60 * 60),
+            _ => return Err(invalid()),
+        };
+        rest = tail;
+    }
+    if total.is_zero() {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    let duration: Option<String> = Option::deserialize(deserializer)?;
+    duration
+        .map(|duration| parse_duration(&duration))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+// patterns are matched against paths relative to the working directory
+pub fn parse_condition(condition: &str) -> Result<Condition, String> {
+    let condition = condition.trim();
+    let Some(pattern) = condition
+        .strip_prefix("changed(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    else {
+        return Ok(Condition::Shell(condition.to_string()));
+    };
+    let mut builder = GitignoreBuilder::new("");
+    builder
+        .add_line(None, pattern)
+        .and_then(|builder| builder.build())
+        .map(|matcher| Condition::Changed {
+            pattern: pattern.to_string(),
+            matcher,
+        })
+        .map_err(|e| format!("invalid pattern in '{}': {}", condition, e))
+}
+
+// letters, digits and _, not starting with a digit
+pub fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+pub fn protected_matcher(protected_paths: &[String]) -> Result<Gitignore, String> {
+    path_matcher(protected_paths, "protected path")
+}
+
+pub fn secret_matcher(secret_files: &[String]) -> Result<Gitignore, String> {
+    path_matcher(secret_files, "secret file")
+}
+
+fn path_matcher(patterns: &[String], kind: &str) -> Result<Gitignore, String> {
+    let mut builder = GitignoreBuilder::new("");
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .map_err(|e| format!("invalid {} '{}': {}", kind, pattern, e))?;
+    }
+    builder
+        .build()
+        .map_err(|e| format!("invalid {}s: {}", kind, e))
+}
+
+fn deserialize_condition<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Condition>, D::Error> {
+    let condition: Option<String> = Option::deserialize(deserializer)?;
+    condition
+        .map(|condition| parse_condition(&condition))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+// reads the `--data` file, json or yaml depending on its extension
+pub fn load_data(data_path: &str) -> Result<JsonValue, String> {
+    let contents =
+        fs::read_to_string(data_path).map_err(|e| format!("unable to read data file: {}", e))?;
+    match Path::new(data_path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+    {
+        Some("yaml") | Some("yml") => {
+            serde_yaml::from_str(&contents).map_err(|e| format!("unable to parse data file: {}", e))
+        }
+        _ => {
+            serde_json::from_str(&contents).map_err(|e| format!("unable to parse data file: {}", e))
+        }
+    }
+}
+
+// fills in what a server table leaves out from [connection], then from ~/.ssh/config, with port 22
+// by default, and replaces a host alias by the HostName it stands for
+fn complete_server(server: &mut toml::Value, connection: &toml::Table) {
+    let Some(server) = server.as_table_mut() else {
+        return;
+    };
+    for (key, setting) in [
+        ("port", "port"),
+        ("user", "user"),
+        ("identity_file", "ssh_key"),
+        ("jump_host", "jump_host"),
+    ] {
+        if let Some(value) = connection.get(key) {
+            server.entry(setting).or_insert(value.clone());
+        }
+    }
+    let Some(alias) = server.get("host").and_then(|host| host.as_str()) else {
+        return;
+    };
+    let host = ssh_config::lookup(alias);
+    if let Some(hostname) = host.hostname {
+        server.insert("host".to_string(), toml::Value::String(hostname));
+    }
+    let port = toml::Value::Integer(host.port.unwrap_or(22).into());
+    let settings = [
+        ("user", host.user.map(toml::Value::String)),
+        ("port", Some(port)),
+        ("ssh_key", host.identity_file.map(toml::Value::String)),
+        ("jump_host", host.proxy_jump.map(toml::Value::String)),
+    ];
+    for (key, value) in settings {
+        if let Some(value) = value {
+            server.entry(key).or_insert(value);
+        }
+    }
+}
+
+// merges the [env.<name>] table over the rest of the config, configs without [env] are left as is
+fn apply_env(value: &mut toml::Value, env: Option<&str>) -> Result<(), String> {
+    let Some(table) = value.as_table_mut() else {
+        return Ok(());
+    };
+    let Some(envs) = table.remove("env") else {
+        return Ok(());
+    };
+    let Some(env) = env else {
+        return Ok(());
+    };
+    match envs.get(env) {
+        Some(overrides) => {
+            merge(value, overrides.clone());
+            Ok(())
+        }
+        None => Err(format!("no [env.{}] table", env)),
+    }
+}
+
+// tables are merged key by key, any other value is replaced
+fn merge(base: &mut toml::Value, overrides: toml::Value) {
+    match (base, overrides) {
+        (toml::Value::Table(base), toml::Value::Table(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+// replaces ${NAME} by the [vars] entry or, failing that, the local environment variable
+fn substitute_vars(value: &mut toml::Value, vars: &toml::Table) -> Result<(), String> {
+    match value {
+        toml::Value::String(s) => {
+            let mut missing = None;
+            let replaced = VARIABLE.replace_all(s, |caps: &Captures| {
+                if !caps[1].is_empty() {
+                    return format!("${{{}}}", &caps[2]);
+                }
+                match vars.get(&caps[2]) {
+                    Some(toml::Value::String(found)) => found.to_owned(),
+                    Some(found) => found.to_string(),
+                    None => env::var(&caps[2]).unwrap_or_else(|_| {
+                        missing.get_or_insert_with(|| caps[2].to_string());
+                        String::new()
+                    }),
+                }
+            });
+            if let Some(name) = missing {
+                return Err(format!(
+                    "'${{{}}}' is not defined in [vars] nor in the environment",
+                    name
+                ));
+            }
+            *s = replaced.into_owned();
+        }
+        toml::Value::Array(values) => {
+            for value in values {
+                substitute_vars(value, vars)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                substitute_vars(value, vars)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// replaces ${vault:<path>#<key>} by the key of the vault secret at path
+fn substitute_vault(value: &mut toml::Value, vault: &mut Vault) -> Result<(), String> {
+    match value {
+        toml::Value::String(s) => {
+            let mut error = None;
+            let replaced = VAULT_VARIABLE.replace_all(s, |caps: &Captures| {
+                if !caps[1].is_empty() {
+                    return format!("${{vault:{}#{}}}", &caps[2], &caps[3]);
+                }
+                match vault.read(&caps[2], &caps[3]) {
+                    Ok(found) => found,
+                    Err(e) => {
+                        error.get_or_insert(e);
+                        String::new()
+                    }
+                }
+            });
+            if let Some(e) = error {
+                return Err(e);
+            }
+            *s = replaced.into_owned();
+        }
+        toml::Value::Array(values) => {
+            for value in values {
+                substitute_vault(value, vault)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                substitute_vault(value, vault)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// replaces the values that are a whole `op://` reference by the 1Password field, each read once
+fn substitute_op_references(
+    value: &mut toml::Value,
+    read: &mut HashMap<String, String>,
+) -> Result<(), String> {
+    match value {
+        toml::Value::String(s) if s.starts_with("op://") => {
+            if !read.contains_key(s.as_str()) {
+                let field = secrets::read_op_reference(s)?;
+                read.insert(s.to_owned(), field);
+            }
+            *s = read[s.as_str()].to_owned();
+        }
+        toml::Value::Array(values) => {
+            for value in values {
+                substitute_op_references(value, read)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                substitute_op_references(value, read)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn substitute_data(value: &mut toml::Value, data: &JsonValue) -> Result<(), String> {
+    match value {
+        toml::Value::String(s) => {
+            let mut missing = None;
+            let replaced =
+                DATA_VARIABLE.replace_all(s, |caps: &Captures| match lookup(data, &caps[1]) {
+                    Some(JsonValue::String(found)) => found.to_owned(),
+                    Some(found) => found.to_string(),
+                    None => {
+                        missing.get_or_insert_with(|| caps[1].to_string());
+                        String::new()
+                    }
+                });
+            if let Some(key) = missing {
+                return Err(format!("'data.{}' is not defined in the data file", key));
+            }
+            *s = replaced.into_owned();
+        }
+        toml::Value::Array(values) => {
+            for value in values {
+                substitute_data(value, data)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                substitute_data(value, data)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn lookup<'a>(data: &'a JsonValue, dotted_key: &str) -> Option<&'a JsonValue> {
+    dotted_key
+        .split('.')
+        .try_fold(data, |node, key| match node {
+            JsonValue::Object(map) => map.get(key),
+            JsonValue::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // loads the config in a temporary folder of its own, with the --data file if any
+    fn load_in_folder(name: &str, config: &str, data: Option<&str>) -> Result<Config, String> {
+        let folder = env::temp_dir().join(format!("deploy-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let config_path = folder.join("deploy.toml");
+        fs::write(&config_path, config).unwrap();
+        let data = data.map(|data| {
+            let data_path = folder.join("data.json");
+            fs::write(&data_path, data).unwrap();
+            load_data(data_path.to_str().unwrap()).unwrap()
+        });
+        let options = LoadOptions {
+            data: data.as_ref(),
+            ..Default::default()
+        };
+        let loaded = load(config_path.to_str().unwrap(), options);
+        fs::remove_dir_all(&folder).unwrap();
+        loaded
+    }
+
+    const DATA: &str = r#"{ "server": { "host": "10.0.0.2" }, "release": 42 }"#;
+
+    #[test]
+    fn data_keys_fill_in_dotted_paths() {
+        let config = r#"
+            [server]
+            host = "{{ data.server.host }}"
+            user = "ubuntu"
+            ssh_key = "~/.ssh/id_ed25519"
+
+            [[actions]]
+            type = "commands"
+            name = "tag"
+            commands = ["echo {{data.release}}"]
+        "#;
+        let config = load_in_folder("data", config, Some(DATA)).unwrap();
+        assert_eq!(config.server.host, "10.0.0.2");
+        let Action::Commands { commands, .. } = &config.actions[0] else {
+            panic!("expected a commands action");
+        };
+        assert_eq!(commands, &["echo 42"]);
+    }
+
+    #[test]
+    fn missing_data_keys_are_errors() {
+        let config = r#"
+            actions = []
+
+            [server]
+            host = "{{ data.server.name }}"
+            user = "ubuntu"
+            ssh_key = "~/.ssh/id_ed25519"
+        "#;
+        let loaded = load_in_folder("missing-data", config, Some(DATA));
+        assert_eq!(
+            loaded.err().as_deref(),
+            Some("'data.server.name' is not defined in the data file")
+        );
+    }
+
+    // a config of the given name in folder, deploying nothing after its depends_on
+    fn write_config(folder: &Path, name: &str, depends_on: &[&str]) -> String {
+        let contents = format!(
+            r#"
+            actions = []
+            depends_on = {:?}
+
+            [server]
+            host = "{}.example.com"
+            port = 22
+            user = "ubuntu"
+            ssh_key = "~/.ssh/id_ed25519"
+            "#,
+            depends_on, name
+        );
+        let path = folder.join(format!("{}.toml", name));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn plain_http_urls_are_refused_unless_allowed() {
+        let config = r#"
+            actions = []
+            {}
+
+            [server]
+            host = "10.0.0.2"
+            user = "ubuntu"
+            ssh_key = "~/.ssh/id_ed25519"
+
+            [healthcheck]
+            url = "http://10.0.0.2:8080/health"
+        "#;
+        let refused = load_in_folder("http", &config.replace("{}", ""), None);
+        assert_eq!(
+            refused.err().unwrap(),
+            "'http://10.0.0.2:8080/health' of [healthcheck] isn't https, \
+             set require_https = false to allow plain http"
+        );
+        let allowed = config.replace("{}", "require_https = false");
+        assert!(load_in_folder("http-allowed", &allowed, None).is_ok());
+    }
+
+    #[test]
+    fn dependencies_load_first() {
+        let folder = env::temp_dir().join(format!("deploy-depends-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let database = write_config(&folder, "database", &[]);
+        let backend = write_config(&folder, "backend", &["database.toml"]);
+        let frontend = write_config(&folder, "frontend", &["backend.toml", "database.toml"]);
+        let loaded = load_with_dependencies(&frontend, LoadOptions::default());
+        write_config(&folder, "database", &["frontend.toml"]);
+        let cycle = load_with_dependencies(&frontend, LoadOptions::default());
+        fs::remove_dir_all(&folder).unwrap();
+
+        let hosts: Vec<String> = loaded
+            .unwrap()
+            .into_iter()
+            .map(|(_, config)| config.server.host)
+            .collect();
+        assert_eq!(
+            hosts,
+            [
+                "database.example.com",
+                "backend.example.com",
+                "frontend.example.com"
+            ]
+        );
+        let cycle = cycle.err().unwrap();
+        assert!(cycle.starts_with("dependency cycle: "));
+        assert!(cycle.contains(&database) && cycle.contains(&backend));
+    }
 }
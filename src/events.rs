@@ -0,0 +1,45 @@
+use chrono::Utc;
+use serde_json::{json, Value as JsonValue};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+// line-delimited json events, written next to the deployment log with log_format = "json"
+#[derive(Clone, Default)]
+pub struct EventLog {
+    // None unless the json log was asked for
+    file: Option<Arc<Mutex<File>>>,
+    host: String,
+}
+
+impl EventLog {
+    pub fn open(path: &Path, host: &str) -> io::Result<EventLog> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(EventLog {
+            file: Some(Arc::new(Mutex::new(file))),
+            host: host.to_string(),
+        })
+    }
+
+    // fields is a json object, completed with the timestamp, the host and the event name
+    pub fn emit(&self, event: &str, fields: JsonValue) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let mut line = json!({
+            "timestamp": Utc::now().to_rfc3339(),
+            "host": self.host,
+            "event": event,
+        });
+        if let (Some(line), JsonValue::Object(fields)) = (line.as_object_mut(), fields) {
+            line.extend(fields);
+        }
+        // each line is written at once, so it's never split by a crash
+        if let Err(e) = writeln!(file.lock().unwrap(), "{}", line) {
+            eprintln!("Failed to write to event log: {}", e);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use crate::theme::THEME;
+use colored::Colorize;
+use std::{
+    env, fs,
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+const DEPLOYIGNORE: &str = "\
+# files left out of the upload, with the .gitignore syntax
+.git/
+.env
+*.log
+node_modules/
+";
+
+// asks for a value on stdin, default is taken for an empty answer
+fn ask(question: &str, default: Option<&str>) -> io::Result<String> {
+    loop {
+        match default {
+            Some(default) if !default.is_empty() => {
+                print!("{} [{}]: ", question, default.color(THEME.muted))
+            }
+            _ => print!("{}: ", question),
+        }
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "no answer"));
+        }
+        let answer = answer.trim();
+        match (answer, default) {
+            ("", Some(default)) => return Ok(default.to_string()),
+            ("", None) => println!("{}", "An answer is required".color(THEME.warning)),
+            (answer, _) => return Ok(answer.to_string()),
+        }
+    }
+}
+
+// a toml string, quoted and escaped
+fn quoted(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+// asks for the server and the usual steps, then writes a commented config and a .deployignore
+pub fn run(config_path: Option<&str>) -> io::Result<()> {
+    let folder_name = env::current_dir()?
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "app".to_string());
+    let default_path = format!("{}.deploy.toml", folder_name);
+    let config_path = match config_path {
+        Some(config_path) => config_path.to_string(),
+        None => ask("Config file", Some(&default_path))?,
+    };
+    if Path::new(&config_path).exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("'{}' already exists", config_path),
+        ));
+    }
+
+    let host = ask("Server host", None)?;
+    let port = loop {
+        let port = ask("SSH port", Some("22"))?;
+        match port.parse::<u16>() {
+            Ok(port) => break port,
+            Err(_) => println!("{}", "Expected a port number".color(THEME.warning)),
+        }
+    };
+    let user = env::var("USER").unwrap_or_default();
+    let user = ask("SSH user", Some(&user))?;
+    let ssh_key = ask("SSH private key", Some("~/.ssh/id_ed25519"))?;
+    let source_folder = ask("Local folder to upload", Some("./dist"))?;
+    let target_folder = ask("Remote folder", Some(&format!("~/{}", folder_name)))?;
+    let restart = ask("Command to run once uploaded, empty for none", Some(""))?;
+    let wait_port = loop {
+        let port = ask("Port to wait for after it, empty for none", Some(""))?;
+        match port.parse::<u16>() {
+            _ if port.is_empty() => break None,
+            Ok(port) => break Some(port),
+            Err(_) => println!("{}", "Expected a port number".color(THEME.warning)),
+        }
+    };
+
+    let mut config = format!(
+        "\
+# deployed with `deploy {config_path}`, `deploy {config_path} --plan` shows what it would do
+
+[server]
+host = {host}
+port = {port}
+user = {user}
+# private key of the SSH connection, ~ is your home folder
+ssh_key = {ssh_key}
+
+# actions run in this order, each once the previous one succeeded
+[[actions]]
+type = \"upload\"
+name = \"upload\"
+# files listed in .deployignore at the root of this folder are left out
+source_folder = {source_folder}
+# relative to the home folder of the SSH user
+target_folder = {target_folder}
+",
+        config_path = config_path,
+        host = quoted(&host),
+        port = port,
+        user = quoted(&user),
+        ssh_key = quoted(&ssh_key),
+        source_folder = quoted(&source_folder),
+        target_folder = quoted(&target_folder),
+    );
+    if !restart.is_empty() {
+        config.push_str(&format!(
+            "
+[[actions]]
+type = \"commands\"
+name = \"restart\"
+# joined with &&, a non-zero exit status stops the deployment
+commands = [{}]
+",
+            quoted(&restart)
+        ));
+    }
+    if let Some(wait_port) = wait_port {
+        config.push_str(&format!(
+            "
+[[actions]]
+type = \"wait_for_port\"
+name = \"ready\"
+# polled from the server, until it accepts connections or timeout seconds passed
+port = {}
+timeout = 30
+",
+            wait_port
+        ));
+    }
+    fs::write(&config_path, config)?;
+    println!("{} '{}'", THEME.success_label("Created:"), config_path);
+
+    let ignore_path = Path::new(&source_folder).join(".deployignore");
+    if ignore_path.exists() {
+        println!(
+            "{} '{}' already exists",
+            "Kept:".bright_black(),
+            ignore_path.display()
+        );
+    } else if Path::new(&source_folder).is_dir() {
+        fs::write(&ignore_path, DEPLOYIGNORE)?;
+        println!(
+            "{} '{}'",
+            THEME.success_label("Created:"),
+            ignore_path.display()
+        );
+    } else {
+        println!(
+            "{} '{}' doesn't exist yet, no .deployignore was written",
+            THEME.warning_label("Warning:"),
+            source_folder
+        );
+    }
+    println!(
+        "{} `deploy {} --validate`, then `--plan` to review it",
+        "Next:".bright_black(),
+        config_path
+    );
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use crate::actions::classify::{classify_files, ContentFilter, Reason};
+use crate::actions::upload::expand_user_path;
+use crate::config::{Action, Config};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+// upload action name -> relative file path -> sha256
+pub type Lock = BTreeMap<String, BTreeMap<String, String>>;
+
+pub fn lock_path(config_path: &str) -> PathBuf {
+    let stem = config_path.strip_suffix(".toml").unwrap_or(config_path);
+    PathBuf::from(format!("{}.lock", stem))
+}
+
+pub fn load(path: &Path) -> io::Result<Lock> {
+    let contents = fs::read_to_string(path)?;
+    toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn save(path: &Path, lock: &Lock) -> io::Result<()> {
+    let contents =
+        toml::to_string(lock).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(path, contents)
+}
+
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 8 * 1024];
+    loop {
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// returns a description of every difference between the local files and the lock
+pub fn verify(config: &Config, lock: &Lock) -> Vec<String> {
+    let mut drift = Vec::new();
+    for action in &config.actions {
+        let Action::Upload {
+            name,
+            source_folder,
+            skip_empty_files,
+            skip_first_line,
+            use_gitignore,
+            ..
+        } = action
+        else {
+            continue;
+        };
+        let Some(locked) = lock.get(name) else {
+            drift.push(format!("{}: not in lock", name));
+            continue;
+        };
+
+        let filter = match ContentFilter::new(*skip_empty_files, skip_first_line.as_deref()) {
+            Ok(filter) => filter,
+            Err(e) => {
+                drift.push(format!("{}: {}", name, e));
+                continue;
+            }
+        };
+        let source_folder = expand_user_path(source_folder);
+        let mut seen = HashSet::new();
+        for (path, reason) in classify_files(&source_folder, &filter, *use_gitignore, false) {
+            if matches!(
+                reason,
+                Reason::Ignored(_) | Reason::GitIgnored | Reason::Skipped(_)
+            ) {
+                continue;
+            }
+            let relative_path = path
+                .strip_prefix(&source_folder)
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            match hash_file(&path) {
+                Ok(hash) => match locked.get(&relative_path) {
+                    Some(locked_hash) if *locked_hash == hash => {}
+                    Some(_) => drift.push(format!("{}: '{}' modified", name, relative_path)),
+                    None => drift.push(format!("{}: '{}' not in lock", name, relative_path)),
+                },
+                Err(e) => drift.push(format!("{}: '{}' {}", name, relative_path, e)),
+            }
+            seen.insert(relative_path);
+        }
+        for relative_path in locked.keys() {
+            if !seen.contains(relative_path) {
+                drift.push(format!("{}: '{}' missing locally", name, relative_path));
+            }
+        }
+    }
+    drift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, LoadOptions};
+
+    // a config uploading src/, with index.html in it, from a temporary folder
+    fn upload_config(name: &str) -> (PathBuf, Config) {
+        let folder = std::env::temp_dir().join(format!("deploy-{}-{}", name, std::process::id()));
+        fs::create_dir_all(folder.join("src")).unwrap();
+        fs::write(folder.join("src/index.html"), "hello").unwrap();
+        let config_path = folder.join("deploy.toml");
+        let contents = format!(
+            r#"
+            [server]
+            host = "localhost"
+            user = "ubuntu"
+            ssh_key = "~/.ssh/id_ed25519"
+
+            [[actions]]
+            type = "upload"
+            name = "upload_sources"
+            source_folder = "{}"
+            target_folder = "~/site/"
+            "#,
+            folder.join("src").display()
+        );
+        fs::write(&config_path, contents).unwrap();
+        let config = config::load(config_path.to_str().unwrap(), LoadOptions::default()).unwrap();
+        (folder, config)
+    }
+
+    #[test]
+    fn verify_reports_hash_mismatches() {
+        let (folder, config) = upload_config("lock");
+        let hash = hash_file(&folder.join("src/index.html")).unwrap();
+        let files = BTreeMap::from([("index.html".to_string(), hash)]);
+        let lock = Lock::from([("upload_sources".to_string(), files)]);
+        assert!(verify(&config, &lock).is_empty());
+
+        fs::write(folder.join("src/index.html"), "changed").unwrap();
+        let drift = verify(&config, &lock);
+        fs::remove_dir_all(&folder).unwrap();
+        assert_eq!(drift, vec!["upload_sources: 'index.html' modified"]);
+    }
+}
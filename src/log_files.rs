@@ -0,0 +1,133 @@
+use chrono::Local;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+pub const DEFAULT_LOG_DIR: &str = ".deployments";
+pub const DEFAULT_LOG_NAME: &str = "{{kind}}_{{n}}.txt";
+// `{{date}}` in log names
+const DATE_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+lazy_static! {
+    static ref PLACEHOLDER: Regex = Regex::new(r"\{\{\s*(\w+)\s*\}\}").unwrap();
+}
+
+// deployments and dry runs are each numbered on their own
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kind {
+    Deployment,
+    DryRun,
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Deployment => "deployment",
+            Kind::DryRun => "dry-run",
+        }
+    }
+}
+
+// where runs are logged, from the log_dir and log_name of the config
+#[derive(Clone)]
+pub struct LogNaming {
+    pub dir: PathBuf,
+    template: String,
+    env: String,
+}
+
+impl LogNaming {
+    pub fn new(dir: Option<&str>, template: Option<&str>, env: Option<&str>) -> LogNaming {
+        LogNaming {
+            dir: PathBuf::from(dir.unwrap_or(DEFAULT_LOG_DIR)),
+            template: template.unwrap_or(DEFAULT_LOG_NAME).to_string(),
+            env: env.unwrap_or("default").to_string(),
+        }
+    }
+
+    // a template without {{kind}} names dry runs with a dry-run_ prefix
+    fn template(&self, kind: Kind) -> String {
+        let has_kind = PLACEHOLDER
+            .captures_iter(&self.template)
+            .any(|caps| &caps[1] == "kind");
+        match kind {
+            Kind::DryRun if !has_kind => format!("dry-run_{}", self.template),
+            _ => self.template.to_owned(),
+        }
+    }
+
+    // matches the names the template gives to every run of this kind, capturing {{n}}
+    fn pattern(&self, kind: Kind) -> Regex {
+        let template = self.template(kind);
+        let mut pattern = String::from("^");
+        let mut last = 0;
+        for caps in PLACEHOLDER.captures_iter(&template) {
+            let placeholder = caps.get(0).unwrap();
+            pattern.push_str(&regex::escape(&template[last..placeholder.start()]));
+            pattern.push_str(match &caps[1] {
+                "n" => r"(?P<n>\d+)",
+                "date" => r"\d{8}-\d{6}",
+                "kind" => kind.as_str(),
+                _ => r"[^/]*?",
+            });
+            last = placeholder.end();
+        }
+        pattern.push_str(&regex::escape(&template[last..]));
+        pattern.push('$');
+        Regex::new(&pattern).unwrap()
+    }
+
+    // existing logs of this kind with their {{n}}, 0 without one, and last write
+    fn numbered_logs(&self, kind: Kind) -> Vec<(u64, SystemTime, PathBuf)> {
+        let pattern = self.pattern(kind);
+        let prefixed_dry_runs = self.template(Kind::Deployment) != self.template(Kind::DryRun);
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut logs: Vec<_> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                // the dry-run_ prefix could pass for the start of a deployment's name
+                if kind == Kind::Deployment
+                    && prefixed_dry_runs
+                    && file_name.starts_with("dry-run_")
+                {
+                    return None;
+                }
+                let caps = pattern.captures(&file_name)?;
+                let n = caps.name("n").and_then(|n| n.as_str().parse().ok());
+                let modified = entry.metadata().and_then(|m| m.modified()).ok()?;
+                Some((n.unwrap_or(0), modified, entry.path()))
+            })
+            .collect();
+        logs.sort();
+        logs
+    }
+
+    // existing logs of this kind, oldest first
+    pub fn logs(&self, kind: Kind) -> Vec<PathBuf> {
+        let logs = self.numbered_logs(kind).into_iter();
+        logs.map(|(_, _, path)| path).collect()
+    }
+
+    // {{n}} is one more than the highest number in use, so removed logs don't leave gaps to fill
+    pub fn next_path(&self, kind: Kind) -> PathBuf {
+        let logs = self.numbered_logs(kind);
+        let n = logs.iter().map(|(n, _, _)| *n).max().unwrap_or(0) + 1;
+        let date = Local::now().format(DATE_FORMAT).to_string();
+        let template = self.template(kind);
+        let file_name = PLACEHOLDER.replace_all(&template, |caps: &Captures| match &caps[1] {
+            "n" => n.to_string(),
+            "date" => date.to_owned(),
+            "env" => self.env.to_owned(),
+            "kind" => kind.as_str().to_string(),
+            _ => caps[0].to_string(),
+        });
+        Path::new(&self.dir).join(file_name.as_ref())
+    }
+}
@@ -1,100 +1,831 @@
-use colored::Colorize;
+use crate::config::{LogDurability, LogFormat};
+use crate::events::EventLog;
+use crate::log_files::{Kind, LogNaming};
+use crate::theme::THEME;
+use chrono::{DateTime, SecondsFormat, Utc};
+use colored::{ColoredString, Colorize};
 use crossterm::{
     cursor::{MoveToColumn, MoveUp},
-    event::{Event, EventStream, KeyCode},
+    event::{Event, EventStream, KeyCode, KeyEventKind, KeyModifiers},
     execute,
-    style::{Color, Print, SetForegroundColor},
-    terminal::{disable_raw_mode, enable_raw_mode, Clear, ClearType},
+    style::Print,
+    terminal::{self, disable_raw_mode, enable_raw_mode, Clear, ClearType},
     ExecutableCommand,
 };
 use futures::{future::FutureExt, StreamExt};
 use lazy_static::lazy_static;
 use regex::Regex;
-use russh::{client, Channel, ChannelMsg};
-use std::str;
+use russh::{client, Channel, ChannelMsg, Sig};
+use serde_json::json;
+use std::str::FromStr;
 use std::{
     collections::VecDeque,
     fs::{self, OpenOptions},
     io::{stdout, Write},
-    path::Path,
-    sync::{Arc, Mutex},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
+use tokio::time::{error::Elapsed, interval, timeout_at, Instant, MissedTickBehavior};
 
 pub const REMOTE_TERM_SIZE: usize = 5;
 
+// extended data type of the stderr of a command
+const STDERR: u32 = 1;
+
+static INTERACTIVE: AtomicBool = AtomicBool::new(true);
+
+// false when stdout isn't a terminal or with --no-tty: no cursor movement, raw mode or ESC
+pub fn set_interactive(interactive: bool) {
+    INTERACTIVE.store(interactive, Ordering::Relaxed);
+}
+
+pub fn interactive() -> bool {
+    INTERACTIVE.load(Ordering::Relaxed)
+}
+
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+// with --output json, stdout only carries the final report and the console moves to stderr
+pub fn set_json_output(json_output: bool) {
+    JSON_OUTPUT.store(json_output, Ordering::Relaxed);
+}
+
+pub fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
 lazy_static! {
     pub static ref ANSI_ESCAPE_CODE: Regex = Regex::new("\x1B\\[[0-9;]*[a-zA-Z]").unwrap();
 }
+// severity written before each line of the log file
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    // only shown with --verbose, always in the log file
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        }
+    }
+}
+
+// how much of the output reaches the console, the log file always gets everything
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Verbosity {
+    // step results, warnings and errors
+    Quiet,
+    #[default]
+    Normal,
+    // also every remote line as it arrives, every uploaded and left out file
+    Verbose,
+}
+
+impl Verbosity {
+    fn shows(&self, level: Level) -> bool {
+        match self {
+            Verbosity::Quiet => level >= Level::Warn,
+            Verbosity::Normal => level >= Level::Info,
+            Verbosity::Verbose => true,
+        }
+    }
+}
+
+// the message of a log file line, without the timestamp and level older logs don't have
+pub fn line_message(line: &str) -> &str {
+    let mut parts = line.splitn(3, ' ');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(timestamp), Some(level), Some(message))
+            if ["DEBUG", "INFO", "WARN", "ERROR"].contains(&level)
+                && DateTime::parse_from_rfc3339(timestamp).is_ok() =>
+        {
+            message.trim_start()
+        }
+        _ => line,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConsoleMode {
+    // live output until the command exits or ESC is pressed
+    Interactive,
+    // no live output, the last N lines are printed once the command exits
+    Tail(usize),
+}
+
+impl FromStr for ConsoleMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode.split_once(':') {
+            None if mode == "interactive" => Ok(ConsoleMode::Interactive),
+            Some(("tail", count)) => count
+                .parse()
+                .map(ConsoleMode::Tail)
+                .map_err(|_| format!("invalid tail length '{}'", count)),
+            _ => Err(format!(
+                "unknown console mode '{}', expected interactive or tail:N",
+                mode
+            )),
+        }
+    }
+}
+
 pub struct Logger {
-    log_file: Arc<tokio::sync::Mutex<std::fs::File>>,
+    // None when running without a log file
+    log_file: Option<Arc<tokio::sync::Mutex<std::fs::File>>>,
     remote_buffer: Arc<Mutex<VecDeque<String>>>,
+    durability: LogDurability,
+    redactions: Vec<Regex>,
+    console_mode: ConsoleMode,
+    // lines of the interactive console, None adapts to the terminal
+    console_height: Option<usize>,
+    // remote commands still running then are killed, from --timeout
+    deadline: Option<Instant>,
+    // minimum time between two redraws of the remote console
+    render_interval: Duration,
+    // host name shown before every line when deploying to several hosts
+    prefix: Option<String>,
+    verbosity: Verbosity,
+    // deployment_N.txt, None when running without a log file
+    log_path: Option<PathBuf>,
+    events: EventLog,
+    // other steps print at the same time, so nothing is redrawn
+    concurrent: bool,
+}
+
+// where a line of the remote console comes from
+#[derive(Clone, Copy, PartialEq)]
+enum Stream {
+    Stdout,
+    Stderr,
+    // written by the tool itself, like the Ctrl+C notices
+    Note,
+}
+
+impl Stream {
+    // stdout keeps the colors of the command, the others get their own
+    fn paint(&self, line: &str) -> String {
+        match self {
+            Stream::Stdout => line.to_string(),
+            Stream::Stderr => ANSI_ESCAPE_CODE
+                .replace_all(line, "")
+                .color(THEME.warning)
+                .to_string(),
+            Stream::Note => ANSI_ESCAPE_CODE
+                .replace_all(line, "")
+                .color(THEME.warning)
+                .bold()
+                .to_string(),
+        }
+    }
+}
+
+// splits the remote output in whole lines, holding back the last one of each stream until its
+// end arrives, so that a secret split across packets is redacted all the same
+#[derive(Default)]
+struct LineSplitter {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+impl LineSplitter {
+    fn push(&mut self, stream: Stream, data: &[u8]) -> Vec<String> {
+        let partial = match stream {
+            Stream::Stderr => &mut self.stderr,
+            _ => &mut self.stdout,
+        };
+        partial.extend_from_slice(data);
+        let Some(end) = partial.iter().rposition(|byte| *byte == b'\n') else {
+            return Vec::new();
+        };
+        let rest = partial.split_off(end + 1);
+        let whole = std::mem::replace(partial, rest);
+        String::from_utf8_lossy(&whole)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    // the unfinished lines, once the command ended
+    fn finish(&mut self) -> Vec<(Stream, String)> {
+        let streams = [
+            (Stream::Stdout, &mut self.stdout),
+            (Stream::Stderr, &mut self.stderr),
+        ];
+        streams
+            .into_iter()
+            .filter(|(_, partial)| !partial.is_empty())
+            .map(|(stream, partial)| {
+                let line = String::from_utf8_lossy(partial).into_owned();
+                partial.clear();
+                (stream, line)
+            })
+            .collect()
+    }
+}
+
+// coalesces the redraws of the console, output marks it dirty and it fires at most once an
+// interval while dirty
+struct RenderTimer {
+    interval: tokio::time::Interval,
+    dirty: bool,
+}
+
+impl RenderTimer {
+    fn new(period: Duration) -> Self {
+        let mut interval = interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        RenderTimer {
+            interval,
+            dirty: false,
+        }
+    }
+
+    fn mark(&mut self) {
+        self.dirty = true;
+    }
+
+    // the next tick, never while there is nothing new to draw
+    async fn due(&mut self) {
+        if !self.dirty {
+            std::future::pending::<()>().await;
+        }
+        self.interval.tick().await;
+        self.dirty = false;
+    }
+}
+
+// the whole remote output of a command, drawn as a window of `height` lines above the banner
+struct Viewport {
+    lines: Vec<(Stream, String)>,
+    // the configured height, None for half the terminal
+    requested: Option<usize>,
+    height: usize,
+    // lines between the bottom of the window and the last line, 0 follows new output
+    scroll: usize,
+    // lines drawn above the banner, moved back over on each redraw
+    drawn: usize,
+    // typed into the banner, sent to the command's stdin on Enter
+    input: String,
+}
+
+impl Viewport {
+    fn new(requested: Option<usize>) -> Viewport {
+        let mut viewport = Viewport {
+            lines: Vec::new(),
+            requested,
+            height: 0,
+            scroll: 0,
+            drawn: 0,
+            input: String::new(),
+        };
+        viewport.resize();
+        viewport
+    }
+
+    // follows the terminal size, leaving rows for the divider, the banner and the cursor
+    fn resize(&mut self) {
+        let rows = terminal::size().map_or(24, |(_, rows)| rows as usize);
+        let available = rows.saturating_sub(3).max(1);
+        let height = self.requested.unwrap_or(available / 2).max(1);
+        self.height = height.min(available);
+        let top = self.lines.len().saturating_sub(self.height);
+        self.scroll = self.scroll.min(top);
+    }
+
+    fn push(&mut self, stream: Stream, line: String) {
+        self.lines.push((stream, line));
+        // a scrolled window keeps showing the same lines
+        if self.scroll > 0 {
+            self.scroll += 1;
+        }
+    }
+
+    fn scroll_up(&mut self, count: usize) {
+        let top = self.lines.len().saturating_sub(self.height);
+        self.scroll = (self.scroll + count).min(top);
+    }
+
+    fn scroll_down(&mut self, count: usize) {
+        self.scroll = self.scroll.saturating_sub(count);
+    }
+
+    // index of the first line of the window and the lines in it
+    fn window(&self) -> (usize, &[(Stream, String)]) {
+        let end = self.lines.len() - self.scroll;
+        let start = end.saturating_sub(self.height);
+        (start, &self.lines[start..end])
+    }
+
+    // lines are cut to the terminal width so each takes a single row to move over
+    fn draw(&mut self, prefix: &str) {
+        let width = terminal::size().map_or(80, |(columns, _)| columns as usize);
+        let width = width.saturating_sub(prefix.chars().count() + 2);
+        let mut writer = stdout();
+        execute!(writer, MoveUp(self.drawn as u16 + 1), MoveToColumn(0)).unwrap();
+        let (start, window) = self.window();
+        for (stream, line) in window {
+            let line = ANSI_ESCAPE_CODE.replace_all(line, "");
+            let line: String = line.chars().take(width).collect();
+            execute!(
+                writer,
+                Clear(ClearType::CurrentLine),
+                Print(prefix),
+                Print("$ ".color(THEME.muted)),
+                Print(stream.paint(&line)),
+                Print("\n"),
+                MoveToColumn(0),
+            )
+            .unwrap();
+        }
+        let position = if self.scroll > 0 {
+            format!(
+                "lines {}-{} of {}, End to follow, ",
+                start + 1,
+                start + window.len(),
+                self.lines.len()
+            )
+        } else {
+            String::new()
+        };
+        execute!(
+            writer,
+            Clear(ClearType::CurrentLine),
+            Print("Remote console: ".color(THEME.muted)),
+            Print(position),
+            Print("Up/Down/PgUp/PgDn to scroll, ESC to quit"),
+            Print(format!(" > {}", self.input).bold()),
+            Print("\n"),
+            MoveToColumn(0),
+            // a shrunk window leaves its former lines below
+            Clear(ClearType::FromCursorDown),
+        )
+        .unwrap();
+        self.drawn = window.len();
+    }
+
+    // replaces the window and the banner with the whole output, kept in the scrollback
+    fn finish(&self, writer: &mut impl Write, prefix: &str) {
+        execute!(
+            writer,
+            MoveUp(self.drawn as u16 + 1),
+            MoveToColumn(0),
+            Clear(ClearType::FromCursorDown),
+        )
+        .unwrap();
+        for (stream, line) in &self.lines {
+            execute!(
+                writer,
+                Print(prefix),
+                Print("$ ".color(THEME.muted)),
+                Print(stream.paint(line)),
+                Print("\n"),
+                MoveToColumn(0),
+            )
+            .unwrap();
+        }
+        execute!(
+            writer,
+            Print(THEME.success_label("Remote console: ")),
+            Print("finished\n"),
+            MoveToColumn(0),
+        )
+        .unwrap();
+    }
+}
+
+// the line separating the output of consecutive remote commands
+fn divider(label: &str) -> ColoredString {
+    format!("── {} ──", label).color(THEME.muted)
+}
+
+// the divider of the command, then the banner its console is drawn above
+fn open_console(writer: &mut impl Write, label: &str) {
+    execute!(
+        writer,
+        Clear(ClearType::CurrentLine),
+        Print(divider(label)),
+        Print("\n"),
+        MoveToColumn(0),
+        Clear(ClearType::CurrentLine),
+        Print("Remote console: ".color(THEME.muted)),
+        Print("loading"),
+        Print("\n"),
+        MoveToColumn(0),
+    )
+    .unwrap();
+}
+
+// the last lines of a command, shown below its divider once it exits
+struct Tail {
+    count: usize,
+    lines: VecDeque<String>,
+}
+
+impl Tail {
+    fn new(count: usize) -> Self {
+        Tail {
+            count,
+            lines: VecDeque::with_capacity(count),
+        }
+    }
+
+    fn push(&mut self, stream: Stream, line: &str) {
+        if self.lines.len() == self.count {
+            self.lines.pop_front();
+        }
+        if self.count > 0 {
+            self.lines.push_back(stream.paint(line));
+        }
+    }
+
+    fn finish(&self, writer: &mut impl Write, prefix: &str, label: &str, status: Option<u32>) {
+        writeln!(writer, "{}{}", prefix, divider(label)).unwrap();
+        for line in &self.lines {
+            writeln!(writer, "{}{} {}", prefix, "$".color(THEME.muted), line).unwrap();
+        }
+        write_exit_status(writer, prefix, status);
+    }
+}
+
+fn write_exit_status(writer: &mut impl Write, prefix: &str, status: Option<u32>) {
+    match status {
+        Some(exit_status) => writeln!(
+            writer,
+            "{}{} {}",
+            prefix,
+            "Exit status:".bright_black(),
+            exit_status
+        ),
+        None => writeln!(writer, "{}{}", prefix, "No exit status".bright_black()),
+    }
+    .unwrap();
+}
+
+// the exit status of a remote command killed at its deadline, as reported by timeout(1)
+pub const TIMEOUT_STATUS: u32 = 124;
+
+// the next message of the channel, an error once the deadline passed
+async fn wait_until(
+    channel: &mut Channel<client::Msg>,
+    deadline: Option<Instant>,
+) -> Result<Option<ChannelMsg>, Elapsed> {
+    match deadline {
+        Some(deadline) => timeout_at(deadline, channel.wait()).await,
+        None => Ok(channel.wait().await),
+    }
+}
+
+// kills the command of a channel that ran past its deadline
+async fn kill(channel: &Channel<client::Msg>) -> Option<u32> {
+    let _ = channel.signal(Sig::KILL).await;
+    let _ = channel.close().await;
+    Some(TIMEOUT_STATUS)
+}
+
+// restores the terminal even when the remote console or the config picker panics
+pub struct RawMode;
+
+impl RawMode {
+    pub fn enable() -> RawMode {
+        enable_raw_mode().unwrap();
+        RawMode
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+    }
+}
+
+// 128 + the signal number, the exit status a shell reports for a command it killed
+fn signal_status(signal: &Sig) -> Option<u32> {
+    let number = match signal {
+        Sig::HUP => 1,
+        Sig::INT => 2,
+        Sig::QUIT => 3,
+        Sig::ILL => 4,
+        Sig::ABRT => 6,
+        Sig::FPE => 8,
+        Sig::KILL => 9,
+        Sig::USR1 => 10,
+        Sig::SEGV => 11,
+        Sig::PIPE => 13,
+        Sig::ALRM => 14,
+        Sig::TERM => 15,
+        Sig::Custom(_) => return None,
+    };
+    Some(128 + number)
+}
+
+// what the log is written to, a file outside of the tests
+trait LogSink: Write {
+    fn sync_all(&self) -> std::io::Result<()>;
+}
+
+impl LogSink for std::fs::File {
+    fn sync_all(&self) -> std::io::Result<()> {
+        std::fs::File::sync_all(self)
+    }
+}
+
+fn flush(log_file: &mut impl LogSink, durability: LogDurability) {
+    if let Err(e) = log_file.flush() {
+        eprintln!("Failed to flush log file: {}", e);
+    }
+    if durability == LogDurability::Synced {
+        if let Err(e) = log_file.sync_all() {
+            eprintln!("Failed to sync log file: {}", e);
+        }
+    }
 }
 
 impl Logger {
-    pub fn new() -> Logger {
-        let deployments_dir = Path::new(".deployments");
+    // dry runs are logged on their own so they aren't listed as deployments
+    pub fn new(naming: Option<&LogNaming>, dry_run: bool) -> Logger {
+        let mut logger = Logger {
+            log_file: None,
+            remote_buffer: Arc::new(Mutex::new(VecDeque::new())),
+            durability: LogDurability::Fast,
+            redactions: Vec::new(),
+            console_mode: ConsoleMode::Interactive,
+            console_height: None,
+            deadline: None,
+            render_interval: Duration::from_millis(50),
+            prefix: None,
+            verbosity: Verbosity::Normal,
+            log_path: None,
+            events: EventLog::default(),
+            concurrent: false,
+        };
+        let Some(naming) = naming else {
+            return logger;
+        };
 
         // Create the directory if it does not exist
-        if !deployments_dir.exists() {
-            fs::create_dir(deployments_dir).expect("failed to create .deployments directory");
+        if !naming.dir.exists() {
+            fs::create_dir_all(&naming.dir).expect("failed to create the log directory");
         }
 
-        // Find the smallest number not already taken
-        let mut num = 1;
-        loop {
-            let file_name = format!("deployment_{}.txt", num);
-            let file_path = deployments_dir.join(file_name);
-            if !file_path.exists() {
-                let log_file = OpenOptions::new()
-                    .append(true)
-                    .create(true)
-                    .open(file_path)
-                    .expect("cannot open file");
-
-                return Logger {
-                    log_file: Arc::new(tokio::sync::Mutex::new(log_file)),
-                    remote_buffer: Arc::new(Mutex::new(VecDeque::new())),
-                };
+        let kind = if dry_run {
+            Kind::DryRun
+        } else {
+            Kind::Deployment
+        };
+        let file_path = naming.next_path(kind);
+        let log_file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&file_path)
+            .expect("cannot open file");
+
+        logger.log_path = Some(file_path);
+        logger.log_file = Some(Arc::new(tokio::sync::Mutex::new(log_file)));
+        logger
+    }
+
+    // appends a timestamped line to the log file, if there is one, without flushing
+    async fn write_to_file(&self, level: Level, line: &str) {
+        if let Some(log_file) = &self.log_file {
+            let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+            let line = format!("{} {:<5} {}", timestamp, level.as_str(), line);
+            if let Err(e) = writeln!(log_file.lock().await, "{}", line) {
+                eprintln!("Failed to write to log file: {}", e);
             }
-            num += 1;
         }
     }
 
-    async fn log_to_file(&mut self, message: String) {
-        let mut log_file = self.log_file.lock().await;
-        if let Err(e) = writeln!(log_file, "{}", message) {
-            eprintln!("Failed to write to log file: {}", e);
+    async fn flush_file(&self) {
+        if let Some(log_file) = &self.log_file {
+            flush(&mut *log_file.lock().await, self.durability);
+        }
+    }
+
+    async fn log_to_file(&mut self, level: Level, message: String) {
+        self.write_to_file(level, &message).await;
+        self.flush_file().await;
+    }
+
+    pub fn set_durability(&mut self, durability: LogDurability) {
+        self.durability = durability;
+    }
+
+    // the json events go to the log file's name with a .jsonl extension
+    pub fn set_log_format(&mut self, log_format: LogFormat, host: &str) {
+        let Some(log_path) = &self.log_path else {
+            return;
+        };
+        if log_format != LogFormat::Json {
+            return;
+        }
+        match EventLog::open(&log_path.with_extension("jsonl"), host) {
+            Ok(events) => self.events = events,
+            Err(e) => eprintln!("Failed to open event log: {}", e),
+        }
+    }
+
+    // shared with the deployment report, which adds the step events
+    pub fn events(&self) -> EventLog {
+        self.events.clone()
+    }
+
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.verbosity = verbosity;
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    pub fn set_console_mode(&mut self, console_mode: ConsoleMode) {
+        self.console_mode = console_mode;
+    }
+
+    pub fn set_console_height(&mut self, console_height: Option<usize>) {
+        self.console_height = console_height;
+    }
+
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    // the earliest of the deployment deadline and the end of the step's timeout
+    fn step_deadline(&self, timeout: Option<Duration>) -> Option<Instant> {
+        let step = timeout.map(|timeout| Instant::now() + timeout);
+        match (self.deadline, step) {
+            (Some(deadline), Some(step)) => Some(deadline.min(step)),
+            (deadline, step) => deadline.or(step),
+        }
+    }
+
+    pub fn set_prefix(&mut self, host: &str) {
+        self.prefix = Some(host.to_string());
+    }
+
+    // a logger for a step, writing to the same files with the same settings
+    pub fn branch(&self) -> Logger {
+        Logger {
+            log_file: self.log_file.clone(),
+            remote_buffer: Arc::new(Mutex::new(VecDeque::new())),
+            durability: self.durability,
+            redactions: self.redactions.clone(),
+            console_mode: self.console_mode,
+            console_height: self.console_height,
+            deadline: self.deadline,
+            render_interval: self.render_interval,
+            prefix: self.prefix.clone(),
+            verbosity: self.verbosity,
+            log_path: self.log_path.clone(),
+            events: self.events.clone(),
+            concurrent: self.concurrent,
         }
-        if let Err(e) = log_file.flush() {
-            eprintln!("Failed to flush log file: {}", e);
+    }
+
+    // the step runs alongside others: its lines are tagged with its name and printed plainly
+    pub fn set_concurrent(&mut self, step: &str) {
+        self.concurrent = true;
+        self.prefix = Some(match &self.prefix {
+            Some(host) => format!("{} {}", host, step),
+            None => step.to_string(),
+        });
+    }
+
+    // the deployment runs alongside other workspace members: its lines are tagged with the member
+    pub fn set_member(&mut self, member: &str) {
+        self.concurrent = true;
+        self.prefix = Some(match &self.prefix {
+            Some(host) => format!("{} {}", member, host),
+            None => member.to_string(),
+        });
+    }
+
+    // whether output can be redrawn in place, like the remote console and upload progress
+    pub fn interactive(&self) -> bool {
+        interactive() && !self.concurrent
+    }
+
+    // "[host] " when a prefix is set
+    pub fn prefix(&self) -> String {
+        match &self.prefix {
+            Some(host) => format!("[{}] ", host),
+            None => String::new(),
         }
     }
 
-    pub async fn log(&mut self, message: String) {
-        println!("{}", message);
-        self.log_to_file(ANSI_ESCAPE_CODE.replace_all(&message, "").to_string())
+    pub fn set_render_interval(&mut self, render_interval: Duration) {
+        // a zero period would make the render timer panic
+        self.render_interval = render_interval.max(Duration::from_millis(1));
+    }
+
+    // a line of the tool itself among the remote output, println! would break the layout
+    async fn note(&self, viewport: &mut Viewport, level: Level, text: String) {
+        self.write_to_file(level, &format!("{}{}", self.prefix(), text))
             .await;
+        viewport.push(Stream::Note, text);
+    }
+
+    // logs each line as it arrives and adds it to the viewport, drawn on the next tick
+    async fn push_output(
+        &mut self,
+        viewport: &mut Viewport,
+        label: &str,
+        stream: Stream,
+        lines: Vec<String>,
+    ) {
+        for line in lines {
+            let line = self.redact(&line);
+            self.log_remote_line(label, stream, &line).await;
+            viewport.push(stream, line);
+        }
+    }
+
+    // logs a line and prints it right away
+    async fn print_output(&mut self, label: &str, stream: Stream, line: &str) {
+        let line = self.redact(line);
+        self.log_remote_line(label, stream, &line).await;
+        let line = stream.paint(&line);
+        println!("{}{} {}", self.prefix(), "$".color(THEME.muted), line);
+    }
+
+    // saves remote output without colors and no flushing
+    async fn log_remote_line(&mut self, label: &str, stream: Stream, line: &str) {
+        let line = ANSI_ESCAPE_CODE.replace_all(line, "");
+        let (name, tag) = match stream {
+            Stream::Stderr => ("stderr", "[stderr] "),
+            _ => ("stdout", ""),
+        };
+        let fields = json!({ "label": label, "stream": name, "line": line });
+        self.events.emit("remote_output", fields);
+        let line = format!("{}$ {}{}", self.prefix(), tag, line);
+        self.write_to_file(Level::Info, &line).await;
+    }
+
+    pub fn set_redactions(&mut self, redactions: Vec<Regex>) {
+        self.redactions = redactions;
+    }
+
+    fn redact(&self, text: &str) -> String {
+        self.redactions
+            .iter()
+            .fold(text.to_string(), |text, pattern| {
+                pattern.replace_all(&text, "***").to_string()
+            })
+    }
+
+    pub async fn log(&mut self, level: Level, message: String) {
+        let message = self.redact(&format!("{}{}", self.prefix(), message));
+        if self.verbosity.shows(level) {
+            crate::console!("{}", message);
+        }
+        let message = ANSI_ESCAPE_CODE.replace_all(&message, "").to_string();
+        let fields = json!({ "level": level.as_str(), "text": message });
+        self.events.emit("message", fields);
+        self.log_to_file(level, message).await;
     }
 
     pub async fn add_uploaded_file(&mut self, file_name: String) {
         let mut buffer = self.remote_buffer.lock().unwrap();
         let prev_buffer_length: u16 = buffer.len().try_into().unwrap();
 
-        if buffer.len() == REMOTE_TERM_SIZE.into() {
+        if buffer.len() == REMOTE_TERM_SIZE {
             buffer.pop_front();
         }
         buffer.push_back(format!(
             "{} '{}'",
-            "✔".bright_green(),
-            file_name.bright_black()
+            THEME.check_mark().color(THEME.success),
+            file_name.color(THEME.muted)
         ));
         // saving without colors and no flushing
-        if let Err(e) = writeln!(
-            self.log_file.lock().await,
-            "{}",
-            format!("✔ '{}'", file_name)
-        ) {
-            eprintln!("Failed to write to log file: {}", e);
+        let line = format!("{} '{}'", THEME.check_mark(), file_name);
+        self.write_to_file(Level::Info, &line).await;
+        self.events
+            .emit("file_uploaded", json!({ "path": file_name }));
+        if self.verbosity == Verbosity::Quiet {
+            return;
+        }
+        if !self.interactive() || self.verbosity == Verbosity::Verbose {
+            // without a cursor to move, the rolling display becomes a list
+            println!("{}{}", self.prefix(), buffer.back().unwrap());
+            return;
         }
 
         let mut writer = stdout();
@@ -117,101 +848,506 @@ impl Logger {
 
     pub async fn stop_files_display(&mut self) {
         self.remote_buffer = Arc::new(Mutex::new(VecDeque::new()));
-        if let Err(e) = self.log_file.lock().await.flush() {
-            eprintln!("Failed to flush log file: {}", e);
-        }
+        self.flush_file().await;
     }
 
-    pub async fn start_remote_logging(&mut self, mut channel: Channel<client::Msg>) {
-        execute!(
-            stdout(),
-            Clear(ClearType::CurrentLine),
-            SetForegroundColor(Color::DarkGrey),
-            Print("Remote console: "),
-            SetForegroundColor(Color::Reset),
-            Print("loading"),
-            Print("\n"),
-            MoveToColumn(0),
-        )
-        .unwrap();
+    // streams the channel output until it exits or ESC is pressed, returns the exit status
+    // the command is killed once it runs longer than timeout, or past the deadline
+    pub async fn start_remote_logging(
+        &mut self,
+        mut channel: Channel<client::Msg>,
+        label: &str,
+        timeout: Option<Duration>,
+    ) -> Option<u32> {
+        let deadline = self.step_deadline(timeout);
+        if let ConsoleMode::Tail(count) = self.console_mode {
+            return self
+                .tail_remote_output(channel, label, count, deadline)
+                .await;
+        }
+        match self.verbosity {
+            // only logged
+            Verbosity::Quiet => return self.tail_remote_output(channel, label, 0, deadline).await,
+            Verbosity::Verbose => return self.stream_remote_output(channel, label, deadline).await,
+            Verbosity::Normal if !self.interactive() => {
+                return self.stream_remote_output(channel, label, deadline).await
+            }
+            Verbosity::Normal => {}
+        }
 
-        enable_raw_mode().unwrap();
+        open_console(&mut stdout(), label);
+
+        let raw_mode = RawMode::enable();
         let mut reader = EventStream::new();
+        let mut status = None;
+        let mut interrupted = false;
+        let mut viewport = Viewport::new(self.console_height);
+        let mut lines = LineSplitter::default();
+        // output is logged as it arrives but only drawn on each tick
+        let mut render = RenderTimer::new(self.render_interval);
         loop {
             tokio::select! {
+                _ = render.due() => viewport.draw(&self.prefix()),
                 msg = reader.next().fuse() => match msg {
-                    Some(Ok(event)) => {
-                        if event == Event::Key(KeyCode::Esc.into()) {
-                            execute!(
-                                stdout(),
-                                MoveUp(1),
-                                Clear(ClearType::CurrentLine),
-                                SetForegroundColor(Color::Green),
-                                Print("Remote console: "),
-                                SetForegroundColor(Color::Reset),
-                                Print("finished\n"),
-                                MoveToColumn(0),
-                            )
-                            .unwrap();
-                            break;
+                    Some(Ok(Event::Resize(_, _))) => {
+                        viewport.resize();
+                        render.mark();
+                    },
+                    Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                        match key.code {
+                            KeyCode::Esc => break,
+                            KeyCode::Up => viewport.scroll_up(1),
+                            KeyCode::Down => viewport.scroll_down(1),
+                            KeyCode::PageUp => viewport.scroll_up(viewport.height),
+                            KeyCode::PageDown => viewport.scroll_down(viewport.height),
+                            KeyCode::End => viewport.scroll = 0,
+                            // the first ctrl+c interrupts the command, a second one closes its channel
+                            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                if interrupted {
+                                    let note = "Closing the remote command".to_string();
+                                    self.note(&mut viewport, Level::Warn, note).await;
+                                    let _ = channel.close().await;
+                                    break;
+                                }
+                                interrupted = true;
+                                let note = match channel.signal(Sig::INT).await {
+                                    Ok(()) => "Sent SIGINT, Ctrl+C again to close the command".to_string(),
+                                    Err(e) => format!("Unable to interrupt the command: {}", e),
+                                };
+                                self.note(&mut viewport, Level::Warn, note).await;
+                            }
+                            // ctrl+d closes the command's stdin, like at a shell
+                            KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                if let Err(e) = channel.eof().await {
+                                    let warning = format!("Unable to close the remote input: {}", e);
+                                    self.note(&mut viewport, Level::Warn, warning).await;
+                                }
+                            }
+                            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                viewport.input.push(c)
+                            }
+                            KeyCode::Backspace => {
+                                viewport.input.pop();
+                            }
+                            KeyCode::Enter => {
+                                let input = std::mem::take(&mut viewport.input);
+                                let line = format!("{}> {}", self.prefix(), self.redact(&input));
+                                self.write_to_file(Level::Info, &line).await;
+                                let data = format!("{}\n", input);
+                                if let Err(e) = channel.data(data.as_bytes()).await {
+                                    let warning = format!("Unable to send the input: {}", e);
+                                    self.note(&mut viewport, Level::Warn, warning).await;
+                                }
+                            }
+                            _ => continue,
                         }
+                        render.mark();
                     },
                     None => break,
                     _ => {},
                 },
-                channel_msg = channel.wait() => match channel_msg {
-                    Some(next_msg) => {
+                channel_msg = wait_until(&mut channel, deadline) => match channel_msg {
+                    Err(_) => {
+                        status = kill(&channel).await;
+                        let note = format!("Timeout: '{}' killed at its deadline", label);
+                        self.note(&mut viewport, Level::Error, note).await;
+                        break;
+                    },
+                    Ok(Some(next_msg)) => {
                         match next_msg {
                             ChannelMsg::Data { ref data } => {
-                                let bytes = data.as_ref();
-                                let next_line = (str::from_utf8(bytes).expect("Invalid UTF-8")).trim_end();
-                                execute!(
-                                    stdout(),
-                                    MoveUp(1),
-                                    Clear(ClearType::CurrentLine),
-                                    SetForegroundColor(Color::DarkGrey),
-                                    Print("$ "),
-                                    SetForegroundColor(Color::Reset),
-                                    Print(next_line),
-                                    Print("\n"),
-                                    MoveToColumn(0),
-                                    Clear(ClearType::CurrentLine),
-                                    SetForegroundColor(Color::DarkGrey),
-                                    Print("Remote console: "),
-                                    SetForegroundColor(Color::Reset),
-                                    Print("Press ESC to quit"),
-                                    Print("\n"),
-                                    MoveToColumn(0),
-                                )
-                                .unwrap();
+                                let whole = lines.push(Stream::Stdout, data);
+                                self.push_output(&mut viewport, label, Stream::Stdout, whole).await;
+                                render.mark();
                             }
-                            ChannelMsg::ExitStatus { exit_status : _ } => {
-                                // println!("Exit status: {}", exit_status);
+                            ChannelMsg::ExtendedData { ref data, ext: STDERR } => {
+                                let whole = lines.push(Stream::Stderr, data);
+                                self.push_output(&mut viewport, label, Stream::Stderr, whole).await;
+                                render.mark();
+                            }
+                            ChannelMsg::ExitStatus { exit_status } => {
+                                status = Some(exit_status);
+                                break;
+                            }
+                            ChannelMsg::ExitSignal { signal_name, .. } => {
+                                let note = format!("Remote command ended by SIG{:?}", signal_name);
+                                self.note(&mut viewport, Level::Warn, note).await;
+                                status = signal_status(&signal_name);
                                 break;
                             }
                             _ => {}
                         }
                     },
-                    None => break,
+                    Ok(None) => break,
                 },
             }
         }
 
-        disable_raw_mode().unwrap();
+        for (stream, line) in lines.finish() {
+            self.push_output(&mut viewport, label, stream, vec![line])
+                .await;
+        }
+        // the banner doesn't linger above the next output
+        viewport.finish(&mut stdout(), &self.prefix());
+        drop(raw_mode);
 
         // Ensure writing logs to file
-        if let Err(e) = self.log_file.lock().await.flush() {
-            eprintln!("Failed to flush log file: {}", e);
-        }
+        self.flush_file().await;
 
         // Clear buffer
         self.remote_buffer = Arc::new(Mutex::new(VecDeque::new()));
+        status
+    }
+
+    fn print_exit_status(&self, status: Option<u32>) {
+        write_exit_status(&mut stdout(), &self.prefix(), status);
+    }
+
+    async fn kill_timed_out(&mut self, channel: &Channel<client::Msg>, label: &str) -> Option<u32> {
+        let status = kill(channel).await;
+        crate::log_error!(
+            self,
+            "{} '{}' killed at its deadline",
+            THEME.error_label("Timeout:"),
+            label
+        );
+        status
+    }
+
+    // prints each line as it arrives, for logs of CI jobs and other non-terminals
+    async fn stream_remote_output(
+        &mut self,
+        mut channel: Channel<client::Msg>,
+        label: &str,
+        deadline: Option<Instant>,
+    ) -> Option<u32> {
+        println!("{}{}", self.prefix(), divider(label));
+        let mut lines = LineSplitter::default();
+        let mut status = None;
+        loop {
+            let msg = match wait_until(&mut channel, deadline).await {
+                Ok(Some(msg)) => msg,
+                Ok(None) => break,
+                Err(_) => {
+                    status = self.kill_timed_out(&channel, label).await;
+                    break;
+                }
+            };
+            match msg {
+                ChannelMsg::Data { ref data } => {
+                    for line in lines.push(Stream::Stdout, data) {
+                        self.print_output(label, Stream::Stdout, &line).await;
+                    }
+                }
+                ChannelMsg::ExtendedData {
+                    ref data,
+                    ext: STDERR,
+                } => {
+                    for line in lines.push(Stream::Stderr, data) {
+                        self.print_output(label, Stream::Stderr, &line).await;
+                    }
+                }
+                ChannelMsg::ExitStatus { exit_status } => {
+                    status = Some(exit_status);
+                    break;
+                }
+                ChannelMsg::ExitSignal { signal_name, .. } => {
+                    status = signal_status(&signal_name);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        for (stream, line) in lines.finish() {
+            self.print_output(label, stream, &line).await;
+        }
+        self.print_exit_status(status);
+        self.flush_file().await;
+        status
+    }
+
+    async fn tail_remote_output(
+        &mut self,
+        mut channel: Channel<client::Msg>,
+        label: &str,
+        count: usize,
+        deadline: Option<Instant>,
+    ) -> Option<u32> {
+        let mut tail = Tail::new(count);
+        let mut lines = LineSplitter::default();
+        let mut status = None;
+        loop {
+            let msg = match wait_until(&mut channel, deadline).await {
+                Ok(Some(msg)) => msg,
+                Ok(None) => break,
+                Err(_) => {
+                    status = self.kill_timed_out(&channel, label).await;
+                    break;
+                }
+            };
+            let (stream, data) = match msg {
+                ChannelMsg::Data { ref data } => (Stream::Stdout, data),
+                ChannelMsg::ExtendedData {
+                    ref data,
+                    ext: STDERR,
+                } => (Stream::Stderr, data),
+                ChannelMsg::ExitStatus { exit_status } => {
+                    status = Some(exit_status);
+                    break;
+                }
+                ChannelMsg::ExitSignal { signal_name, .. } => {
+                    status = signal_status(&signal_name);
+                    break;
+                }
+                _ => continue,
+            };
+            for line in lines.push(stream, data) {
+                let line = self.redact(&line);
+                self.log_remote_line(label, stream, &line).await;
+                tail.push(stream, &line);
+            }
+        }
+        for (stream, line) in lines.finish() {
+            let line = self.redact(&line);
+            self.log_remote_line(label, stream, &line).await;
+            tail.push(stream, &line);
+        }
+
+        // the result of the step is all a quiet console shows
+        if self.verbosity == Verbosity::Quiet {
+            self.flush_file().await;
+            return status;
+        }
+        tail.finish(&mut stdout(), &self.prefix(), label, status);
+        self.flush_file().await;
+        status
     }
 }
 
 #[macro_export]
 macro_rules! log {
     ($logger:expr, $($arg:tt)*) => {
-        $logger.log(format!($($arg)*)).await;
+        $logger.log($crate::logger::Level::Info, format!($($arg)*)).await;
+    };
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($logger:expr, $($arg:tt)*) => {
+        $logger.log($crate::logger::Level::Debug, format!($($arg)*)).await;
+    };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($logger:expr, $($arg:tt)*) => {
+        $logger.log($crate::logger::Level::Warn, format!($($arg)*)).await;
+    };
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($logger:expr, $($arg:tt)*) => {
+        $logger.log($crate::logger::Level::Error, format!($($arg)*)).await;
+    };
+}
+
+// println! for lines printed outside the logger, on stderr with --output json
+#[macro_export]
+macro_rules! console {
+    ($($arg:tt)*) => {
+        if $crate::logger::json_output() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[derive(Default)]
+    struct CountingSink {
+        flushes: usize,
+        syncs: Cell<usize>,
+    }
+
+    impl Write for CountingSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    impl LogSink for CountingSink {
+        fn sync_all(&self) -> std::io::Result<()> {
+            self.syncs.set(self.syncs.get() + 1);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn synced_durability_syncs_after_each_write() {
+        let mut fast = CountingSink::default();
+        let mut synced = CountingSink::default();
+        for line in ["first", "second", "third"] {
+            writeln!(fast, "{}", line).unwrap();
+            flush(&mut fast, LogDurability::Fast);
+            writeln!(synced, "{}", line).unwrap();
+            flush(&mut synced, LogDurability::Synced);
+        }
+        assert_eq!((fast.flushes, fast.syncs.get()), (3, 0));
+        assert_eq!((synced.flushes, synced.syncs.get()), (3, 3));
+    }
+
+    #[test]
+    fn consecutive_consoles_are_divided_without_stale_banners() {
+        let mut output = Vec::new();
+        for (label, line) in [("build", "compiled"), ("restart", "restarted")] {
+            open_console(&mut output, label);
+            let mut viewport = Viewport::new(Some(5));
+            viewport.push(Stream::Stdout, line.to_string());
+            viewport.finish(&mut output, "");
+        }
+        let output = String::from_utf8(output).unwrap();
+        // each banner is moved back over and cleared before the output replaces it
+        let banner_cleared = "loading\n\x1b[1G\x1b[1A\x1b[1G\x1b[J";
+        assert_eq!(output.matches(banner_cleared).count(), 2);
+
+        assert_eq!(
+            ANSI_ESCAPE_CODE.replace_all(&output, ""),
+            "── build ──\nRemote console: loading\n$ compiled\nRemote console: finished\n\
+             ── restart ──\nRemote console: loading\n$ restarted\nRemote console: finished\n"
+        );
+    }
+
+    #[test]
+    fn tail_shows_the_last_lines_and_the_exit_status() {
+        let mut tail = Tail::new(5);
+        for index in 1..=20 {
+            tail.push(Stream::Stdout, &format!("line {}", index));
+        }
+        let mut output = Vec::new();
+        tail.finish(&mut output, "", "build", Some(0));
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            ANSI_ESCAPE_CODE.replace_all(&output, ""),
+            "── build ──\n$ line 16\n$ line 17\n$ line 18\n$ line 19\n$ line 20\nExit status: 0\n"
+        );
+    }
+
+    #[test]
+    fn bursts_of_output_are_all_logged_but_drawn_once_an_interval() {
+        let dir = std::env::temp_dir().join(format!("deploy-render-{}", std::process::id()));
+        let naming = LogNaming::new(dir.to_str(), None, None);
+        let mut logger = Logger::new(Some(&naming), false);
+        let (sender, mut receiver) = futures::channel::mpsc::unbounded();
+        // 2000 lines, 10 at a time, over about 200ms
+        let burst = std::thread::spawn(move || {
+            for chunk in 0..200 {
+                let data: String = (0..10)
+                    .map(|n| format!("line {}\n", chunk * 10 + n))
+                    .collect();
+                sender.unbounded_send(data).unwrap();
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        let period = Duration::from_millis(50);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (renders, elapsed) = runtime.block_on(async {
+            let started = Instant::now();
+            let mut viewport = Viewport::new(Some(5));
+            let mut render = RenderTimer::new(period);
+            let mut lines = LineSplitter::default();
+            let mut renders = 0;
+            loop {
+                tokio::select! {
+                    _ = render.due() => renders += 1,
+                    data = receiver.next() => match data {
+                        Some(data) => {
+                            let whole = lines.push(Stream::Stdout, data.as_bytes());
+                            logger.push_output(&mut viewport, "burst", Stream::Stdout, whole).await;
+                            render.mark();
+                        }
+                        None => break,
+                    },
+                }
+            }
+            logger.flush_file().await;
+            (renders, started.elapsed())
+        });
+        burst.join().unwrap();
+
+        let logged = fs::read_to_string(logger.log_path.as_ref().unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(logged.matches("$ line ").count(), 2000);
+        // a first draw right away, then one an interval at most
+        let bound = (elapsed.as_millis() / period.as_millis()) as usize + 1;
+        assert!(
+            renders >= 1 && renders <= bound,
+            "{} renders in {:?}",
+            renders,
+            elapsed
+        );
+    }
+
+    #[test]
+    fn secrets_are_redacted_on_screen_and_in_the_log_file() {
+        let dir = std::env::temp_dir().join(format!("deploy-redact-{}", std::process::id()));
+        let naming = LogNaming::new(dir.to_str(), None, None);
+        let mut logger = Logger::new(Some(&naming), false);
+        logger.set_redactions(vec![Regex::new("s3cr3t").unwrap()]);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(logger.log(Level::Info, "token=s3cr3t".to_string()));
+
+        let shown = logger.redact("$ curl -H 'Authorization: s3cr3t'");
+        let logged = fs::read_to_string(logger.log_path.as_ref().unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(shown, "$ curl -H 'Authorization: ***'");
+        assert!(logged.contains("token=***") && !logged.contains("s3cr3t"));
+    }
+
+    #[test]
+    fn secrets_split_across_packets_are_redacted() {
+        let dir = std::env::temp_dir().join(format!("deploy-split-{}", std::process::id()));
+        let naming = LogNaming::new(dir.to_str(), None, None);
+        let mut logger = Logger::new(Some(&naming), false);
+        logger.set_redactions(vec![Regex::new("s3cr3t").unwrap()]);
+        let mut viewport = Viewport::new(Some(5));
+        let mut lines = LineSplitter::default();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            for packet in ["token=s3", "cr3t\nkey=s3cr", "3t"] {
+                let whole = lines.push(Stream::Stdout, packet.as_bytes());
+                logger
+                    .push_output(&mut viewport, "env", Stream::Stdout, whole)
+                    .await;
+            }
+            for (stream, line) in lines.finish() {
+                logger
+                    .push_output(&mut viewport, "env", stream, vec![line])
+                    .await;
+            }
+            logger.flush_file().await;
+        });
+
+        let logged = fs::read_to_string(logger.log_path.as_ref().unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let shown: Vec<&str> = viewport
+            .lines
+            .iter()
+            .map(|(_, line)| line.as_str())
+            .collect();
+        assert_eq!(shown, ["token=***", "key=***"]);
+        assert!(logged.contains("$ token=***\n") && logged.contains("$ key=***\n"));
+        assert!(!logged.contains("s3"));
+    }
+}
@@ -1,100 +1,1503 @@
 mod actions;
+mod cache;
+mod checkpoint;
 mod config;
+mod events;
 mod finder;
 mod info;
+mod init;
+mod lock;
+mod log_files;
 mod logger;
-use crate::logger::Logger;
+mod picker;
+mod report;
+mod resume;
+mod retention;
+mod secrets;
+mod ssh_config;
+mod theme;
+mod validate;
+mod vault;
+mod watch;
+mod workspace;
+use crate::log_files::LogNaming;
+use crate::logger::{ConsoleMode, Logger, Verbosity};
+use crate::report::{DeploymentReport, Output, Status};
+use crate::theme::THEME;
 use chrono::{DateTime, Local, Utc};
 use clap::Parser;
 use colored::*;
+use futures::stream::{FuturesUnordered, StreamExt};
 use git2::Repository;
-use std::{fs, path::PathBuf, time::SystemTime};
+use regex::Regex;
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    future::Future,
+    io::{self, IsTerminal, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime},
+};
 
-#[derive(Parser, Debug)]
+// how long past --timeout a step that isn't a remote command gets before it's dropped
+const TIMEOUT_GRACE: Duration = Duration::from_secs(5);
+
+// set by ctrl+c, the deployments that didn't start yet are aborted without running
+static ABORTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    // to find config files in the folder
+    // to find config files in the folder, with a terminal the one picked among them is deployed
     #[arg(long)]
     find: Option<String>,
 
     #[arg(long)]
     info: bool,
 
+    // deploy every config found in the --find folder, or the current one, one after the other
+    #[arg(
+        long,
+        conflicts_with_all = ["file", "info", "explain", "plan", "validate", "rollback"]
+    )]
+    all: bool,
+
+    // ask for the server and the usual steps, then write the config file and a .deployignore
+    #[arg(long, conflicts_with_all = ["find", "info", "all"])]
+    init: bool,
+
     // config file
     file: Option<String>,
 
-    // actions from the config file to skip
+    // actions or hooks from the config file to skip
     #[arg(long, use_value_delimiter = true)]
     skip: Option<Vec<String>>,
+
+    // the only actions or hooks from the config file to run
+    #[arg(long, use_value_delimiter = true)]
+    only: Option<Vec<String>>,
+
+    // exclude unreadable local files instead of aborting before connecting
+    #[arg(long)]
+    skip_unreadable: bool,
+
+    // list .deployignore patterns that matched no file
+    #[arg(long)]
+    report_unused_ignores: bool,
+
+    // upload every file, even those unchanged since the last upload
+    #[arg(long)]
+    force: bool,
+
+    // also remove the server files of each upload target that aren't local, or no longer are,
+    // except the protected_paths; with --dry-run they're only listed
+    #[arg(long, conflicts_with_all = ["rollback", "restore_backup", "releases"])]
+    delete: bool,
+
+    // print what would be uploaded and run, without connecting to the server
+    #[arg(long)]
+    dry_run: bool,
+
+    // print why each file is or isn't uploaded, without deploying
+    #[arg(long)]
+    explain: bool,
+
+    // print the hosts, the steps in order and the files each upload would send, without deploying
+    #[arg(long, conflicts_with_all = ["explain", "dry_run", "rollback"])]
+    plan: bool,
+
+    // check the config file and its dependencies for unknown keys, missing fields, paths and
+    // patterns, without deploying
+    #[arg(long, conflicts_with_all = ["explain", "plan", "dry_run", "rollback"])]
+    validate: bool,
+
+    // write a JUnit XML report of the deployment phases
+    #[arg(long)]
+    junit: Option<String>,
+
+    // write a JSON report of the deployment, even when it fails
+    #[arg(long)]
+    report: Option<String>,
+
+    // json or yaml file whose keys are available as {{data.key}} in the config
+    #[arg(long)]
+    data: Option<String>,
+
+    // name=value overriding the [vars] entry of the same name, can be repeated
+    #[arg(long, value_parser = config::parse_assignment)]
+    set: Vec<(String, String)>,
+
+    // profile whose [env.<name>] table overrides the rest of the config
+    #[arg(long)]
+    env: Option<String>,
+
+    // remote output display, `interactive` or `tail:N` for the last N lines on exit
+    #[arg(long, default_value = "interactive")]
+    console_mode: ConsoleMode,
+
+    // minimum milliseconds between two redraws of the interactive remote console
+    #[arg(long, default_value_t = 50)]
+    console_refresh_ms: u64,
+
+    // only print the result of each step, warnings and errors
+    #[arg(long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    // also print every remote line as it arrives and every file left out of the uploads
+    #[arg(long)]
+    verbose: bool,
+
+    // plain line by line output without cursor movement or ESC, implied when stdout isn't a terminal
+    #[arg(long)]
+    no_tty: bool,
+
+    // `json` prints the --report document on stdout once done and implies --quiet --no-tty
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Output::Text,
+        conflicts_with_all = ["explain", "plan", "validate"]
+    )]
+    output: Output,
+
+    // console only, nothing is written to .deployments (also DEPLOY_NO_LOGFILE)
+    #[arg(long)]
+    no_logfile: bool,
+
+    // status markers, `accessible` uses text instead of relying on color
+    #[arg(long, value_enum, default_value_t = theme::Symbols::Default)]
+    symbols: theme::Symbols,
+
+    // bytes per second of the uploads like 5MB/s, replacing max_bandwidth of the config
+    #[arg(long, value_parser = config::parse_rate)]
+    limit_rate: Option<u64>,
+
+    // fail the deployment once it runs longer, like 30m, killing the remote command running then
+    #[arg(long, value_parser = config::parse_duration)]
+    timeout: Option<Duration>,
+
+    // fail before deploying if local files drifted from the lock file
+    #[arg(long)]
+    verify_lock: bool,
+
+    // point `current` back to the previous release instead of deploying
+    #[arg(long, conflicts_with = "dry_run")]
+    rollback: bool,
+
+    // list the releases on the servers, with the one `current` points to
+    #[arg(
+        long,
+        conflicts_with_all = ["rollback", "explain", "plan", "validate", "watch", "all"]
+    )]
+    releases: bool,
+
+    // list the files each upload would add or change on the servers, and those only there
+    #[arg(
+        long,
+        conflicts_with_all = ["rollback", "releases", "explain", "plan", "validate", "watch", "all"]
+    )]
+    diff: bool,
+
+    // check that the files the lock file records as uploaded are unchanged on the servers
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "diff", "rollback", "releases", "explain", "plan", "validate", "watch", "all"
+        ]
+    )]
+    verify: bool,
+
+    // release to roll back to, defaults to the one before `current`
+    #[arg(long, requires = "rollback")]
+    to: Option<String>,
+
+    // put back what the uploads with backup = true overwrote, from the latest backup or the one named
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "latest",
+        conflicts_with_all = ["rollback", "dry_run", "releases", "watch", "all"]
+    )]
+    restore_backup: Option<String>,
+
+    // download this server path, or the target folder of every upload, instead of deploying
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "",
+        conflicts_with_all = ["rollback", "restore_backup", "dry_run", "releases", "watch", "all"]
+    )]
+    pull: Option<String>,
+
+    // run this command on the servers instead of deploying, with the remote console and the log file
+    // of a deployment
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "rollback", "restore_backup", "pull", "dry_run", "releases", "diff", "verify", "watch",
+            "all"
+        ]
+    )]
+    run: Option<String>,
+
+    // open a shell on the server, or on the one of the hosts given, in the deployed folder
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "",
+        conflicts_with_all = [
+            "rollback", "restore_backup", "pull", "run", "dry_run", "releases", "diff", "verify",
+            "watch", "all"
+        ]
+    )]
+    ssh: Option<String>,
+
+    // follow the remote_log of the config on the servers, one after the other
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "rollback", "restore_backup", "pull", "run", "ssh", "dry_run", "releases", "diff",
+            "verify", "watch", "all"
+        ]
+    )]
+    logs: bool,
+
+    // report whether the service of the [status] config is up on the servers, and what's deployed
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "rollback", "restore_backup", "pull", "run", "ssh", "logs", "dry_run", "releases",
+            "diff", "verify", "watch", "all"
+        ]
+    )]
+    status: bool,
+
+    // remove the deployment from the servers: base_path with the releases strategy, else the target
+    // folder of every upload
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "rollback", "restore_backup", "pull", "run", "ssh", "logs", "status", "dry_run",
+            "releases", "diff", "verify", "watch", "all"
+        ]
+    )]
+    clean: bool,
+
+    // confirm --clean without being asked
+    #[arg(long, requires = "clean")]
+    yes: bool,
+
+    // deploy the hosts after those of a `pause = "manual"` canary without asking
+    #[arg(long)]
+    continue_canary: bool,
+
+    // local folder --pull downloads into, `pulled` by default
+    #[arg(long, requires = "pull")]
+    into: Option<String>,
+
+    // skip the steps the last failed deployment of each config completed
+    #[arg(long, conflicts_with = "rollback")]
+    resume: bool,
+
+    // deploy, then again each time the uploaded files change: the uploads and the watch.reload steps
+    #[arg(
+        long,
+        conflicts_with_all = ["all", "explain", "plan", "validate", "rollback", "resume"]
+    )]
+    watch: bool,
+}
+
+impl Cli {
+    // instead of deploying, a command run on the servers of the given config with a log file
+    fn remote_command(&self) -> bool {
+        let restore = self.rollback || self.restore_backup.is_some();
+        restore || self.pull.is_some() || self.run.is_some() || self.logs || self.clean
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    let args = Cli::parse();
-
-    if args.info {
-        info::get_info();
-    } else if let Some(start_folder) = args.find {
-        let mut found: u32 = 0;
-        let mut action = |path: PathBuf| {
-            if let Ok(metadata) = fs::metadata(&path) {
-                let modified_date = DateTime::<Local>::from(
-                    metadata.modified().unwrap_or_else(|_| SystemTime::now()),
-                )
-                .format("%Y-%m-%d %H:%M:%S");
+    let mut args = Cli::parse();
+    // report invalid color overrides before any output
+    lazy_static::initialize(&THEME);
+    theme::set_symbols(args.symbols);
+    let json_output = args.output == Output::Json;
+    logger::set_json_output(json_output);
+    logger::set_interactive(!args.no_tty && !json_output && io::stdout().is_terminal());
+
+    if args.all {
+        let start_folder = args.find.take().unwrap_or_else(|| ".".to_string());
+        deploy_all(&start_folder, &args).await;
+        return;
+    }
+    if let Some(start_folder) = args.find.take() {
+        match find_config(&start_folder) {
+            Some(config_path) => args.file = Some(config_path),
+            None => return,
+        }
+    }
+
+    if args.init {
+        if let Err(e) = init::run(args.file.as_deref()) {
+            eprintln!(
+                "{} {}",
+                THEME.error_label("Error:"),
+                e.to_string().color(THEME.error)
+            );
+            std::process::exit(1);
+        }
+    } else if args.info {
+        // with a config file, its logs are listed from where it writes them
+        let naming = match &args.file {
+            Some(config_path) => {
+                let deployments = load_deployments(config_path, &args);
+                log_naming(&deployments.last().unwrap().1, &args)
+            }
+            None => LogNaming::new(None, None, None),
+        };
+        info::get_info(&naming);
+    } else if let Some(config_path) = &args.file {
+        if workspace::is_workspace(config_path) {
+            deploy_workspace(config_path, &args).await;
+            return;
+        }
+        if args.validate {
+            let data = load_data(&args);
+            let problems = validate::validate(config_path, load_options(&args, data.as_ref()));
+            for problem in &problems {
+                eprintln!(
+                    "{} {}",
+                    THEME.error_label("Error:"),
+                    problem.to_string().color(THEME.error)
+                );
+            }
+            if !problems.is_empty() {
+                std::process::exit(1);
+            }
+            println!("{} {}", "Valid:".bright_black(), config_path);
+            return;
+        }
+        if args.watch {
+            watch_config(config_path, &args).await;
+            return;
+        }
+        let deployments = load_deployments(config_path, &args);
+        check_step_names(&deployments, &args);
+
+        // the retention of the given config applies, not that of its dependencies
+        let (_, root) = deployments.last().unwrap();
+        let log_retention = root.log_retention.clone();
+        let naming = log_naming(root, &args);
+        let prune_logs = || {
+            if let Some(log_retention) = &log_retention {
+                retention::apply(log_retention, &naming);
+            }
+        };
+        if args.releases {
+            // only those of the given config, not of its dependencies
+            let (_, config) = deployments.last().unwrap();
+            for config in config::for_each_host(config.clone()) {
+                if let Err(e) = actions::runner::list_releases(&config).await {
+                    eprintln!("{} {}", THEME.error_label("Error:"), e.color(THEME.error));
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        if args.diff {
+            // only the given config, not its dependencies
+            let (_, config) = deployments.last().unwrap();
+            for config in config::for_each_host(config.clone()) {
+                let skip = skipped_steps(&config, &args);
+                if let Err(e) = actions::runner::diff(&config, &skip).await {
+                    eprintln!("{} {}", THEME.error_label("Error:"), e.color(THEME.error));
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        if let Some(host) = &args.ssh {
+            let (_, config) = deployments.last().unwrap();
+            let configs = config::for_each_host(config.clone());
+            let count = configs.len();
+            let picked = configs
+                .into_iter()
+                .find(|config| host.is_empty() || config.server.host == *host);
+            let config = match picked {
+                Some(config) => config,
+                None => {
+                    let e = format!("no host '{}' in the config", host);
+                    eprintln!("{} {}", THEME.error_label("Error:"), e.color(THEME.error));
+                    std::process::exit(1);
+                }
+            };
+            if count > 1 && host.is_empty() {
                 println!(
-                    "{:50} {:10} {}",
-                    path.to_string_lossy().green(),
-                    format!("{}B", metadata.len()).blue(),
-                    modified_date.to_string().yellow()
+                    "{} {}, the first of {} hosts, pick another with --ssh <host>",
+                    "Connecting:".bright_black(),
+                    config.server.host,
+                    count
                 );
-                found += 1;
             }
+            match actions::runner::open_shell(&config) {
+                Ok(code) => std::process::exit(code),
+                Err(e) => {
+                    eprintln!("{} {}", THEME.error_label("Error:"), e.color(THEME.error));
+                    std::process::exit(1);
+                }
+            }
+        }
+        if args.status {
+            let (_, config) = deployments.last().unwrap();
+            let mut all_up = true;
+            for config in config::for_each_host(config.clone()) {
+                match actions::status::status(&config).await {
+                    Ok(up) => all_up &= up,
+                    Err(e) => {
+                        eprintln!("{} {}", THEME.error_label("Error:"), e.color(THEME.error));
+                        std::process::exit(1);
+                    }
+                }
+            }
+            if !all_up {
+                std::process::exit(1);
+            }
+            return;
+        }
+        if args.verify {
+            let (path, config) = deployments.last().unwrap();
+            let lock_path = lock::lock_path(path);
+            let deploy_lock = match lock::load(&lock_path) {
+                Ok(deploy_lock) => deploy_lock,
+                Err(e) => {
+                    let e = format!("unable to read '{}': {}", lock_path.display(), e);
+                    eprintln!("{} {}", THEME.error_label("Error:"), e.color(THEME.error));
+                    std::process::exit(1);
+                }
+            };
+            let mut mismatches = 0;
+            for config in config::for_each_host(config.clone()) {
+                let skip = skipped_steps(&config, &args);
+                match actions::runner::verify(&config, &deploy_lock, &skip).await {
+                    Ok(count) => mismatches += count,
+                    Err(e) => {
+                        eprintln!("{} {}", THEME.error_label("Error:"), e.color(THEME.error));
+                        std::process::exit(1);
+                    }
+                }
+            }
+            if mismatches > 0 {
+                std::process::exit(1);
+            }
+            return;
+        }
+        if args.remote_command() {
+            // only the given config, not its dependencies
+            let (path, config) = deployments.last().unwrap();
+            if args.clean && !args.yes && !confirm_clean(config) {
+                std::process::exit(1);
+            }
+            let mut reports = Vec::new();
+            for config in config::for_each_host(config.clone()) {
+                let mut report = DeploymentReport::new(path, &config.server.host);
+                let mut logger = start_logger(path, &config, &args, None).await;
+                report.events = logger.events();
+                report.print_phases = args.quiet;
+                if args.clean {
+                    actions::runner::clean(&mut logger, &config, &mut report).await;
+                } else if args.logs {
+                    actions::runner::follow_logs(&mut logger, &config, &mut report).await;
+                } else if let Some(command) = args.run.as_deref() {
+                    actions::runner::run_command(&mut logger, &config, command, &mut report).await;
+                } else if let Some(remote) = args.pull.as_deref() {
+                    let remote = (!remote.is_empty()).then_some(remote);
+                    let into = args.into.as_deref().unwrap_or("pulled");
+                    // each host in its own folder
+                    let into = match config.hosts.len() {
+                        0 | 1 => into.to_string(),
+                        _ => format!("{}/{}", into.trim_end_matches('/'), config.server.host),
+                    };
+                    let skip = skipped_steps(&config, &args);
+                    actions::runner::pull(&mut logger, &config, remote, &into, &skip, &mut report)
+                        .await;
+                } else if let Some(backup) = args.restore_backup.as_deref() {
+                    let backup = (backup != "latest").then_some(backup);
+                    actions::runner::restore_backup(&mut logger, &config, backup, &mut report)
+                        .await;
+                } else {
+                    let to = args.to.as_deref();
+                    actions::runner::rollback(&mut logger, &config, to, &mut report).await;
+                }
+                let failed = report.error.is_some();
+                reports.push(report);
+                if failed {
+                    break;
+                }
+            }
+            print_output(&args, &reports);
+            prune_logs();
+            if let Some(code) = report::exit_code(&reports) {
+                std::process::exit(code);
+            }
+            return;
+        }
+
+        let reports = if args.explain || args.plan {
+            for (path, config) in deployments {
+                if args.explain {
+                    println!("{} {}", "Loading:".bright_black(), path);
+                    actions::runner::explain(&config, &skipped_steps(&config, &args));
+                    continue;
+                }
+                match &args.env {
+                    Some(env) => {
+                        println!("{} {} with --env {}", "Planning:".bright_black(), path, env)
+                    }
+                    None => println!("{} {}", "Planning:".bright_black(), path),
+                }
+                for config in config::for_each_host(config) {
+                    let skip = skipped_steps(&config, &args);
+                    actions::runner::plan(&config, &skip, args.force);
+                }
+            }
+            Vec::new()
+        } else {
+            // dependents don't start once a deployment failed
+            let args = &args;
+            let deploy_host =
+                |path: String, config| async move { deploy(&path, config, args, None).await };
+            let deployed = &mut HashMap::new();
+            deploy_in_order(deployments, deployed, args.continue_canary, deploy_host).await
         };
 
-        if let Err(e) = finder::find_deploy_files(&start_folder, &mut action) {
-            eprintln!("Error: {}", e.to_string().red());
+        write_reports(&args, &reports);
+        prune_logs();
+        if let Some(code) = report::exit_code(&reports) {
+            std::process::exit(code);
         }
+    }
+}
 
-        if found == 0 {
+// lists what --clean removes from each host, then asks to type yes
+fn confirm_clean(config: &config::Config) -> bool {
+    println!("{}", "Removing:".bright_black());
+    for config in config::for_each_host(config.clone()) {
+        for path in actions::runner::clean_paths(&config) {
+            println!("  {}:{}", config.server.host, path);
+        }
+    }
+    if !logger::interactive() {
+        eprintln!(
+            "{} without a terminal, pass --yes to confirm",
+            THEME.error_label("Error:")
+        );
+        return false;
+    }
+    print!("Type 'yes' to remove them for good: ");
+    if io::stdout().flush().is_err() {
+        return false;
+    }
+    let mut answer = String::new();
+    let confirmed = io::stdin().read_line(&mut answer).is_ok() && answer.trim() == "yes";
+    if !confirmed {
+        println!("{}", "Nothing was removed".color(THEME.muted));
+    }
+    confirmed
+}
+
+// the config picked among those found in the folder, None once they're listed instead
+fn find_config(start_folder: &str) -> Option<String> {
+    let mut paths = Vec::new();
+    if let Err(e) = finder::find_deploy_files(start_folder, &mut |path| paths.push(path)) {
+        eprintln!(
+            "{} {}",
+            THEME.error_label("Error:"),
+            e.to_string().color(THEME.error)
+        );
+    }
+
+    if paths.len() > 1 && logger::interactive() {
+        return match picker::pick(&paths) {
+            Ok(picked) => picked.map(|index| paths[index].to_string_lossy().to_string()),
+            Err(e) => {
+                eprintln!(
+                    "{} {}",
+                    THEME.error_label("Error:"),
+                    e.to_string().color(THEME.error)
+                );
+                None
+            }
+        };
+    }
+
+    let mut found: u32 = 0;
+    for path in paths {
+        if let Ok(metadata) = fs::metadata(&path) {
+            let modified_date =
+                DateTime::<Local>::from(metadata.modified().unwrap_or_else(|_| SystemTime::now()))
+                    .format("%Y-%m-%d %H:%M:%S");
             println!(
-                "{}",
-                "No .deploy.toml files found in the specified directory".yellow()
+                "{:50} {:10} {}",
+                path.to_string_lossy().green(),
+                format!("{}B", metadata.len()).blue(),
+                modified_date.to_string().yellow()
             );
-        } else {
-            println!("{} files found.", found.to_string().cyan());
+            found += 1;
         }
-    } else if let Some(config_path) = args.file {
-        let mut logger = Logger::new();
-        log!(logger, "{} {}", "Loading:".bright_black(), &config_path);
-        log!(
-            logger,
+    }
+    if found == 0 {
+        println!(
+            "{}",
+            "No .deploy.toml files found in the specified directory".yellow()
+        );
+    } else {
+        println!("{} files found.", found.to_string().cyan());
+    }
+    None
+}
+
+// every config found in the folder, one after the other: a failure stops its dependents, not the others
+async fn deploy_all(start_folder: &str, args: &Cli) {
+    let mut paths = Vec::new();
+    if let Err(e) = finder::find_deploy_files(start_folder, &mut |path| paths.push(path)) {
+        eprintln!(
             "{} {}",
-            "Timestamp:".bright_black(),
-            Utc::now().timestamp()
+            THEME.error_label("Error:"),
+            e.to_string().color(THEME.error)
         );
-        let config = config::load(&config_path);
-        match Repository::open(".") {
-            Ok(repo) => {
-                let head = repo.head().expect("Unable to access git HEAD");
-                let head = head
-                    .peel_to_commit()
-                    .expect("Unable to pull reference to HEAD commit");
-                log!(
-                    logger,
-                    "{} {}",
-                    "Commit hash:".bright_black(),
-                    head.id().to_string()
+        std::process::exit(1);
+    }
+    if paths.is_empty() {
+        println!(
+            "{}",
+            "No .deploy.toml files found in the specified directory".yellow()
+        );
+        return;
+    }
+    paths.sort();
+
+    // all are loaded first, a broken config or a --skip typo stops the run before anything is deployed
+    let configs: Vec<_> = paths
+        .iter()
+        .map(|path| load_deployments(&path.to_string_lossy(), args))
+        .collect();
+    check_step_names(&configs.concat(), args);
+
+    // a config shared as a dependency is deployed once
+    let mut deployed = HashMap::new();
+    let mut reports = Vec::new();
+    for deployments in configs {
+        let root = deployments.last().unwrap().1.clone();
+        let deploy_host =
+            |path: String, config| async move { deploy(&path, config, args, None).await };
+        let continue_canary = args.continue_canary;
+        let deployed = &mut deployed;
+        reports.extend(deploy_in_order(deployments, deployed, continue_canary, deploy_host).await);
+        if let Some(log_retention) = &root.log_retention {
+            retention::apply(log_retention, &log_naming(&root, args));
+        }
+    }
+
+    report::print_summary(&reports);
+    write_reports(args, &reports);
+    if let Some(code) = report::exit_code(&reports) {
+        std::process::exit(code);
+    }
+}
+
+// deploys each config on each of its hosts, dependencies first; once one failed, the configs after
+// it are reported skipped instead. deployed holds those an earlier call deployed, canonical path
+// -> whether it failed, so that a shared dependency goes once
+async fn deploy_in_order<F: Future<Output = DeploymentReport>>(
+    deployments: Vec<(String, config::Config)>,
+    deployed: &mut HashMap<PathBuf, bool>,
+    continue_canary: bool,
+    mut deploy_host: impl FnMut(String, config::Config) -> F,
+) -> Vec<DeploymentReport> {
+    let mut reports = Vec::new();
+    let mut failed: Option<String> = None;
+    for (path, config) in deployments {
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| PathBuf::from(&path));
+        if let Some(&dependency_failed) = deployed.get(&canonical) {
+            if dependency_failed && failed.is_none() {
+                failed = Some(path);
+            }
+            continue;
+        }
+        if let Some(dependency) = &failed {
+            console!(
+                "{} '{}', '{}' failed before it",
+                "Skipped:".bright_black(),
+                path,
+                dependency
+            );
+            for config in config::for_each_host(config) {
+                let mut report = DeploymentReport::new(&path, &config.server.host);
+                report.status = Status::Skipped;
+                reports.push(report);
+            }
+            deployed.insert(canonical, true);
+            continue;
+        }
+        let hosts = config::for_each_host(config);
+        // the pause comes once the first hosts succeeded, when others are left
+        let canary = hosts[0].canary.clone();
+        let canary = canary.filter(|canary| canary.first < hosts.len());
+        for (index, config) in hosts.into_iter().enumerate() {
+            let pause = canary.as_ref().filter(|canary| canary.first == index);
+            if let Some(canary) = pause {
+                if !canary_gate(canary.pause, continue_canary).await {
+                    let mut report = DeploymentReport::new(&path, &config.server.host);
+                    let error = "the canary hosts weren't confirmed".to_string();
+                    report.fail("canary", Duration::ZERO, error);
+                    reports.push(report);
+                    failed = Some(path.clone());
+                    break;
+                }
+            }
+            let report = deploy_host(path.clone(), config).await;
+            let host_failed = report.error.is_some();
+            reports.push(report);
+            if host_failed {
+                failed = Some(path.clone());
+                break;
+            }
+        }
+        deployed.insert(canonical, failed.is_some());
+    }
+    reports
+}
+
+// the pause once the canary hosts succeeded, false when the other hosts aren't to be deployed
+async fn canary_gate(pause: config::Pause, continue_canary: bool) -> bool {
+    match pause {
+        config::Pause::Delay(delay) => {
+            console!(
+                "{} waiting {:?} before the other hosts",
+                "Canary:".bright_black(),
+                delay
+            );
+            tokio::time::sleep(delay).await;
+            true
+        }
+        config::Pause::Manual if continue_canary => true,
+        config::Pause::Manual if !logger::interactive() => {
+            eprintln!(
+                "{} the canary hosts are deployed, without a terminal pass --continue-canary to \
+                 deploy the others",
+                THEME.error_label("Error:")
+            );
+            false
+        }
+        config::Pause::Manual => {
+            eprint!("Canary hosts deployed, type 'yes' to deploy the others: ");
+            let mut answer = String::new();
+            let confirmed = io::stdin().read_line(&mut answer).is_ok() && answer.trim() == "yes";
+            if !confirmed {
+                console!("{}", "The other hosts are not deployed".color(THEME.muted));
+            }
+            confirmed
+        }
+    }
+}
+
+// the members of a workspace, each as soon as those it depends on succeeded, stopping its
+// dependents when it fails
+async fn deploy_workspace(workspace_path: &str, args: &Cli) {
+    let remote = args.remote_command() || args.releases || args.diff || args.verify;
+    let remote = remote || args.ssh.is_some() || args.status;
+    let members = if args.validate || args.plan || args.explain || remote {
+        Err("only deployments take a workspace, the other commands a member's config".to_string())
+    } else {
+        workspace::load(workspace_path)
+    };
+    let members = match members {
+        Ok(members) => members,
+        Err(e) => {
+            eprintln!("{} {}", THEME.error_label("Error:"), e.color(THEME.error));
+            std::process::exit(1);
+        }
+    };
+    // all are loaded first, a broken config or a --skip typo stops the run before anything is deployed
+    let mut configs: Vec<_> = members
+        .iter()
+        .map(|member| Some(load_deployments(&member.path, args)))
+        .collect();
+    let deployments: Vec<_> = configs.iter().flatten().flatten().cloned().collect();
+    check_step_names(&deployments, args);
+    // the retention of each member's config applies, not that of its dependencies
+    let log_retention: Vec<_> = configs
+        .iter()
+        .flatten()
+        .filter_map(|deployments| {
+            let (_, root) = deployments.last().unwrap();
+            Some((root.log_retention.clone()?, log_naming(root, args)))
+        })
+        .collect();
+
+    // a chain of members keeps the interactive console, members running at once can't share it
+    let concurrent =
+        (1..members.len()).any(|index| !members[index].depends_on.contains(&(index - 1)));
+    let mut reports: Vec<Vec<DeploymentReport>> = members.iter().map(|_| Vec::new()).collect();
+    let mut done = HashSet::new();
+    let mut failed = HashSet::new();
+    let mut started = HashSet::new();
+    let mut running = FuturesUnordered::new();
+    loop {
+        // the members are ordered after their dependencies, so a skip reaches every dependent
+        for (index, member) in members.iter().enumerate() {
+            if started.contains(&index) {
+                continue;
+            }
+            if let Some(&dependency) = member.depends_on.iter().find(|d| failed.contains(*d)) {
+                console!(
+                    "{} member '{}', its dependency '{}' didn't succeed",
+                    "Skipped:".bright_black(),
+                    member.name,
+                    members[dependency].name
                 );
+                for (path, config) in configs[index].take().unwrap_or_default() {
+                    for config in config::for_each_host(config) {
+                        let mut report = DeploymentReport::new(&path, &config.server.host);
+                        report.status = Status::Skipped;
+                        reports[index].push(report);
+                    }
+                }
+                started.insert(index);
+                failed.insert(index);
+                continue;
+            }
+            if !member.depends_on.iter().all(|d| done.contains(d)) {
+                continue;
             }
-            Err(_) => {}
+            started.insert(index);
+            let deployments = configs[index].take().unwrap_or_default();
+            let name = concurrent.then_some(member.name.as_str());
+            running.push(async move {
+                // like a single config: its dependencies first, stopping at the first failure
+                let mut member_reports = Vec::new();
+                'deployments: for (path, config) in deployments {
+                    for config in config::for_each_host(config) {
+                        let report = deploy(&path, config, args, name).await;
+                        let host_failed = report.error.is_some();
+                        member_reports.push(report);
+                        if host_failed {
+                            break 'deployments;
+                        }
+                    }
+                }
+                (index, member_reports)
+            });
+        }
+        let Some((index, member_reports)) = running.next().await else {
+            break;
+        };
+        if member_reports.iter().any(|report| report.error.is_some()) {
+            failed.insert(index);
+        }
+        done.insert(index);
+        reports[index] = member_reports;
+    }
+
+    let reports: Vec<_> = reports.into_iter().flatten().collect();
+    report::print_summary(&reports);
+    write_reports(args, &reports);
+    for (log_retention, naming) in &log_retention {
+        retention::apply(log_retention, naming);
+    }
+    if let Some(code) = report::exit_code(&reports) {
+        std::process::exit(code);
+    }
+}
+
+// deploys the config, then again after each change of the files it uploads, until interrupted;
+// its depends_on configs are deployed the first time only
+async fn watch_config(config_path: &str, args: &Cli) {
+    let deployments = load_deployments(config_path, args);
+    check_step_names(&deployments, args);
+    let (path, config) = deployments.last().unwrap().clone();
+    let debounce = config.watch.as_ref().map_or(300, |watch| watch.debounce_ms);
+    let debounce = Duration::from_millis(debounce);
+    let reload = config.watch.as_ref().map(|watch| watch.reload.clone());
+    let reload = reload.unwrap_or_default();
+    let skip = skipped_steps(&config, args);
+
+    // reruns only send the changed files, the cache knows the others, then reload
+    let mut rerun = args.clone();
+    rerun.resume = false;
+    rerun.force = false;
+    rerun.only = None;
+    let rerun_skip = step_names(&config).filter(|step| {
+        let upload = config.actions.iter().any(|action| {
+            action.name() == *step && matches!(action, config::Action::Upload { .. })
+        });
+        skip.contains(*step) || !(upload || reload.iter().any(|name| name == step))
+    });
+    rerun.skip = Some(rerun_skip.map(String::from).collect());
+
+    let mut run = args;
+    let mut deployments = deployments;
+    loop {
+        let mut reports = Vec::new();
+        'deployments: for (path, config) in deployments {
+            for config in config::for_each_host(config) {
+                let report = deploy(&path, config, run, None).await;
+                let failed = report.error.is_some();
+                reports.push(report);
+                if failed {
+                    break 'deployments;
+                }
+            }
+        }
+        write_reports(args, &reports);
+        if let Some(log_retention) = &config.log_retention {
+            retention::apply(log_retention, &log_naming(&config, args));
+        }
+
+        // taken after the deployment, so files written by its own build aren't seen as changes
+        let files = watch::snapshot(&config, &skip);
+        console!(
+            "{} {} files of '{}', ctrl-c to stop",
+            "Watching:".bright_black(),
+            files.len(),
+            path
+        );
+        // a file changed and changed back doesn't need a deployment
+        let changed = loop {
+            let changed = watch::wait_for_changes(&config, &skip, &files, debounce).await;
+            if !changed.is_empty() {
+                break changed;
+            }
+        };
+        let names: Vec<String> = changed
+            .iter()
+            .take(5)
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+        let more = match changed.len() {
+            count if count > names.len() => format!(" and {} more", count - names.len()),
+            _ => String::new(),
+        };
+        console!("{} {}{}", "Changed:".bright_black(), names.join(", "), more);
+        run = &rerun;
+        deployments = vec![(path.clone(), config.clone())];
+    }
+}
+
+// --junit and --report files, and the json report on stdout with --output json
+fn write_reports(args: &Cli, reports: &[DeploymentReport]) {
+    if let Some(junit_path) = &args.junit {
+        if let Err(e) = report::write_junit(junit_path, reports) {
+            eprintln!(
+                "Unable to write JUnit report: {}",
+                e.to_string().color(THEME.error)
+            );
         }
+    }
+    if let Some(report_path) = &args.report {
+        if let Err(e) = report::write_json(report_path, reports) {
+            eprintln!(
+                "Unable to write deployment report: {}",
+                e.to_string().color(THEME.error)
+            );
+        }
+    }
+    print_output(args, reports);
+}
 
-        actions::runner::execute_actions(
-            &mut logger,
-            config,
-            args.skip.unwrap_or_else(Vec::new).into_iter().collect(),
-        )
-        .await;
+// the report of every deployment for --output json, nothing for text
+fn print_output(args: &Cli, reports: &[DeploymentReport]) {
+    if args.output != Output::Json {
+        return;
+    }
+    match report::to_json(reports) {
+        Ok(json) => println!("{}", json),
+        Err(e) => eprintln!(
+            "Unable to print deployment report: {}",
+            e.to_string().color(THEME.error)
+        ),
+    }
+}
+
+// the `--data` file, exits when it can't be read
+fn load_data(args: &Cli) -> Option<serde_json::Value> {
+    match args.data.as_deref().map(config::load_data).transpose() {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("{} {}", THEME.error_label("Error:"), e.color(THEME.error));
+            std::process::exit(1);
+        }
+    }
+}
+
+fn load_options<'a>(args: &'a Cli, data: Option<&'a serde_json::Value>) -> config::LoadOptions<'a> {
+    config::LoadOptions {
+        data,
+        env: args.env.as_deref(),
+        set: &args.set,
+    }
+}
+
+// the config and its dependencies, exits on error
+fn load_deployments(config_path: &str, args: &Cli) -> Vec<(String, config::Config)> {
+    let data = load_data(args);
+    let options = load_options(args, data.as_ref());
+    match config::load_with_dependencies(config_path, options) {
+        Ok(deployments) => deployments,
+        Err(e) => {
+            eprintln!("{} {}", THEME.error_label("Error:"), e.color(THEME.error));
+            std::process::exit(1);
+        }
+    }
+}
+
+// --skip, and with --only every action and hook it leaves out
+fn skipped_steps(config: &config::Config, args: &Cli) -> HashSet<String> {
+    let mut skip: HashSet<String> = args.skip.iter().flatten().cloned().collect();
+    if let Some(only) = &args.only {
+        skip.extend(
+            step_names(config)
+                .filter(|step| !only.iter().any(|name| name == step))
+                .map(String::from),
+        );
+    }
+    skip
+}
+
+// the actions, hooks and healthcheck of a config, what --skip, --only and --resume refer to
+fn step_names(config: &config::Config) -> impl Iterator<Item = &str> {
+    config
+        .actions
+        .iter()
+        .map(|action| action.name())
+        .chain(config::HOOKS)
+        .chain(config.healthcheck.as_ref().map(|_| "healthcheck"))
+}
+
+// exits on a --skip or --only name that no deployed config has, most likely a typo
+fn check_step_names(deployments: &[(String, config::Config)], args: &Cli) {
+    let known: HashSet<&str> = deployments
+        .iter()
+        .flat_map(|(_, config)| step_names(config))
+        .collect();
+    let mut names = args.skip.iter().chain(args.only.iter()).flatten();
+    if let Some(name) = names.find(|name| !known.contains(name.as_str())) {
+        eprintln!(
+            "{} {}",
+            THEME.error_label("Error:"),
+            format!("no action or hook is named '{}'", name).color(THEME.error)
+        );
+        std::process::exit(1);
+    }
+}
+
+fn log_naming(config: &config::Config, args: &Cli) -> LogNaming {
+    LogNaming::new(
+        config.log_dir.as_deref(),
+        config.log_name.as_deref(),
+        args.env.as_deref(),
+    )
+}
+
+// the commit checked out in the current folder, None outside of a git repository
+fn git_revision() -> Option<String> {
+    let repo = Repository::open(".").ok()?;
+    let head = repo.head().ok()?.peel_to_commit().ok()?;
+    Some(head.id().to_string())
+}
+
+// opens the deployment log and writes its header, the member is set for workspace members
+// deploying alongside others
+async fn start_logger(
+    config_path: &str,
+    config: &config::Config,
+    args: &Cli,
+    member: Option<&str>,
+) -> Logger {
+    let no_logfile =
+        args.no_logfile || env::var_os("DEPLOY_NO_LOGFILE").is_some_and(|v| !v.is_empty());
+    let naming = (!no_logfile).then(|| log_naming(config, args));
+    let mut logger = Logger::new(naming.as_ref(), args.dry_run);
+    logger.set_log_format(config.log_format, &config.server.host);
+    log!(logger, "{} {}", "Loading:".bright_black(), config_path);
+    log!(
+        logger,
+        "{} {}",
+        "Timestamp:".bright_black(),
+        Utc::now().timestamp()
+    );
+    logger.set_durability(config.log_durability);
+    logger.set_console_mode(args.console_mode);
+    logger.set_console_height(config.console_height);
+    let quiet = args.quiet || args.output == Output::Json;
+    logger.set_verbosity(match (quiet, args.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (_, true) => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    });
+    logger.set_render_interval(Duration::from_millis(args.console_refresh_ms));
+    let redactions: Result<Vec<Regex>, _> = config.redact.iter().map(|p| Regex::new(p)).collect();
+    match redactions {
+        Ok(mut redactions) => {
+            let secrets = config.secret_values.iter();
+            redactions.extend(secrets.map(|secret| Regex::new(&regex::escape(secret)).unwrap()));
+            logger.set_redactions(redactions)
+        }
+        Err(e) => {
+            eprintln!(
+                "{} invalid redact pattern: {}",
+                THEME.error_label("Error:"),
+                e.to_string().color(THEME.error)
+            );
+            std::process::exit(1);
+        }
+    }
+    if let Some(revision) = git_revision() {
+        log!(logger, "{} {}", "Commit hash:".bright_black(), revision);
+    }
+    for (name, value) in &args.set {
+        log!(logger, "{} {}={}", "Set:".bright_black(), name, value);
+    }
+    // after the header lines, which --info reads
+    if config.hosts.len() > 1 {
+        logger.set_prefix(&config.server.host);
+    }
+    if let Some(member) = member {
+        logger.set_member(member);
+    }
+    logger
+}
+
+async fn deploy(
+    config_path: &str,
+    config: config::Config,
+    args: &Cli,
+    member: Option<&str>,
+) -> DeploymentReport {
+    let mut report = DeploymentReport::new(config_path, &config.server.host);
+    if ABORTED.load(Ordering::SeqCst) {
+        report.abort(Duration::ZERO);
+        return report;
+    }
+    let mut logger = start_logger(config_path, &config, args, member).await;
+    report.events = logger.events();
+    report.print_phases = args.quiet;
+    let started = tokio::time::Instant::now();
+    let deadline = args.timeout.map(|timeout| started + timeout);
+    logger.set_deadline(deadline);
+
+    let lock_path = lock::lock_path(config_path);
+    let loaded = if lock_path.exists() {
+        lock::load(&lock_path)
+    } else {
+        Ok(lock::Lock::new())
+    };
+    let mut deploy_lock = match loaded {
+        Ok(deploy_lock) => deploy_lock,
+        Err(e) => {
+            let error = format!("unable to read '{}': {}", lock_path.display(), e);
+            log_error!(logger, "{} {}", THEME.error_label("Error:"), error);
+            report.fail("verify_lock", started.elapsed(), error);
+            return report;
+        }
+    };
+    if args.verify_lock {
+        let started = report.start("verify_lock");
+        let drift = lock::verify(&config, &deploy_lock);
+        if !drift.is_empty() {
+            for difference in &drift {
+                log_error!(logger, "{} {}", THEME.error_label("Drift:"), difference);
+            }
+            log_error!(
+                logger,
+                "{} local files don't match '{}'",
+                THEME.error_label("Error:"),
+                lock_path.display()
+            );
+            let error = format!("{} differences with the lock file", drift.len());
+            report.fail("verify_lock", started.elapsed(), error);
+            return report;
+        }
+        log!(
+            logger,
+            "{} local files match '{}'",
+            "Verified:".bright_black(),
+            lock_path.display()
+        );
+        report.record("verify_lock", started.elapsed(), None);
+    }
+
+    let checkpoint_key = checkpoint::key(config_path, &config.server.host);
+    let resumed = if args.resume {
+        checkpoint::get(&checkpoint_key)
+    } else {
+        None
+    };
+    let mut skip = skipped_steps(&config, args);
+    match &resumed {
+        Some(resumed) if !resumed.completed.is_empty() => {
+            log!(
+                logger,
+                "{} skipping {} completed by the last deployment",
+                "Resuming:".bright_black(),
+                resumed.completed.join(", ")
+            );
+            skip.extend(resumed.completed.iter().cloned());
+        }
+        Some(_) => {
+            log!(
+                logger,
+                "{} the last deployment completed no step",
+                "Resuming:".bright_black()
+            );
+        }
+        None if args.resume => {
+            log!(
+                logger,
+                "{} no failed deployment to resume, deploying everything",
+                "Resuming:".bright_black()
+            );
+        }
+        None => {}
+    }
+    let steps: Vec<String> = step_names(&config).map(String::from).collect();
+
+    let options = actions::runner::Options {
+        skip,
+        skip_unreadable: args.skip_unreadable,
+        report_unused_ignores: args.report_unused_ignores,
+        dry_run: args.dry_run,
+        force: args.force,
+        limit_rate: args.limit_rate,
+        release: resumed.as_ref().and_then(|resumed| resumed.release.clone()),
+        env: args.env.clone(),
+        revision: git_revision(),
+        delete: args.delete,
+    };
+    let execution = actions::runner::execute_actions(&mut logger, config, options, &mut report);
+    // remote commands are killed at the deadline, the grace lets them report it first
+    let execution = async {
+        match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline + TIMEOUT_GRACE, execution).await,
+            None => Ok(execution.await),
+        }
+    };
+    // ctrl+c drops the deployment where it is, what it did is still reported
+    let result = tokio::select! {
+        result = execution => Some(result),
+        Ok(()) = tokio::signal::ctrl_c() => None,
+    };
+    let Some(result) = result else {
+        ABORTED.store(true, Ordering::SeqCst);
+        log_error!(
+            logger,
+            "{} the deployment was interrupted",
+            THEME.error_label("Aborted:")
+        );
+        report.abort(started.elapsed());
+        if !args.dry_run {
+            update_checkpoint(&checkpoint_key, resumed, &steps, &report);
+        }
+        return report;
+    };
+    let Ok(uploaded) = result else {
+        log_error!(
+            logger,
+            "{} the deployment ran longer than {}s",
+            THEME.error_label("Timeout:"),
+            args.timeout.unwrap_or_default().as_secs()
+        );
+        let error = "deployment timed out".to_string();
+        report.fail("timeout", started.elapsed(), error);
+        if !args.dry_run {
+            update_checkpoint(&checkpoint_key, resumed, &steps, &report);
+        }
+        return report;
+    };
+    if !args.dry_run {
+        update_checkpoint(&checkpoint_key, resumed, &steps, &report);
+    }
+    if report.error.is_none() && !uploaded.is_empty() {
+        deploy_lock.extend(uploaded);
+        if let Err(e) = lock::save(&lock_path, &deploy_lock) {
+            eprintln!(
+                "Unable to write lock file: {}",
+                e.to_string().color(THEME.error)
+            );
+        }
+    }
+    report
+}
+
+// a failed deployment keeps the steps it completed for --resume, a successful one clears them
+fn update_checkpoint(
+    key: &str,
+    resumed: Option<checkpoint::Checkpoint>,
+    steps: &[String],
+    report: &DeploymentReport,
+) {
+    let result = match report.status {
+        Status::Success => checkpoint::forget(key),
+        Status::Skipped => return,
+        Status::Failed | Status::Aborted | Status::RolledBack => {
+            let mut checkpoint = resumed.unwrap_or_default();
+            for phase in &report.phases {
+                let completed = phase.failure.is_none() && steps.contains(&phase.name);
+                if completed && !checkpoint.completed.contains(&phase.name) {
+                    checkpoint.completed.push(phase.name.clone());
+                }
+            }
+            checkpoint.release = report.release.clone();
+            checkpoint::record(key, checkpoint)
+        }
+    };
+    if let Err(e) = result {
+        eprintln!(
+            "Unable to write checkpoint: {}",
+            e.to_string().color(THEME.error)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a config logging to logs/ in a temporary folder
+    fn logged_config(name: &str) -> (PathBuf, config::Config) {
+        let folder = env::temp_dir().join(format!("deploy-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let config_path = folder.join("deploy.toml");
+        let contents = format!(
+            r#"
+            log_dir = "{}"
+
+            [server]
+            host = "localhost"
+            user = "ubuntu"
+            ssh_key = "~/.ssh/id_ed25519"
+
+            [[actions]]
+            type = "commands"
+            name = "build"
+            commands = ["make"]
+            "#,
+            folder.join("logs").display()
+        );
+        fs::write(&config_path, contents).unwrap();
+        let options = config::LoadOptions::default();
+        let config = config::load(config_path.to_str().unwrap(), options).unwrap();
+        (folder, config)
+    }
+
+    #[test]
+    fn no_logfile_writes_nothing_to_the_log_folder() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        for (flags, logged) in [
+            (vec!["deploy"], true),
+            (vec!["deploy", "--no-logfile"], false),
+        ] {
+            let (folder, config) = logged_config(if logged { "logfile" } else { "no-logfile" });
+            let args = Cli::parse_from(flags);
+            let mut logger = runtime.block_on(start_logger("deploy.toml", &config, &args, None));
+            runtime.block_on(logger.log(logger::Level::Info, "deployed".to_string()));
+            let exists = folder.join("logs").exists();
+            fs::remove_dir_all(&folder).unwrap();
+            assert_eq!(exists, logged);
+        }
+    }
+
+    #[test]
+    fn dependents_of_a_failed_config_are_skipped() {
+        let folder = env::temp_dir().join(format!("deploy-order-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let write = |name: &str, depends_on: &[&str]| {
+            let contents = format!(
+                "actions = []\ndepends_on = {:?}\n\n[server]\nhost = \"{}.example.com\"\n\
+                 user = \"ubuntu\"\nssh_key = \"~/.ssh/id_ed25519\"\n",
+                depends_on, name
+            );
+            let path = folder.join(format!("{}.toml", name));
+            fs::write(&path, contents).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        write("database", &[]);
+        write("backend", &["database.toml"]);
+        let frontend = write("frontend", &["backend.toml"]);
+        let admin = write("admin", &["database.toml"]);
+        let load = |path: &str| config::load_with_dependencies(path, Default::default()).unwrap();
+        let (frontend, admin) = (load(&frontend), load(&admin));
+
+        // the database fails, whatever depends on it never starts
+        let deployed_hosts = std::cell::RefCell::new(Vec::new());
+        let deploy_host = |path: String, config: config::Config| {
+            let mut report = DeploymentReport::new(&path, &config.server.host);
+            if config.server.host.starts_with("database") {
+                let error = "unreachable".to_string();
+                report.fail("connect", std::time::Duration::ZERO, error);
+            }
+            deployed_hosts.borrow_mut().push(config.server.host);
+            async { report }
+        };
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut deployed = HashMap::new();
+        let deployed = &mut deployed;
+        let mut reports = runtime.block_on(deploy_in_order(frontend, deployed, false, deploy_host));
+        reports.extend(runtime.block_on(deploy_in_order(admin, deployed, false, deploy_host)));
+        fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(*deployed_hosts.borrow(), ["database.example.com"]);
+        let statuses: Vec<(&str, Status)> = reports
+            .iter()
+            .map(|report| (report.host.as_str(), report.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("database.example.com", Status::Failed),
+                ("backend.example.com", Status::Skipped),
+                ("frontend.example.com", Status::Skipped),
+                ("admin.example.com", Status::Skipped),
+            ]
+        );
+    }
+
+    #[test]
+    fn hosts_after_the_canary_wait_for_the_pause() {
+        let folder = env::temp_dir().join(format!("deploy-canary-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("deploy.toml");
+        let contents = r#"
+            actions = []
+            canary = { first = 1, pause = "200ms" }
+
+            [[hosts]]
+            host = "web1.example.com"
+            user = "ubuntu"
+            ssh_key = "~/.ssh/id_ed25519"
+
+            [[hosts]]
+            host = "web2.example.com"
+            user = "ubuntu"
+            ssh_key = "~/.ssh/id_ed25519"
+            "#;
+        fs::write(&path, contents).unwrap();
+        let path = path.to_string_lossy().into_owned();
+        let deployments = config::load_with_dependencies(&path, Default::default()).unwrap();
+        fs::remove_dir_all(&folder).unwrap();
+
+        let started = std::cell::RefCell::new(Vec::new());
+        let deploy_host = |path: String, config: config::Config| {
+            started.borrow_mut().push(std::time::Instant::now());
+            let report = DeploymentReport::new(&path, &config.server.host);
+            async { report }
+        };
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let deployed = &mut HashMap::new();
+        let reports = runtime.block_on(deploy_in_order(deployments, deployed, false, deploy_host));
+        assert_eq!(reports.len(), 2);
+        let started = started.borrow();
+        assert!(started[1] - started[0] >= Duration::from_millis(200));
     }
 }
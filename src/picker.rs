@@ -0,0 +1,144 @@
+use crate::logger::RawMode;
+use crate::theme::THEME;
+use colored::Colorize;
+use crossterm::{
+    cursor::{MoveToColumn, MoveUp},
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    style::Print,
+    terminal::{Clear, ClearType},
+};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+// rows of paths shown at once, the list scrolls with the selection
+const VISIBLE: usize = 10;
+
+// lower is a closer match, None when the query letters aren't all in the path in order
+fn fuzzy_score(query: &str, candidate: &str) -> Option<usize> {
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    for letter in query.to_lowercase().chars() {
+        let found = candidate[position..]
+            .iter()
+            .position(|&other| other == letter)?;
+        // letters far apart in the path make a weaker match
+        score += found;
+        position += found + 1;
+    }
+    Some(score)
+}
+
+// indexes of the paths matching the query, closest first
+fn matching(paths: &[PathBuf], query: &str) -> Vec<usize> {
+    let mut matches: Vec<(usize, usize)> = paths
+        .iter()
+        .enumerate()
+        .filter_map(|(index, path)| {
+            fuzzy_score(query, &path.to_string_lossy()).map(|score| (score, index))
+        })
+        .collect();
+    matches.sort();
+    matches.into_iter().map(|(_, index)| index).collect()
+}
+
+// the prompt and visible paths, returns how many lines were printed
+fn draw(
+    out: &mut impl Write,
+    paths: &[PathBuf],
+    query: &str,
+    matches: &[usize],
+    selected: usize,
+) -> io::Result<u16> {
+    execute!(
+        out,
+        Print(format!(
+            "{} {}{}",
+            "Pick a config".bold(),
+            "(type to filter, ↑↓ to move, enter to deploy, esc to cancel): ".color(THEME.muted),
+            query
+        ))
+    )?;
+    let mut lines = 1;
+    if matches.is_empty() {
+        execute!(
+            out,
+            Print(format!(
+                "\r\n  {}",
+                "no matching config".color(THEME.warning)
+            ))
+        )?;
+        lines += 1;
+    }
+    let first = selected.saturating_sub(VISIBLE - 1);
+    for (row, &index) in matches.iter().enumerate().skip(first).take(VISIBLE) {
+        let path = paths[index].to_string_lossy();
+        let line = if row == selected {
+            format!("\r\n{} {}", ">".color(THEME.success), path.bold())
+        } else {
+            format!("\r\n  {}", path)
+        };
+        execute!(out, Print(line))?;
+        lines += 1;
+    }
+    if matches.len() > first + VISIBLE {
+        let more = format!("\r\n  {} more", matches.len() - first - VISIBLE);
+        execute!(out, Print(more.color(THEME.muted)))?;
+        lines += 1;
+    }
+    out.flush()?;
+    Ok(lines)
+}
+
+// removes what draw printed, leaving the cursor where the prompt started
+fn clear(out: &mut impl Write, lines: u16) -> io::Result<()> {
+    if lines > 1 {
+        execute!(out, MoveUp(lines - 1))?;
+    }
+    execute!(out, MoveToColumn(0), Clear(ClearType::FromCursorDown))
+}
+
+// lets the user choose one of the paths with the arrow keys and a fuzzy filter, None when cancelled
+pub fn pick(paths: &[PathBuf]) -> io::Result<Option<usize>> {
+    let mut out = io::stdout();
+    let _raw_mode = RawMode::enable();
+    let mut query = String::new();
+    let mut matches = matching(paths, &query);
+    let mut selected = 0;
+    let mut lines = draw(&mut out, paths, &query, &matches, selected)?;
+    loop {
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => break,
+            KeyCode::Char('c') | KeyCode::Char('d') if ctrl => break,
+            KeyCode::Enter if !matches.is_empty() => {
+                clear(&mut out, lines)?;
+                return Ok(Some(matches[selected]));
+            }
+            KeyCode::Up => selected = selected.saturating_sub(1),
+            KeyCode::Down if selected + 1 < matches.len() => selected += 1,
+            KeyCode::Backspace => {
+                query.pop();
+                matches = matching(paths, &query);
+                selected = 0;
+            }
+            KeyCode::Char(letter) if !ctrl => {
+                query.push(letter);
+                matches = matching(paths, &query);
+                selected = 0;
+            }
+            _ => continue,
+        }
+        clear(&mut out, lines)?;
+        lines = draw(&mut out, paths, &query, &matches, selected)?;
+    }
+    clear(&mut out, lines)?;
+    Ok(None)
+}
@@ -0,0 +1,476 @@
+use crate::console;
+use crate::events::EventLog;
+use crate::theme::THEME;
+use clap::ValueEnum;
+use colored::Colorize;
+use serde::{Serialize, Serializer};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    time::{Duration, Instant},
+};
+
+// what the run prints on stdout
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Output {
+    Text,
+    // the json report once every deployment ran, the console goes to stderr
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Success,
+    Failed,
+    // vetoed by the preflight command
+    Skipped,
+    // stopped by ctrl+c
+    Aborted,
+    // failed its healthcheck, and the release it replaced is current again
+    #[serde(rename = "rolled back")]
+    RolledBack,
+}
+
+#[derive(Serialize)]
+pub struct Phase {
+    pub name: String,
+    #[serde(serialize_with = "seconds")]
+    pub duration: Duration,
+    pub exit_status: Option<u32>,
+    pub failure: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct UploadedFile {
+    pub path: String,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+// a file of an upload's source folder that wasn't uploaded
+#[derive(Serialize)]
+pub struct SkippedFile {
+    pub action: String,
+    pub path: String,
+    pub reason: String,
+}
+
+#[derive(Serialize)]
+pub struct DeploymentReport {
+    pub config: String,
+    pub host: String,
+    pub status: Status,
+    pub phases: Vec<Phase>,
+    // upload action name -> files it uploaded
+    pub files: BTreeMap<String, Vec<UploadedFile>>,
+    pub bytes: u64,
+    pub skipped: Vec<SkippedFile>,
+    // set when the deployment stopped early
+    pub error: Option<String>,
+    // where the phases are also written as they start and finish
+    #[serde(skip)]
+    pub events: EventLog,
+    // print a line per finished phase, for --quiet where little else is shown
+    #[serde(skip)]
+    pub print_phases: bool,
+    // the release folder of the deployment, with the releases strategy
+    #[serde(skip)]
+    pub release: Option<String>,
+}
+
+impl DeploymentReport {
+    pub fn new(config: &str, host: &str) -> DeploymentReport {
+        DeploymentReport {
+            config: config.to_string(),
+            host: host.to_string(),
+            status: Status::Success,
+            phases: Vec::new(),
+            files: BTreeMap::new(),
+            bytes: 0,
+            skipped: Vec::new(),
+            error: None,
+            events: EventLog::default(),
+            print_phases: false,
+            release: None,
+        }
+    }
+
+    // a report for one step, merged back once the step finished
+    pub fn branch(&self) -> DeploymentReport {
+        let mut branch = DeploymentReport::new(&self.config, &self.host);
+        branch.events = self.events.clone();
+        branch.print_phases = self.print_phases;
+        branch
+    }
+
+    pub fn merge(&mut self, branch: DeploymentReport) {
+        self.phases.extend(branch.phases);
+        for (action, files) in branch.files {
+            self.add_files(&action, files);
+        }
+        self.skipped.extend(branch.skipped);
+        if branch.status == Status::Failed {
+            self.status = Status::Failed;
+            self.error = branch.error;
+        }
+    }
+
+    // returns the start of the phase, to be recorded with its elapsed time
+    pub fn start(&self, name: &str) -> Instant {
+        self.events.emit("step_started", json!({ "step": name }));
+        Instant::now()
+    }
+
+    pub fn record(&mut self, name: &str, duration: Duration, failure: Option<String>) {
+        self.record_command(name, duration, None, failure);
+    }
+
+    pub fn record_command(
+        &mut self,
+        name: &str,
+        duration: Duration,
+        exit_status: Option<u32>,
+        failure: Option<String>,
+    ) {
+        let phase = Phase {
+            name: name.to_string(),
+            duration,
+            exit_status,
+            failure,
+        };
+        self.events.emit(
+            "step_finished",
+            json!({
+                "step": name,
+                "duration": duration.as_secs_f64(),
+                "exit_status": exit_status,
+                "failure": phase.failure,
+            }),
+        );
+        if self.print_phases {
+            print_phase(&phase);
+        }
+        self.phases.push(phase);
+    }
+
+    pub fn fail(&mut self, name: &str, duration: Duration, error: String) {
+        self.fail_with(name, duration, None, error);
+    }
+
+    // a remote command whose exit status stops the deployment, and becomes that of the run
+    pub fn fail_command(
+        &mut self,
+        name: &str,
+        duration: Duration,
+        exit_status: u32,
+        error: String,
+    ) {
+        self.fail_with(name, duration, Some(exit_status), error);
+    }
+
+    // the deployment still failed, but the rollback that followed put the previous release back
+    pub fn mark_rolled_back(&mut self) {
+        self.events
+            .emit("rolled_back", json!({ "release": self.release }));
+        self.status = Status::RolledBack;
+    }
+
+    // ctrl+c stopped the deployment after the phases recorded so far
+    pub fn abort(&mut self, duration: Duration) {
+        self.fail_with("abort", duration, None, "deployment aborted".to_string());
+        self.status = Status::Aborted;
+    }
+
+    fn fail_with(
+        &mut self,
+        name: &str,
+        duration: Duration,
+        exit_status: Option<u32>,
+        error: String,
+    ) {
+        self.record_command(name, duration, exit_status, Some(error.clone()));
+        self.events
+            .emit("error", json!({ "step": name, "error": error }));
+        self.status = Status::Failed;
+        self.error = Some(error);
+    }
+
+    pub fn add_files(&mut self, action: &str, files: Vec<UploadedFile>) {
+        self.bytes += files.iter().map(|file| file.bytes).sum::<u64>();
+        self.files
+            .entry(action.to_string())
+            .or_default()
+            .extend(files);
+    }
+
+    pub fn add_skipped(&mut self, action: &str, path: String, reason: String) {
+        self.skipped.push(SkippedFile {
+            action: action.to_string(),
+            path,
+            reason,
+        });
+    }
+}
+
+fn print_phase(phase: &Phase) {
+    let seconds = phase.duration.as_secs_f64();
+    match &phase.failure {
+        Some(failure) => console!(
+            "{} {} ({:.1}s): {}",
+            THEME.error_label("Failed:"),
+            phase.name,
+            seconds,
+            failure
+        ),
+        None => console!(
+            "{} {} ({:.1}s)",
+            THEME.success_label("Done:"),
+            phase.name,
+            seconds
+        ),
+    }
+}
+
+fn seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// one testsuite per deployed config and host, one testcase per phase
+pub fn write_junit(path: &str, reports: &[DeploymentReport]) -> io::Result<()> {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    for report in reports {
+        let failures = report
+            .phases
+            .iter()
+            .filter(|phase| phase.failure.is_some())
+            .count();
+        let total: Duration = report.phases.iter().map(|phase| phase.duration).sum();
+        let suite = escape_xml(&report.config);
+        xml.push_str(&format!(
+            "  <testsuite name=\"{}\" hostname=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+            suite,
+            escape_xml(&report.host),
+            report.phases.len(),
+            failures,
+            total.as_secs_f64()
+        ));
+        for phase in &report.phases {
+            let testcase = format!(
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                escape_xml(&phase.name),
+                suite,
+                phase.duration.as_secs_f64()
+            );
+            match &phase.failure {
+                Some(failure) => xml.push_str(&format!(
+                    "{}>\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+                    testcase,
+                    escape_xml(failure),
+                    escape_xml(failure)
+                )),
+                None => xml.push_str(&format!("{}/>\n", testcase)),
+            }
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    fs::write(path, xml)
+}
+
+// a line per deployed config and host with its status and duration, then the count of each status
+pub fn print_summary(reports: &[DeploymentReport]) {
+    let config_width = reports.iter().map(|r| r.config.len()).max().unwrap_or(0);
+    let host_width = reports.iter().map(|r| r.host.len()).max().unwrap_or(0);
+    console!("{}", "Summary:".color(THEME.muted));
+    for report in reports {
+        let total: Duration = report.phases.iter().map(|phase| phase.duration).sum();
+        let status = match report.status {
+            Status::Success => format!("{:11}", "success").color(THEME.success),
+            Status::Failed => format!("{:11}", "failed").color(THEME.error),
+            Status::Skipped => format!("{:11}", "skipped").color(THEME.muted),
+            Status::Aborted => format!("{:11}", "aborted").color(THEME.error),
+            Status::RolledBack => format!("{:11}", "rolled back").color(THEME.warning),
+        };
+        // the step that failed, the error itself is printed with the deployment
+        let failed = report
+            .phases
+            .iter()
+            .rev()
+            .find(|phase| phase.failure.is_some());
+        let failed_step = match (&report.error, failed.or(report.phases.last())) {
+            (Some(_), Some(phase)) => format!(" at '{}'", phase.name),
+            _ => String::new(),
+        };
+        console!(
+            "  {:config_width$}  {:host_width$}  {} {:>7.1}s{}",
+            report.config,
+            report.host,
+            status,
+            total.as_secs_f64(),
+            failed_step,
+        );
+    }
+    let count = |status| reports.iter().filter(|r| r.status == status).count();
+    let others: String = [
+        (Status::RolledBack, "rolled back"),
+        (Status::Aborted, "aborted"),
+    ]
+    .into_iter()
+    .filter(|(status, _)| count(*status) > 0)
+    .map(|(status, name)| format!(", {} {}", count(status), name))
+    .collect();
+    console!(
+        "{} succeeded, {} failed, {} skipped{}",
+        count(Status::Success),
+        count(Status::Failed),
+        count(Status::Skipped),
+        others
+    );
+}
+
+// the exit status of the remote command that failed the run, 130 like a shell when ctrl+c
+// aborted it, 1 for other failures
+pub fn exit_code(reports: &[DeploymentReport]) -> Option<i32> {
+    if reports.iter().any(|r| r.status == Status::Aborted) {
+        return Some(130);
+    }
+    let failed = reports.iter().find(|report| report.error.is_some())?;
+    let status = failed.phases.last().and_then(|phase| phase.exit_status);
+    match status {
+        Some(status @ 1..=255) => Some(status as i32),
+        _ => Some(1),
+    }
+}
+
+#[derive(Serialize)]
+struct RunReport<'a> {
+    status: Status,
+    deployments: &'a [DeploymentReport],
+}
+
+// the run is aborted if ctrl+c stopped a deployment, fails if any deployment failed or was
+// rolled back, and is skipped if all were
+pub fn to_json(reports: &[DeploymentReport]) -> io::Result<String> {
+    let failed = |r: &DeploymentReport| matches!(r.status, Status::Failed | Status::RolledBack);
+    let status = if reports.iter().any(|r| r.status == Status::Aborted) {
+        Status::Aborted
+    } else if reports.iter().any(failed) {
+        Status::Failed
+    } else if !reports.is_empty() && reports.iter().all(|r| r.status == Status::Skipped) {
+        Status::Skipped
+    } else {
+        Status::Success
+    };
+    let report = RunReport {
+        status,
+        deployments: reports,
+    };
+    serde_json::to_string_pretty(&report).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_json(path: &str, reports: &[DeploymentReport]) -> io::Result<()> {
+    fs::write(path, to_json(reports)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("deploy-{}-{}", std::process::id(), name));
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn junit_has_a_failed_testcase_per_failed_phase() {
+        // the phases of a deployment whose upload action, named site, fails
+        let mut report = DeploymentReport::new("api/deploy.toml", "10.0.0.2");
+        report.record("prepare", Duration::from_millis(50), None);
+        report.record("connect", Duration::from_millis(250), None);
+        let error = "disk full: <quota> & \"limit\"".to_string();
+        report.fail("site", Duration::from_millis(1500), error);
+        let path = temp_path("junit.xml");
+        write_junit(&path, &[report]).unwrap();
+        let xml = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let failure = "disk full: &lt;quota&gt; &amp; &quot;limit&quot;";
+        let expected = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites>
+  <testsuite name="api/deploy.toml" hostname="10.0.0.2" tests="3" failures="1" time="1.800">
+    <testcase name="prepare" classname="api/deploy.toml" time="0.050"/>
+    <testcase name="connect" classname="api/deploy.toml" time="0.250"/>
+    <testcase name="site" classname="api/deploy.toml" time="1.500">
+      <failure message="{failure}">{failure}</failure>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+        assert_eq!(xml, expected);
+    }
+
+    #[test]
+    fn json_report_is_complete_whatever_the_outcome() {
+        let mut succeeded = DeploymentReport::new("deploy.toml", "web-1");
+        succeeded.record("prepare", Duration::from_millis(100), None);
+        succeeded.record("connect", Duration::from_millis(250), None);
+        let file = UploadedFile {
+            path: "index.html".to_string(),
+            bytes: 5,
+            sha256: "2cf24dba".to_string(),
+        };
+        succeeded.add_files("site", vec![file]);
+        let mut failed = DeploymentReport::new("deploy.toml", "web-2");
+        failed.fail_command(
+            "restart",
+            Duration::from_secs(2),
+            3,
+            "exit status 3".to_string(),
+        );
+        let mut skipped = DeploymentReport::new("deploy.toml", "web-3");
+        skipped.record_command("preflight", Duration::ZERO, Some(2), None);
+        skipped.status = Status::Skipped;
+        let mut aborted = DeploymentReport::new("deploy.toml", "web-4");
+        aborted.record("connect", Duration::from_millis(100), None);
+        aborted.abort(Duration::from_secs(1));
+        let mut reports = vec![succeeded, failed, skipped];
+        let json: serde_json::Value = serde_json::from_str(&to_json(&reports).unwrap()).unwrap();
+        assert_eq!(json["status"], "failed");
+        assert_eq!(exit_code(&reports), Some(3));
+        reports.push(aborted);
+        let path = temp_path("report.json");
+        write_json(&path, &reports).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(json["status"], "aborted");
+        assert_eq!(exit_code(&reports), Some(130));
+        let deployments = &json["deployments"];
+        assert_eq!(deployments[0]["status"], "success");
+        assert_eq!(deployments[0]["bytes"], 5);
+        assert_eq!(deployments[0]["files"]["site"][0]["path"], "index.html");
+        assert_eq!(deployments[0]["phases"][0]["duration"], 0.1);
+        assert_eq!(deployments[1]["status"], "failed");
+        assert_eq!(deployments[1]["error"], "exit status 3");
+        assert_eq!(deployments[1]["phases"][0]["exit_status"], 3);
+        assert_eq!(deployments[2]["status"], "skipped");
+        assert_eq!(deployments[2]["phases"][0]["name"], "preflight");
+        assert_eq!(deployments[3]["status"], "aborted");
+        assert_eq!(deployments[3]["error"], "deployment aborted");
+        assert_eq!(deployments[3]["phases"][0]["name"], "connect");
+        assert_eq!(deployments[3]["phases"][1]["name"], "abort");
+    }
+}
@@ -0,0 +1,57 @@
+use crate::theme::THEME;
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+const RESUME_PATH: &str = ".deployresume";
+
+// partial upload destination -> sha256 of each block known to be on the server
+pub type Journal = BTreeMap<String, Vec<String>>;
+
+// an unreadable journal only costs restarting the partial uploads
+fn load() -> Journal {
+    if !Path::new(RESUME_PATH).exists() {
+        return Journal::new();
+    }
+    let journal = fs::read_to_string(RESUME_PATH)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| toml::from_str(&contents).map_err(|e| e.to_string()));
+    journal.unwrap_or_else(|e| {
+        eprintln!(
+            "{} ignoring {}: {}",
+            THEME.warning_label("Warning:"),
+            RESUME_PATH,
+            e
+        );
+        Journal::new()
+    })
+}
+
+fn save(journal: &Journal) -> io::Result<()> {
+    if journal.is_empty() {
+        return match fs::remove_file(RESUME_PATH) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    let contents =
+        toml::to_string(journal).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(RESUME_PATH, contents)
+}
+
+pub fn blocks(key: &str) -> Vec<String> {
+    load().remove(key).unwrap_or_default()
+}
+
+// read back on every block, as uploads running concurrently share the file
+pub fn record(key: &str, blocks: &[String]) -> io::Result<()> {
+    let mut journal = load();
+    journal.insert(key.to_string(), blocks.to_vec());
+    save(&journal)
+}
+
+pub fn forget(key: &str) -> io::Result<()> {
+    let mut journal = load();
+    if journal.remove(key).is_none() {
+        return Ok(());
+    }
+    save(&journal)
+}
@@ -0,0 +1,70 @@
+use crate::config::LogRetention;
+use crate::log_files::{Kind, LogNaming};
+use crate::theme::THEME;
+use chrono::{DateTime, Local};
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    fs::{self, File},
+    io,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+// drops the oldest deployment and dry run logs, along with their json events
+pub fn apply(retention: &LogRetention, naming: &LogNaming) {
+    for kind in [Kind::Deployment, Kind::DryRun] {
+        if let Err(e) = apply_to_kind(retention, naming, kind) {
+            eprintln!(
+                "{} unable to prune the logs: {}",
+                THEME.warning_label("Warning:"),
+                e
+            );
+        }
+    }
+}
+
+fn apply_to_kind(retention: &LogRetention, naming: &LogNaming, kind: Kind) -> io::Result<()> {
+    let logs = naming.logs(kind);
+    let too_old = |path: &Path| -> bool {
+        let Some(max_age_days) = retention.max_age_days else {
+            return false;
+        };
+        let max_age = Duration::from_secs(max_age_days * 24 * 60 * 60);
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > max_age)
+    };
+    let over_count = retention
+        .keep
+        .map_or(0, |keep| logs.len().saturating_sub(keep));
+    let expired = logs.iter().take_while(|path| too_old(path)).count();
+    let dropped = over_count.max(expired);
+
+    for log in &logs[..dropped] {
+        for path in [log.to_owned(), log.with_extension("jsonl")] {
+            if !path.exists() {
+                continue;
+            }
+            if retention.compress {
+                compress(&naming.dir.join("archive"), &path)?;
+            }
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+// archived under the time the log was last written, as names like {{kind}}_{{n}} can come back
+fn compress(archive_dir: &Path, path: &Path) -> io::Result<()> {
+    fs::create_dir_all(archive_dir)?;
+    let modified = DateTime::<Local>::from(fs::metadata(path)?.modified()?);
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let archive_name = format!("{}_{}.gz", modified.format("%Y%m%d-%H%M%S"), file_name);
+    let archive = File::create(archive_dir.join(archive_name))?;
+    let mut encoder = GzEncoder::new(archive, Compression::default());
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
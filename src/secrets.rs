@@ -0,0 +1,142 @@
+use crate::actions::upload::expand_user_path;
+use crate::config::{is_variable_name, Secrets};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// decrypts the [secrets] file in memory, with age for a .age file and sops otherwise, and returns
+// its values by name; the plaintext is never written to disk
+pub fn decrypt(secrets: &Secrets) -> Result<BTreeMap<String, String>, String> {
+    let file = expand_user_path(&secrets.file);
+    let identity = secrets.identity.as_deref().map(expand_user_path);
+    let (mut command, format) = match file.strip_suffix(".age") {
+        Some(plaintext) => {
+            let Some(identity) = &identity else {
+                return Err("secrets: an age file needs an identity".to_string());
+            };
+            let mut command = Command::new("age");
+            command.args(["-d", "-i", identity.as_str(), file.as_str()]);
+            (command, extension(plaintext))
+        }
+        None => {
+            // sops reads yaml, json, dotenv and ini files, whichever it is the output is json
+            let mut command = Command::new("sops");
+            command.args(["-d", "--output-type", "json", file.as_str()]);
+            if let Some(identity) = &identity {
+                command.env("SOPS_AGE_KEY_FILE", identity);
+            }
+            (command, Some("json"))
+        }
+    };
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .output()
+        .map_err(|e| format!("unable to run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "unable to decrypt '{}': {}",
+            secrets.file,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let plaintext = String::from_utf8(output.stdout)
+        .map_err(|_| format!("'{}' isn't utf-8 once decrypted", secrets.file))?;
+    // the parse errors only give a position, never a part of the plaintext
+    let values: JsonValue = match format {
+        Some("json") => serde_json::from_str(&plaintext)
+            .map_err(|e| format!("unable to parse '{}': {}", secrets.file, json_position(&e)))?,
+        Some("yaml") | Some("yml") => serde_yaml::from_str(&plaintext)
+            .map_err(|e| format!("unable to parse '{}': {}", secrets.file, yaml_position(&e)))?,
+        _ => return parse_dotenv(&plaintext),
+    };
+    let JsonValue::Object(values) = values else {
+        return Err(format!("'{}' must hold a map of secrets", secrets.file));
+    };
+    let mut secrets = BTreeMap::new();
+    for (name, value) in values {
+        let value = match value {
+            JsonValue::String(value) => value,
+            JsonValue::Number(_) | JsonValue::Bool(_) => value.to_string(),
+            JsonValue::Null => String::new(),
+            _ => return Err(format!("secret '{}' must be a string", name)),
+        };
+        insert(&mut secrets, name, value)?;
+    }
+    Ok(secrets)
+}
+
+fn extension(path: &str) -> Option<&str> {
+    Path::new(path).extension().and_then(|ext| ext.to_str())
+}
+
+fn json_position(error: &serde_json::Error) -> String {
+    format!(
+        "invalid json at line {} column {}",
+        error.line(),
+        error.column()
+    )
+}
+
+fn yaml_position(error: &serde_yaml::Error) -> String {
+    match error.location() {
+        Some(location) => format!(
+            "invalid yaml at line {} column {}",
+            location.line(),
+            location.column()
+        ),
+        None => "invalid yaml".to_string(),
+    }
+}
+
+// KEY=VALUE lines, with optional `export` and quotes, like the files sourced by shells
+fn parse_dotenv(plaintext: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut secrets = BTreeMap::new();
+    for (index, line) in plaintext.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(format!("line {} of the secrets isn't KEY=VALUE", index + 1));
+        };
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+            .unwrap_or(value);
+        insert(&mut secrets, name.trim().to_string(), value.to_string())?;
+    }
+    Ok(secrets)
+}
+
+fn insert(
+    secrets: &mut BTreeMap<String, String>,
+    name: String,
+    value: String,
+) -> Result<(), String> {
+    if !is_variable_name(&name) {
+        return Err(format!("secret '{}' isn't a valid variable name", name));
+    }
+    secrets.insert(name, value);
+    Ok(())
+}
+
+// the value of an `op://<vault>/<item>/<field>` reference, read by the 1Password cli, which can
+// prompt on the terminal to unlock
+pub fn read_op_reference(reference: &str) -> Result<String, String> {
+    let output = Command::new("op")
+        .args(["read", "--no-newline", reference])
+        .stdin(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("unable to run op: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "unable to read '{}': {}",
+            reference,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|_| format!("'{}' isn't utf-8", reference))
+}
@@ -0,0 +1,191 @@
+use crate::actions::upload::expand_user_path;
+use crate::config::Server;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// includes nested deeper are left out, like a loop would be
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+// what ~/.ssh/config sets for a host, the first value of each keyword like for ssh
+#[derive(Default)]
+pub struct HostConfig {
+    pub hostname: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub identity_file: Option<String>,
+    pub proxy_jump: Option<String>,
+}
+
+// the entries of ~/.ssh/config whose Host patterns match alias, empty without the file
+pub fn lookup(alias: &str) -> HostConfig {
+    let mut lines = Vec::new();
+    read_lines(&expand_user_path("~/.ssh/config"), 0, &mut lines);
+    let mut host = HostConfig::default();
+    // the settings before the first Host apply to every host
+    let mut matching = true;
+    for line in lines {
+        let (keyword, value) = match line.split_once(|c: char| c.is_whitespace() || c == '=') {
+            Some((keyword, value)) => (keyword.to_lowercase(), value),
+            None => continue,
+        };
+        let value = value.trim_start_matches(|c: char| c.is_whitespace() || c == '=');
+        let value = value.trim().trim_matches('"');
+        match keyword.as_str() {
+            "host" => matching = host_matches(value, alias),
+            // only `Match all` is understood, other criteria never match
+            "match" => matching = value.eq_ignore_ascii_case("all"),
+            _ if !matching => {}
+            "hostname" => {
+                let hostname = value.replace("%h", alias);
+                host.hostname.get_or_insert(hostname);
+            }
+            "user" => {
+                host.user.get_or_insert(value.to_string());
+            }
+            "port" => {
+                if let (None, Ok(port)) = (host.port, value.parse()) {
+                    host.port = Some(port);
+                }
+            }
+            "identityfile" => {
+                host.identity_file.get_or_insert(value.to_string());
+            }
+            "proxyjump" => {
+                host.proxy_jump.get_or_insert(value.to_string());
+            }
+            _ => {}
+        }
+    }
+    // `ProxyJump none` turns off a jump a broader pattern would set
+    host.proxy_jump = host.proxy_jump.filter(|jump| jump != "none");
+    host
+}
+
+// the lines of path with its includes in their place, without comments and blank lines
+fn read_lines(path: &str, depth: usize, lines: &mut Vec<String>) {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return;
+    };
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let include = line
+            .split_once(char::is_whitespace)
+            .filter(|(keyword, _)| keyword.eq_ignore_ascii_case("include"));
+        match include {
+            Some((_, patterns)) if depth < MAX_INCLUDE_DEPTH => {
+                for pattern in patterns.split_whitespace() {
+                    for included in included_files(pattern) {
+                        read_lines(&included.to_string_lossy(), depth + 1, lines);
+                    }
+                }
+            }
+            Some(_) => {}
+            None => lines.push(line.to_string()),
+        }
+    }
+}
+
+// the files an Include pattern names, relative to ~/.ssh, with wildcards only in the file names
+fn included_files(pattern: &str) -> Vec<PathBuf> {
+    let pattern = expand_user_path(pattern);
+    let pattern = if Path::new(&pattern).is_absolute() {
+        PathBuf::from(pattern)
+    } else {
+        Path::new(&expand_user_path("~/.ssh")).join(pattern)
+    };
+    let file_name = pattern.file_name().map(|name| name.to_string_lossy());
+    let (Some(folder), Some(file_name)) = (pattern.parent(), file_name) else {
+        return Vec::new();
+    };
+    if !file_name.contains(['*', '?']) {
+        return vec![pattern.to_owned()];
+    }
+    let Ok(entries) = fs::read_dir(folder) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| wildcard_match(&file_name, &entry.file_name().to_string_lossy()))
+        .map(|entry| entry.path())
+        .collect();
+    files.sort();
+    files
+}
+
+// whether the space separated patterns of a Host line match alias, a `!pattern` excluding it
+fn host_matches(patterns: &str, alias: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns.split_whitespace() {
+        match pattern.strip_prefix('!') {
+            Some(negated) if wildcard_match(negated, alias) => return false,
+            Some(_) => {}
+            None => matched |= wildcard_match(pattern, alias),
+        }
+    }
+    matched
+}
+
+// `*` matches any characters and `?` a single one
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), text.chars().collect());
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || c.eq_ignore_ascii_case(&text[t]) => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// the servers of server.jump_host in the order they are connected to, resolved through
+// ~/.ssh/config and falling back on the user and key of server
+pub fn jump_servers(server: &Server) -> Vec<Server> {
+    server
+        .jump_host
+        .iter()
+        .map(|jump| {
+            let jump = jump.trim_start_matches("ssh://");
+            let (user, jump) = match jump.split_once('@') {
+                Some((user, jump)) => (Some(user), jump),
+                None => (None, jump),
+            };
+            let (alias, port) = match jump.rsplit_once(':') {
+                Some((alias, port)) => (alias, port.parse().ok()),
+                None => (jump, None),
+            };
+            let host = lookup(alias);
+            Server {
+                host: host.hostname.unwrap_or_else(|| alias.to_string()),
+                port: port.or(host.port).unwrap_or(22),
+                user: user
+                    .map(str::to_string)
+                    .or(host.user)
+                    .unwrap_or_else(|| server.user.to_owned()),
+                ssh_key: host
+                    .identity_file
+                    .unwrap_or_else(|| server.ssh_key.to_owned()),
+                jump_host: Vec::new(),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,152 @@
+use clap::ValueEnum;
+use colored::{Color, ColoredString, Colorize};
+use lazy_static::lazy_static;
+use std::{
+    env,
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+lazy_static! {
+    pub static ref THEME: Theme = Theme::from_env();
+}
+
+static ACCESSIBLE_SYMBOLS: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Symbols {
+    Default,
+    // textual markers that don't rely on color
+    Accessible,
+}
+
+pub fn set_symbols(symbols: Symbols) {
+    ACCESSIBLE_SYMBOLS.store(symbols == Symbols::Accessible, Ordering::Relaxed);
+}
+
+fn accessible() -> bool {
+    ACCESSIBLE_SYMBOLS.load(Ordering::Relaxed)
+}
+
+pub struct Theme {
+    pub success: Color,
+    pub error: Color,
+    pub warning: Color,
+    pub muted: Color,
+}
+
+impl Theme {
+    // defaults can be overridden with DEPLOY_COLOR_* variables (named colors or #rrggbb)
+    fn from_env() -> Theme {
+        Theme {
+            success: color_from_env("DEPLOY_COLOR_SUCCESS", Color::BrightGreen),
+            error: color_from_env("DEPLOY_COLOR_ERROR", Color::BrightRed),
+            warning: color_from_env("DEPLOY_COLOR_WARNING", Color::Yellow),
+            muted: color_from_env("DEPLOY_COLOR_MUTED", Color::BrightBlack),
+        }
+    }
+
+    pub fn check_mark(&self) -> &'static str {
+        if accessible() {
+            "[OK]"
+        } else {
+            "✔"
+        }
+    }
+
+    pub fn success_label(&self, label: &str) -> ColoredString {
+        marked("[OK]", label).color(self.success)
+    }
+
+    pub fn warning_label(&self, label: &str) -> ColoredString {
+        marked("[!!]", label).color(self.warning)
+    }
+
+    pub fn error_label(&self, label: &str) -> ColoredString {
+        marked("[xx]", label).color(self.error)
+    }
+}
+
+fn marked(marker: &str, label: &str) -> String {
+    if accessible() {
+        format!("{} {}", marker, label)
+    } else {
+        label.to_string()
+    }
+}
+
+fn color_from_env(var: &str, default: Color) -> Color {
+    match env::var(var) {
+        Ok(value) => parse_color(&value).unwrap_or_else(|| {
+            eprintln!(
+                "{} invalid color '{}' in {}, using default",
+                "Warning:".yellow(),
+                value,
+                var
+            );
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+fn parse_color(value: &str) -> Option<Color> {
+    let value = value.trim();
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        return Some(Color::TrueColor {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+        });
+    }
+    Color::from_str(&value.replace('_', " ")).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_colors_override_the_defaults() {
+        colored::control::set_override(true);
+        env::set_var("DEPLOY_COLOR_SUCCESS", "magenta");
+        env::set_var("DEPLOY_COLOR_ERROR", "not a color");
+        let theme = Theme::from_env();
+        env::remove_var("DEPLOY_COLOR_SUCCESS");
+        env::remove_var("DEPLOY_COLOR_ERROR");
+
+        let check_mark = theme.check_mark();
+        let rendered = check_mark.color(theme.success).to_string();
+        assert_eq!(rendered, format!("\x1b[35m{}\x1b[0m", check_mark));
+        assert_eq!(theme.error, Color::BrightRed);
+        let orange = Color::TrueColor {
+            r: 255,
+            g: 136,
+            b: 0,
+        };
+        assert_eq!(parse_color("#ff8800"), Some(orange));
+    }
+
+    #[test]
+    fn accessible_symbols_mark_the_labels() {
+        set_symbols(Symbols::Accessible);
+        let theme = Theme::from_env();
+        // the text of the labels, without their color
+        let labels = [
+            theme.check_mark().to_string(),
+            (*theme.success_label("Deployed:")).to_string(),
+            (*theme.warning_label("Warning:")).to_string(),
+            (*theme.error_label("Error:")).to_string(),
+        ];
+        set_symbols(Symbols::Default);
+
+        assert_eq!(
+            labels,
+            ["[OK]", "[OK] Deployed:", "[!!] Warning:", "[xx] Error:"]
+        );
+    }
+}
@@ -0,0 +1,504 @@
+use crate::actions::shell::check_account_name;
+use crate::actions::upload::expand_user_path;
+use crate::config::{self, Action, Config, LoadOptions};
+use ignore::gitignore::GitignoreBuilder;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{fmt, fs, path::Path};
+
+const TOP_LEVEL: [&str; 43] = [
+    "server",
+    "hosts",
+    "canary",
+    "connection",
+    "actions",
+    "relay",
+    "log_durability",
+    "log_dir",
+    "log_name",
+    "log_format",
+    "log_retention",
+    "console_height",
+    "upload_concurrency",
+    "upload_concurrency_min",
+    "upload_concurrency_max",
+    "transfer_mode",
+    "compression",
+    "compression_level",
+    "max_bandwidth",
+    "redact",
+    "preflight",
+    "depends_on",
+    "remote_shell",
+    "strategy",
+    "base_path",
+    "keep_releases",
+    "linked_files",
+    "linked_dirs",
+    "on_rollback",
+    "protected_paths",
+    "remote_log",
+    "pre_deploy_local",
+    "post_deploy_local",
+    "pre_deploy_remote",
+    "post_deploy_remote",
+    "watch",
+    "healthcheck",
+    "status",
+    "require_https",
+    "min_tls",
+    "secrets",
+    "vars",
+    "env",
+];
+const SERVER: [&str; 5] = ["host", "port", "user", "ssh_key", "jump_host"];
+const CONNECTION: [&str; 4] = ["port", "user", "identity_file", "jump_host"];
+const LOG_RETENTION: [&str; 3] = ["keep", "max_age_days", "compress"];
+const WATCH: [&str; 2] = ["reload", "debounce_ms"];
+const CANARY: [&str; 2] = ["first", "pause"];
+const HEALTHCHECK: [&str; 8] = [
+    "url",
+    "expect_status",
+    "expect_body",
+    "port",
+    "host",
+    "timeout",
+    "interval",
+    "rollback",
+];
+const STATUS: [&str; 4] = ["systemd_unit", "docker_container", "url", "expect_status"];
+const SECRETS: [&str; 2] = ["file", "identity"];
+// keys of every action type
+const ACTION: [&str; 6] = [
+    "type",
+    "name",
+    "depends_on",
+    "only_if",
+    "unless",
+    "only_if_env",
+];
+const COMMANDS: [&str; 7] = [
+    "commands",
+    "check",
+    "allow_failure",
+    "retries",
+    "retry_delay",
+    "timeout",
+    "env",
+];
+const UPLOAD: [&str; 11] = [
+    "source_folder",
+    "target_folder",
+    "skip_empty_files",
+    "skip_first_line",
+    "use_gitignore",
+    "remote_owner",
+    "remote_group",
+    "retries",
+    "retry_delay",
+    "backup",
+    "secret_files",
+];
+const WAIT_FOR_PORT: [&str; 3] = ["host", "port", "timeout"];
+
+lazy_static! {
+    static ref KEY: Regex = Regex::new(r#"^\s*"?([A-Za-z0-9_-]+)"?\s*="#).unwrap();
+}
+
+// something wrong with a config file, at the key it's about when it could be found
+pub struct Problem {
+    path: String,
+    // line and column, from 1
+    position: Option<(usize, usize)>,
+    message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some((line, column)) => {
+                write!(f, "{}:{}:{}: {}", self.path, line, column, self.message)
+            }
+            None => write!(f, "{}: {}", self.path, self.message),
+        }
+    }
+}
+
+// one step of the way to a value: a table key or an index in an array of tables
+#[derive(Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+fn key(name: &str) -> Segment {
+    Segment::Key(name.to_string())
+}
+
+// the problems of a config file and its depends_on configs, checked without deploying
+pub fn validate(config_path: &str, options: LoadOptions) -> Vec<Problem> {
+    match config::load_with_dependencies(config_path, options) {
+        Ok(deployments) => deployments
+            .iter()
+            .flat_map(|(path, config)| check_file(path, Some(config)))
+            .collect(),
+        Err(e) => {
+            // the loader stops at the first error, the file checks usually say more about it
+            let mut problems = check_file(config_path, None);
+            if problems.is_empty() {
+                problems.push(Problem {
+                    path: config_path.to_string(),
+                    position: None,
+                    message: e,
+                });
+            }
+            problems
+        }
+    }
+}
+
+// the keys of the file, and with its loaded config the paths and patterns it names
+fn check_file(path: &str, config: Option<&Config>) -> Vec<Problem> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            return vec![Problem {
+                path: path.to_string(),
+                position: None,
+                message: format!("unable to read file: {}", e),
+            }]
+        }
+    };
+    let table: toml::Table = match toml::from_str(&contents) {
+        Ok(table) => table,
+        Err(e) => {
+            let position = e.span().map(|span| position(&contents, span.start));
+            return vec![Problem {
+                path: path.to_string(),
+                position,
+                message: e.message().to_string(),
+            }];
+        }
+    };
+    let mut checker = Checker {
+        path,
+        contents: &contents,
+        problems: Vec::new(),
+    };
+    checker.check_top_level(&table, &[], true);
+    if let Some(config) = config {
+        checker.check_config(config);
+    }
+    checker.problems
+}
+
+// line and column of a byte offset
+fn position(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |start| start + 1) + 1;
+    (line, column)
+}
+
+struct Checker<'a> {
+    path: &'a str,
+    contents: &'a str,
+    problems: Vec<Problem>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, at: &[Segment], message: String) {
+        self.problems.push(Problem {
+            path: self.path.to_string(),
+            position: self.locate(at),
+            message,
+        });
+    }
+
+    // finds the line of a key by following the table headers, or that of the closest parent
+    // found, as keys of inline tables aren't looked for
+    fn locate(&self, at: &[Segment]) -> Option<(usize, usize)> {
+        let mut header: Vec<Segment> = Vec::new();
+        let mut arrays: Vec<(Vec<Segment>, usize)> = Vec::new();
+        let mut found: Option<(usize, (usize, usize))> = None;
+        for (number, line) in self.contents.lines().enumerate() {
+            let trimmed = line.trim();
+            let column = line.len() - line.trim_start().len() + 1;
+            let path = if let Some(name) = trimmed
+                .strip_prefix("[[")
+                .and_then(|rest| rest.split("]]").next())
+            {
+                let mut path = dotted(name);
+                let count = match arrays.iter_mut().find(|(array, _)| *array == path) {
+                    Some((_, count)) => {
+                        *count += 1;
+                        *count
+                    }
+                    None => {
+                        arrays.push((path.clone(), 0));
+                        0
+                    }
+                };
+                path.push(Segment::Index(count));
+                header = path.clone();
+                path
+            } else if let Some(name) = trimmed
+                .strip_prefix('[')
+                .and_then(|rest| rest.split(']').next())
+            {
+                header = dotted(name);
+                header.clone()
+            } else if let Some(captures) = KEY.captures(line) {
+                let mut path = header.clone();
+                path.push(key(&captures[1]));
+                path
+            } else {
+                continue;
+            };
+            // the deepest match wins, the header of a table before its keys
+            let deeper = !matches!(found, Some((depth, _)) if depth >= path.len());
+            if at.starts_with(&path) && deeper {
+                found = Some((path.len(), (number + 1, column)));
+            }
+        }
+        found.map(|(_, position)| position)
+    }
+
+    // the top level of the file, or an [env.<name>] table overriding some of it
+    fn check_top_level(&mut self, table: &toml::Table, at: &[Segment], root: bool) {
+        self.check_keys(table, at, &TOP_LEVEL, "setting");
+        let child = |name: &str| [at, &[key(name)]].concat();
+        if root && !table.contains_key("server") && !table.contains_key("hosts") {
+            self.report(&[], "missing [server] or [[hosts]]".to_string());
+        }
+        if root && !table.contains_key("actions") {
+            self.report(&[], "missing [[actions]]".to_string());
+        }
+        if let Some(server) = table.get("server") {
+            self.check_server(server, &child("server"), root, &[]);
+        }
+        if let Some(hosts) = table.get("hosts") {
+            for (index, host) in hosts.as_array().into_iter().flatten().enumerate() {
+                let at = [child("hosts"), vec![Segment::Index(index)]].concat();
+                self.check_server(host, &at, root, &[]);
+            }
+        }
+        if let Some(relay) = table.get("relay") {
+            self.check_server(relay, &child("relay"), root, &["fan_out"]);
+        }
+        if let Some(toml::Value::Table(log_retention)) = table.get("log_retention") {
+            self.check_keys(
+                log_retention,
+                &child("log_retention"),
+                &LOG_RETENTION,
+                "key",
+            );
+        }
+        if let Some(toml::Value::Table(canary)) = table.get("canary") {
+            self.check_keys(canary, &child("canary"), &CANARY, "key");
+        }
+        if let Some(toml::Value::Table(watch)) = table.get("watch") {
+            self.check_keys(watch, &child("watch"), &WATCH, "key");
+        }
+        if let Some(toml::Value::Table(healthcheck)) = table.get("healthcheck") {
+            self.check_keys(healthcheck, &child("healthcheck"), &HEALTHCHECK, "key");
+        }
+        if let Some(toml::Value::Table(status)) = table.get("status") {
+            self.check_keys(status, &child("status"), &STATUS, "key");
+        }
+        if let Some(toml::Value::Table(connection)) = table.get("connection") {
+            self.check_keys(connection, &child("connection"), &CONNECTION, "key");
+        }
+        if let Some(toml::Value::Table(secrets)) = table.get("secrets") {
+            self.check_keys(secrets, &child("secrets"), &SECRETS, "key");
+        }
+        if let Some(actions) = table.get("actions") {
+            for (index, action) in tables(actions) {
+                let at = [child("actions"), vec![Segment::Index(index)]].concat();
+                self.check_action(action, &at);
+            }
+        }
+        if let (true, Some(toml::Value::Table(envs))) = (root, table.get("env")) {
+            for (name, env) in envs {
+                if let toml::Value::Table(env) = env {
+                    self.check_top_level(env, &[key("env"), key(name)], false);
+                }
+            }
+        }
+    }
+
+    fn check_server(&mut self, server: &toml::Value, at: &[Segment], root: bool, extra: &[&str]) {
+        let toml::Value::Table(server) = server else {
+            return;
+        };
+        let known: Vec<&str> = SERVER.iter().chain(extra).copied().collect();
+        self.check_keys(server, at, &known, "key");
+        // an [env.<name>] table may only override some of them, and [connection] or ~/.ssh/config
+        // can give all but the host
+        if root {
+            let required: Vec<&str> = ["host"].iter().chain(extra).copied().collect();
+            self.check_required(server, at, &required);
+        }
+    }
+
+    fn check_action(&mut self, action: &toml::Table, at: &[Segment]) {
+        let (keys, required): (&[&str], &[&str]) =
+            match action.get("type").and_then(|kind| kind.as_str()) {
+                Some("commands") => (&COMMANDS, &["commands"]),
+                Some("upload") => (&UPLOAD, &["source_folder", "target_folder"]),
+                Some("wait_for_port") => (&WAIT_FOR_PORT, &["port"]),
+                Some(kind) => {
+                    let at = [at, &[key("type")]].concat();
+                    let message = format!(
+                        "unknown action type '{}', expected commands, upload or wait_for_port",
+                        kind
+                    );
+                    self.report(&at, message);
+                    return;
+                }
+                None => {
+                    self.report(at, "action without a type".to_string());
+                    return;
+                }
+            };
+        let known: Vec<&str> = ACTION.iter().chain(keys).copied().collect();
+        self.check_keys(action, at, &known, "key");
+        let required: Vec<&str> = ["name"].iter().chain(required).copied().collect();
+        self.check_required(action, at, &required);
+    }
+
+    fn check_keys(&mut self, table: &toml::Table, at: &[Segment], known: &[&str], kind: &str) {
+        for name in table.keys() {
+            if known.contains(&name.as_str()) {
+                continue;
+            }
+            let message = match closest(name, known) {
+                Some(closest) => {
+                    format!("unknown {} '{}', did you mean '{}'?", kind, name, closest)
+                }
+                None => format!("unknown {} '{}'", kind, name),
+            };
+            self.report(&[at, &[key(name)]].concat(), message);
+        }
+    }
+
+    fn check_required(&mut self, table: &toml::Table, at: &[Segment], required: &[&str]) {
+        for name in required {
+            if !table.contains_key(*name) {
+                self.report(at, format!("missing '{}'", name));
+            }
+        }
+    }
+
+    // checks on the values once variables are substituted and the environment applied
+    fn check_config(&mut self, config: &Config) {
+        let servers = config.hosts.iter().enumerate().map(|(index, host)| {
+            let at = vec![key("hosts"), Segment::Index(index), key("ssh_key")];
+            (host, at)
+        });
+        let servers = servers
+            .chain([(&config.server, vec![key("server"), key("ssh_key")])])
+            .chain(
+                config
+                    .relay
+                    .iter()
+                    .map(|relay| (&relay.server, vec![key("relay"), key("ssh_key")])),
+            );
+        let mut missing_keys = Vec::new();
+        for (server, at) in servers {
+            let ssh_key = expand_user_path(&server.ssh_key);
+            // [server] stands for the first of [[hosts]], which is reported there
+            if !Path::new(&ssh_key).is_file() && !missing_keys.contains(&ssh_key) {
+                missing_keys.push(ssh_key.clone());
+                self.report(&at, format!("ssh_key '{}' is not a file", ssh_key));
+            }
+        }
+        for (index, pattern) in config.redact.iter().enumerate() {
+            if let Err(e) = Regex::new(pattern) {
+                let message = format!("redact pattern {} is not a valid regex: {}", index + 1, e);
+                self.report(&[key("redact")], message);
+            }
+        }
+        for (index, action) in config.actions.iter().enumerate() {
+            let Action::Upload {
+                source_folder,
+                skip_first_line,
+                remote_owner,
+                remote_group,
+                ..
+            } = action
+            else {
+                continue;
+            };
+            let at = |name: &str| vec![key("actions"), Segment::Index(index), key(name)];
+            let folder = expand_user_path(source_folder);
+            if !Path::new(&folder).is_dir() {
+                let message = format!("source_folder '{}' is not a folder", folder);
+                self.report(&at("source_folder"), message);
+            }
+            let ignore_path = Path::new(&folder).join(".deployignore");
+            if ignore_path.exists() {
+                let mut builder = GitignoreBuilder::new(&folder);
+                let error = builder.add(&ignore_path).or_else(|| builder.build().err());
+                if let Some(e) = error {
+                    let message = format!("invalid pattern in '{}': {}", ignore_path.display(), e);
+                    self.report(&at("source_folder"), message);
+                }
+            }
+            if let Some(Err(e)) = skip_first_line.as_deref().map(Regex::new) {
+                let message = format!("skip_first_line is not a valid regex: {}", e);
+                self.report(&at("skip_first_line"), message);
+            }
+            for (name, account) in [
+                ("remote_owner", remote_owner),
+                ("remote_group", remote_group),
+            ] {
+                if let Some(Err(e)) = account.as_deref().map(check_account_name) {
+                    self.report(&at(name), e);
+                }
+            }
+        }
+    }
+}
+
+// `a.b."c"` -> [a, b, c]
+fn dotted(name: &str) -> Vec<Segment> {
+    name.split('.')
+        .map(|part| key(part.trim().trim_matches('"')))
+        .collect()
+}
+
+// the tables of an array of tables, with their index
+fn tables(value: &toml::Value) -> impl Iterator<Item = (usize, &toml::Table)> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .filter_map(|(index, value)| value.as_table().map(|table| (index, table)))
+}
+
+// the known name a typo most likely meant, at most two edits away
+fn closest<'a>(name: &str, known: &[&'a str]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|candidate| (distance(name, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+// levenshtein distance
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
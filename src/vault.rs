@@ -0,0 +1,121 @@
+use crate::actions::health::send;
+use crate::actions::upload::expand_user_path;
+use crate::config::{check_https, parse_url, HttpUrl, TlsVersion};
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashMap;
+use std::{env, fs};
+
+// reads the secrets of `${vault:<path>#<key>}` values from the server at VAULT_ADDR, logged in
+// with VAULT_TOKEN, the AppRole of VAULT_ROLE_ID and VAULT_SECRET_ID, or else the token the vault
+// cli saved in ~/.vault-token
+#[derive(Default)]
+pub struct Vault {
+    session: Option<(HttpUrl, String)>,
+    // read once per path, however many keys are used
+    secrets: HashMap<String, JsonValue>,
+    // everything read, to be masked in the log
+    pub values: Vec<String>,
+    // require_https and min_tls of the config being loaded
+    require_https: bool,
+    min_tls: TlsVersion,
+}
+
+impl Vault {
+    pub fn new(require_https: bool, min_tls: TlsVersion) -> Vault {
+        Vault {
+            require_https,
+            min_tls,
+            ..Default::default()
+        }
+    }
+
+    pub fn read(&mut self, path: &str, key: &str) -> Result<String, String> {
+        if !self.secrets.contains_key(path) {
+            if self.session.is_none() {
+                self.session = Some(login(self.require_https, self.min_tls)?);
+            }
+            let (address, token) = self.session.as_ref().unwrap();
+            let token = Some(token.as_str());
+            let secret = request(address, self.min_tls, "GET", path, token, None)?;
+            self.secrets.insert(path.to_string(), secret);
+        }
+        let data = &self.secrets[path]["data"];
+        // the kv version 2 engine nests the values in a second data, next to their metadata
+        let values = match &data["data"] {
+            JsonValue::Object(_) => &data["data"],
+            _ => data,
+        };
+        let value = match &values[key] {
+            JsonValue::String(value) => value.to_owned(),
+            JsonValue::Null => return Err(format!("vault secret '{}' has no '{}'", path, key)),
+            value => value.to_string(),
+        };
+        if !value.is_empty() {
+            self.values.push(value.clone());
+        }
+        Ok(value)
+    }
+}
+
+fn login(require_https: bool, min_tls: TlsVersion) -> Result<(HttpUrl, String), String> {
+    let address = env::var("VAULT_ADDR")
+        .map_err(|_| "VAULT_ADDR must be set to read ${vault:...} values".to_string())?;
+    let address = parse_url(&address).map_err(|e| format!("invalid VAULT_ADDR: {}", e))?;
+    check_https(&address, require_https, "VAULT_ADDR")?;
+    if let Ok(token) = env::var("VAULT_TOKEN") {
+        return Ok((address, token));
+    }
+    if let (Ok(role_id), Ok(secret_id)) = (env::var("VAULT_ROLE_ID"), env::var("VAULT_SECRET_ID")) {
+        let body = json!({ "role_id": role_id, "secret_id": secret_id }).to_string();
+        let path = "auth/approle/login";
+        let response = request(&address, min_tls, "POST", path, None, Some(&body))?;
+        return match response["auth"]["client_token"].as_str() {
+            Some(token) => Ok((address, token.to_string())),
+            None => Err("the vault AppRole login returned no token".to_string()),
+        };
+    }
+    match fs::read_to_string(expand_user_path("~/.vault-token")) {
+        Ok(token) => Ok((address, token.trim().to_string())),
+        Err(_) => Err(
+            "set VAULT_TOKEN, or VAULT_ROLE_ID and VAULT_SECRET_ID, to read from vault".to_string(),
+        ),
+    }
+}
+
+// a call of the http api, whose errors are reported with the messages vault gives
+fn request(
+    address: &HttpUrl,
+    min_tls: TlsVersion,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+    body: Option<&str>,
+) -> Result<JsonValue, String> {
+    let path = format!("{}/v1/{}", address.path.trim_end_matches('/'), path);
+    // http/1.0 keeps the body from being chunked
+    let mut request = format!(
+        "{} {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: deploy\r\n",
+        method, path, address.host
+    );
+    if let Some(token) = token {
+        request.push_str(&format!("X-Vault-Token: {}\r\n", token));
+    }
+    if let Ok(namespace) = env::var("VAULT_NAMESPACE") {
+        request.push_str(&format!("X-Vault-Namespace: {}\r\n", namespace));
+    }
+    let body = body.unwrap_or_default();
+    request.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    let (status, response) =
+        send(address, &request, min_tls).map_err(|e| format!("unable to reach vault: {}", e))?;
+    let response: JsonValue = serde_json::from_str(&response).unwrap_or_default();
+    if status != 200 {
+        let errors = response["errors"].as_array().into_iter().flatten();
+        let errors: Vec<_> = errors.filter_map(|error| error.as_str()).collect();
+        let mut message = format!("vault answered {} to {} {}", status, method, path);
+        if !errors.is_empty() {
+            message.push_str(&format!(": {}", errors.join(", ")));
+        }
+        return Err(message);
+    }
+    Ok(response)
+}
@@ -0,0 +1,92 @@
+use crate::actions::classify::{classify_files, ContentFilter, Reason};
+use crate::actions::upload::expand_user_path;
+use crate::config::{Action, Config};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+// how often the files are listed again when nothing changed
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// every file the uploads would send, with its size and last modification
+pub type Snapshot = BTreeMap<PathBuf, (u64, Option<SystemTime>)>;
+
+// honors .deployignore and the content filters, like the upload itself
+pub fn snapshot(config: &Config, skip: &HashSet<String>) -> Snapshot {
+    let mut snapshot = Snapshot::new();
+    for action in &config.actions {
+        let Action::Upload {
+            name,
+            source_folder,
+            skip_empty_files,
+            skip_first_line,
+            use_gitignore,
+            ..
+        } = action
+        else {
+            continue;
+        };
+        if skip.contains(name) {
+            continue;
+        }
+        // an invalid filter fails the deployment, which reports it
+        let Ok(filter) = ContentFilter::new(*skip_empty_files, skip_first_line.as_deref()) else {
+            continue;
+        };
+        let source_folder = expand_user_path(source_folder);
+        for (path, reason) in classify_files(&source_folder, &filter, *use_gitignore, false) {
+            if !matches!(reason, Reason::Upload) {
+                continue;
+            }
+            let metadata = fs::metadata(&path).ok();
+            let size = metadata.as_ref().map_or(0, |metadata| metadata.len());
+            let modified = metadata.and_then(|metadata| metadata.modified().ok());
+            snapshot.insert(path, (size, modified));
+        }
+    }
+    snapshot
+}
+
+// the files added, modified or removed since the snapshot
+fn changes(before: &Snapshot, after: &Snapshot) -> Vec<PathBuf> {
+    let mut changed: Vec<PathBuf> = after
+        .iter()
+        .filter(|(path, state)| before.get(*path) != Some(state))
+        .map(|(path, _)| path.clone())
+        .chain(
+            before
+                .keys()
+                .filter(|path| !after.contains_key(*path))
+                .cloned(),
+        )
+        .collect();
+    changed.sort();
+    changed
+}
+
+// waits for the files to differ from the snapshot, then for the debounce to pass without
+// another change, as a save or a build often writes several files
+pub async fn wait_for_changes(
+    config: &Config,
+    skip: &HashSet<String>,
+    before: &Snapshot,
+    debounce: Duration,
+) -> Vec<PathBuf> {
+    let mut current = loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let current = snapshot(config, skip);
+        if current != *before {
+            break current;
+        }
+    };
+    loop {
+        tokio::time::sleep(debounce).await;
+        let settled = snapshot(config, skip);
+        if settled == current {
+            break;
+        }
+        current = settled;
+    }
+    changes(before, &current)
+}
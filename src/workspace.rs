@@ -0,0 +1,120 @@
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+pub const WORKSPACE_FILE: &str = "deploy.workspace.toml";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorkspaceFile {
+    members: Vec<MemberEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MemberEntry {
+    // defaults to the config file name without .deploy.toml
+    name: Option<String>,
+    // relative to the workspace file
+    path: String,
+    // members whose deployment must succeed before this one starts
+    #[serde(default)]
+    depends_on: Vec<String>,
+}
+
+pub struct Member {
+    pub name: String,
+    pub path: String,
+    // indexes of earlier members
+    pub depends_on: Vec<usize>,
+}
+
+// deploy.workspace.toml, or any other name ending with .workspace.toml
+pub fn is_workspace(path: &str) -> bool {
+    Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .is_some_and(|name| name == WORKSPACE_FILE || name.ends_with(".workspace.toml"))
+}
+
+// the members in an order where each comes after its dependencies, in file order otherwise
+pub fn load(workspace_path: &str) -> Result<Vec<Member>, String> {
+    let contents = fs::read_to_string(workspace_path)
+        .map_err(|e| format!("unable to read '{}': {}", workspace_path, e))?;
+    let workspace: WorkspaceFile =
+        toml::from_str(&contents).map_err(|e| format!("unable to deserialize workspace: {}", e))?;
+    let folder = Path::new(workspace_path).parent().unwrap_or(Path::new(""));
+
+    let names: Vec<String> = workspace
+        .members
+        .iter()
+        .map(|member| match &member.name {
+            Some(name) => name.to_owned(),
+            None => {
+                let file_name = Path::new(&member.path).file_name().unwrap_or_default();
+                let file_name = file_name.to_string_lossy();
+                file_name.trim_end_matches(".deploy.toml").to_string()
+            }
+        })
+        .collect();
+    for (index, name) in names.iter().enumerate() {
+        if names[..index].contains(name) {
+            return Err(format!("two members are named '{}'", name));
+        }
+    }
+    let mut dependencies = Vec::new();
+    for (member, name) in workspace.members.iter().zip(&names) {
+        let mut indexes = Vec::new();
+        for dependency in &member.depends_on {
+            match names.iter().position(|other| other == dependency) {
+                Some(index) => indexes.push(index),
+                None => {
+                    return Err(format!(
+                        "member '{}' depends on '{}', which isn't a member",
+                        name, dependency
+                    ))
+                }
+            }
+        }
+        dependencies.push(indexes);
+    }
+
+    let mut order: Vec<usize> = Vec::new();
+    while order.len() < names.len() {
+        let next = (0..names.len()).find(|index| {
+            !order.contains(index)
+                && dependencies[*index]
+                    .iter()
+                    .all(|dependency| order.contains(dependency))
+        });
+        match next {
+            Some(index) => order.push(index),
+            None => {
+                let cycle: Vec<&str> = (0..names.len())
+                    .filter(|index| !order.contains(index))
+                    .map(|index| names[index].as_str())
+                    .collect();
+                return Err(format!(
+                    "members depend on each other: {}",
+                    cycle.join(", ")
+                ));
+            }
+        }
+    }
+
+    let members = order
+        .iter()
+        .map(|&index| Member {
+            name: names[index].to_owned(),
+            path: folder
+                .join(&workspace.members[index].path)
+                .to_string_lossy()
+                .to_string(),
+            depends_on: dependencies[index]
+                .iter()
+                .map(|dependency| order.iter().position(|other| other == dependency).unwrap())
+                .collect(),
+        })
+        .collect();
+    Ok(members)
+}